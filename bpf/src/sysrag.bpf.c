@@ -1,49 +1,590 @@
-#include <linux/types.h>
-#include <linux/bpf.h>
+// vmlinux.h gives us the kernel's own types (task_struct etc.) for CO-RE relocations
+#include "vmlinux.h"
 #include <bpf/bpf_helpers.h>
+#include <bpf/bpf_core_read.h>
 
-// Define standard Rust/C types so the compiler stops complaining
-typedef unsigned int u32;
-typedef unsigned long long u64;
+// Every record in the `events` ring buffer starts with one of these tags.
+// These MUST match the `EVENT_KIND_*` constants in `bpf.rs`.
+#define EVENT_KIND_EXEC 1
+#define EVENT_KIND_CONNECT 2
+#define EVENT_KIND_OPEN 3
+#define EVENT_KIND_FORK 4
+#define EVENT_KIND_EXIT 5
 
-// The structure of the data we will send to Rust
+// Address families we decode in `trace_connect` (not part of vmlinux.h)
+#define AF_INET 2
+#define AF_INET6 10
+
+// Paths longer than this are matched (and reported) by their first MAX_PATH_LEN bytes
+#define MAX_PATH_LEN 256
+#define MAX_SUFFIX_LEN 32
+
+// Limits for the variable-size part of the exec record.
+// These MUST match the constants in `bpf.rs`.
+#define TTY_NAME_LEN 64
+#define MAX_FILENAME_LEN 256
+#define MAX_ARG_LEN 128
+#define MAX_ARGS 20
+#define ARGS_BUF_SIZE (MAX_ARG_LEN * MAX_ARGS)
+// The last offset at which a full MAX_ARG_LEN read still fits into `args`
+#define LAST_ARG_OFFSET (ARGS_BUF_SIZE - MAX_ARG_LEN)
+
+// The structure of the data we will send to Rust.
+// Only the first `offsetof(args) + args_size` bytes are written to the ring buffer.
 struct process_event {
+    u32 kind;             // EVENT_KIND_EXEC
     u32 pid;
     u32 uid;
+    u32 ppid;             // tgid of the real parent
+    u32 gid;
+    u32 loginuid;         // Audit login uid, (u32)-1 if never set (daemons)
+    u32 mnt_ns;           // Mount namespace inode number
+    u32 pid_ns;           // PID namespace inode number
+    u64 cgroup_id;        // cgroup v2 id
     char comm[16];
+    char tty[TTY_NAME_LEN]; // Controlling terminal, empty if none
+    char filename[MAX_FILENAME_LEN];
+    u32 argc;             // Number of arguments stored in `args`
+    u32 args_size;        // Bytes of `args` actually used
+    u8 args_truncated;    // 1 if argv had more than we could copy
+    u8 _pad[7];
+    char args[ARGS_BUF_SIZE]; // argv[0], argv[1], ... each NUL-terminated
 };
 
+// Layout of the `sys_enter_execve` tracepoint arguments
+// (see /sys/kernel/tracing/events/syscalls/sys_enter_execve/format)
+struct execve_args {
+    u64 common;           // Common tracepoint header
+    long syscall_nr;
+    const char *filename;
+    const char *const *argv;
+    const char *const *envp;
+};
+
+// An outbound `connect` to an IPv4 or IPv6 address
+struct connect_event {
+    u32 kind;             // EVENT_KIND_CONNECT
+    u32 pid;
+    u32 uid;
+    u16 family;           // AF_INET or AF_INET6
+    u16 port;             // Destination port, network byte order
+    u8 addr[16];          // IPv4 uses the first 4 bytes
+    char comm[16];
+};
+
+// Layout of the `sys_enter_connect` tracepoint arguments
+// (see /sys/kernel/tracing/events/syscalls/sys_enter_connect/format)
+struct connect_args {
+    u64 common;           // Common tracepoint header
+    long syscall_nr;
+    long fd;
+    struct sockaddr *uservaddr;
+    long addrlen;
+};
+
+// An open of a path under one of the watched prefixes
+struct open_event {
+    u32 kind;             // EVENT_KIND_OPEN
+    u32 pid;
+    u32 uid;
+    u32 flags;            // O_* flags passed to open
+    char comm[16];
+    char path[MAX_PATH_LEN];
+};
+
+// A new process (not a thread) was forked
+struct fork_event {
+    u32 kind;             // EVENT_KIND_FORK
+    u32 parent_pid;
+    u32 child_pid;
+    u32 uid;
+    char comm[16];        // The child starts with the parent's comm
+};
+
+// A process (its thread group leader) exited
+struct exit_event {
+    u32 kind;             // EVENT_KIND_EXIT
+    u32 pid;
+    u32 exit_code;        // Raw wait status: code << 8 | signal
+    u32 _pad;
+    char comm[16];
+};
+
+// Layout of the `sys_enter_openat` tracepoint arguments
+struct openat_args {
+    u64 common;
+    long syscall_nr;
+    long dfd;
+    const char *filename;
+    long flags;
+    long mode;
+};
+
+// Layout of the `sys_enter_openat2` tracepoint arguments
+struct openat2_args {
+    u64 common;
+    long syscall_nr;
+    long dfd;
+    const char *filename;
+    struct open_how *how;
+    long usize;
+};
+
+// Key of the `watched_prefixes` LPM trie: prefixlen is in bits, data is the path
+struct path_key {
+    u32 prefixlen;
+    char path[MAX_PATH_LEN];
+};
+
+// Optional extra condition for a prefix, e.g. "/proc/" + "/mem" for "/proc/*/mem".
+// This MUST match `PrefixRule` in `bpf.rs`.
+struct prefix_rule {
+    u32 suffix_len;       // 0 means any path under the prefix matches
+    char suffix[MAX_SUFFIX_LEN];
+};
+
+// Sensitive path prefixes, filled by the daemon from `files.watched_paths` before attaching
+struct {
+    __uint(type, BPF_MAP_TYPE_LPM_TRIE);
+    __uint(max_entries, 256);
+    __uint(map_flags, BPF_F_NO_PREALLOC);
+    __type(key, struct path_key);
+    __type(value, struct prefix_rule);
+} watched_prefixes SEC(".maps");
+
+// The path key is too big to build next to the rest of our stack, so it lives here
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, u32);
+    __type(value, struct path_key);
+} path_scratch SEC(".maps");
+
 // Create the Ring Buffer to communicate with User Space (Rust)
 struct {
     __uint(type, BPF_MAP_TYPE_RINGBUF);
     __uint(max_entries, 256 * 1024); // 256 KB buffer
 } events SEC(".maps");
 
-// We use `void *ctx` instead of a complex kernel struct because 
-// we don't actually need to read the syscall arguments!
-SEC("tracepoint/syscalls/sys_enter_execve")
-int trace_execve(void *ctx) {
-    struct process_event *event;
+// The full record is too big for the 512 byte BPF stack, so we build it in per-CPU scratch space
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, u32);
+    __type(value, struct process_event);
+} exec_scratch SEC(".maps");
+
+// Exec filters, managed by the daemon from the `[filters]` config section and `sysrag filter`.
+// A matching exec is dropped here, before any of it is copied or sent to user space.
+#define FILTER_MAX_ENTRIES 1024
+
+// Indices into `filter_drops`. These MUST match the `FILTER_DROP_*` constants in `filters.rs`.
+#define FILTER_DROP_COMM 0
+#define FILTER_DROP_UID 1
+#define FILTER_DROP_CGROUP 2
+#define FILTER_DROP_SLOTS 3
+
+// Programs we trust, keyed by the NUL-padded name they run under (the comm after the exec)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, char[16]);
+    __type(value, u8);
+} allowed_comms SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, u32);
+    __type(value, u8);
+} ignored_uids SEC(".maps");
+
+// Keyed by cgroup v2 id (the inode number of the cgroup directory)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, u64);
+    __type(value, u8);
+} ignored_cgroups SEC(".maps");
+
+// How many execs each filter dropped, summed over all CPUs by the daemon
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, FILTER_DROP_SLOTS);
+    __type(key, u32);
+    __type(value, u64);
+} filter_drops SEC(".maps");
+
+static __always_inline void count_drop(u32 slot) {
+    u64 *drops = bpf_map_lookup_elem(&filter_drops, &slot);
+    if (drops) {
+        *drops += 1;
+    }
+}
+
+// Returns 1 if the current exec matches one of the filters and must not be reported.
+// `filename` is the path handed to execve; the comm filter matches the program it names rather
+// than the caller, so trusting `make` doesn't hide everything make runs.
+static __always_inline int exec_filtered(const char filename[MAX_FILENAME_LEN]) {
+    // The kernel names the new image after the last path component, cut to 15 bytes
+    u32 start = 0;
+    #pragma unroll
+    for (u32 i = 0; i < MAX_FILENAME_LEN; i++) {
+        if (filename[i] == '\0') {
+            break;
+        }
+        if (filename[i] == '/') {
+            start = i + 1;
+        }
+    }
+
+    char comm[16] = {};
+    #pragma unroll
+    for (u32 i = 0; i < sizeof(comm) - 1; i++) {
+        char c = filename[(start + i) & (MAX_FILENAME_LEN - 1)];
+        if (start + i >= MAX_FILENAME_LEN || c == '\0') {
+            break;
+        }
+        comm[i] = c;
+    }
+    if (bpf_map_lookup_elem(&allowed_comms, &comm)) {
+        count_drop(FILTER_DROP_COMM);
+        return 1;
+    }
+
+    u32 uid = bpf_get_current_uid_gid();
+    if (bpf_map_lookup_elem(&ignored_uids, &uid)) {
+        count_drop(FILTER_DROP_UID);
+        return 1;
+    }
+
+    u64 cgroup_id = bpf_get_current_cgroup_id();
+    if (bpf_map_lookup_elem(&ignored_cgroups, &cgroup_id)) {
+        count_drop(FILTER_DROP_CGROUP);
+        return 1;
+    }
+
+    return 0;
+}
+
+// One counter slot per program in `events_sent` and `events_lost`.
+// These MUST match the order of `SENSOR_PROGRAMS` in `counters.rs`.
+#define PROG_EXECVE 0
+#define PROG_CONNECT 1
+#define PROG_OPENAT 2
+#define PROG_OPENAT2 3
+#define PROG_FORK 4
+#define PROG_EXIT 5
+#define PROG_SLOTS 6
+
+// Records each program wrote to `events`, summed over all CPUs by the daemon
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, PROG_SLOTS);
+    __type(key, u32);
+    __type(value, u64);
+} events_sent SEC(".maps");
+
+// Records each program had to throw away because `events` was full
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, PROG_SLOTS);
+    __type(key, u32);
+    __type(value, u64);
+} events_lost SEC(".maps");
+
+static __always_inline void count_event(void *counters, u32 prog) {
+    u64 *count = bpf_map_lookup_elem(counters, &prog);
+    if (count) {
+        *count += 1;
+    }
+}
+
+// Reads who the process is and where it lives from `task_struct` via CO-RE
+static __always_inline void read_task_context(struct process_event *event) {
+    struct task_struct *task = (struct task_struct *)bpf_get_current_task();
+
+    event->ppid = BPF_CORE_READ(task, real_parent, tgid);
+    event->loginuid = BPF_CORE_READ(task, loginuid.val);
+    event->mnt_ns = BPF_CORE_READ(task, nsproxy, mnt_ns, ns.inum);
+    // The namespace the task's own PID lives in; pid_ns_for_children changes after unshare(CLONE_NEWPID)
+    struct pid *thread_pid = BPF_CORE_READ(task, thread_pid);
+    unsigned int level = BPF_CORE_READ(thread_pid, level);
+    event->pid_ns = BPF_CORE_READ(thread_pid, numbers[level].ns, ns.inum);
+    event->cgroup_id = bpf_get_current_cgroup_id();
+
+    event->tty[0] = '\0';
+    struct tty_struct *tty = BPF_CORE_READ(task, signal, tty);
+    if (tty) {
+        BPF_CORE_READ_STR_INTO(&event->tty, tty, name);
+    }
+}
+
+// Copies a bounded argv into `event->args`, one NUL-terminated string after another
+static __always_inline void read_argv(struct process_event *event, const char *const *argv) {
+    u32 argc = 0;
+    u32 args_size = 0;
+
+    event->args_truncated = 0;
+
+    #pragma unroll
+    for (int i = 0; i < MAX_ARGS; i++) {
+        const char *arg = NULL;
+        bpf_probe_read_user(&arg, sizeof(arg), &argv[i]);
+        if (!arg) {
+            goto done;
+        }
+
+        // Keep the offset provably inside `args` for the verifier
+        if (args_size > LAST_ARG_OFFSET) {
+            event->args_truncated = 1;
+            goto done;
+        }
+
+        long len = bpf_probe_read_user_str(&event->args[args_size], MAX_ARG_LEN, arg);
+        if (len <= 0) {
+            goto done;
+        }
+
+        argc++;
+        args_size += len;
+    }
+
+    // We copied MAX_ARGS arguments; flag it if there is at least one more
+    const char *extra = NULL;
+    bpf_probe_read_user(&extra, sizeof(extra), &argv[MAX_ARGS]);
+    if (extra) {
+        event->args_truncated = 1;
+    }
 
-    // Reserve space in the ring buffer
-    event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+done:
+    event->argc = argc;
+    event->args_size = args_size;
+}
+
+SEC("tracepoint/syscalls/sys_enter_execve")
+int trace_execve(struct execve_args *ctx) {
+    u32 zero = 0;
+    struct process_event *event = bpf_map_lookup_elem(&exec_scratch, &zero);
     if (!event) {
-        return 0; // Buffer full, drop the event
+        return 0;
     }
 
-    // Grab the Process ID and User ID using built-in BPF helpers
+    // The path handed to execve, e.g. "/usr/bin/nc". Read first, since the filters match on it.
+    if (bpf_probe_read_user_str(event->filename, sizeof(event->filename), ctx->filename) < 0) {
+        event->filename[0] = '\0';
+    }
+    if (exec_filtered(event->filename)) {
+        return 0;
+    }
+
+    event->kind = EVENT_KIND_EXEC;
+
+    // Grab the Process ID, User ID and Group ID using built-in BPF helpers
     u64 id = bpf_get_current_pid_tgid();
-    event->pid = id >> 32; 
+    event->pid = id >> 32;
+    u64 uid_gid = bpf_get_current_uid_gid();
+    event->uid = uid_gid;
+    event->gid = uid_gid >> 32;
+
+    read_task_context(event);
+
+    // Grab the name of the task calling execve, which is still the caller's
+    bpf_get_current_comm(&event->comm, sizeof(event->comm));
+
+    read_argv(event, ctx->argv);
+
+    // Only send the part of `args` we actually filled
+    u64 size = __builtin_offsetof(struct process_event, args) + event->args_size;
+    if (size > sizeof(*event)) {
+        return 0;
+    }
+
+    // Send it to the Rust daemon! If the buffer is full, the event is dropped and counted.
+    if (bpf_ringbuf_output(&events, event, size, 0) < 0) {
+        count_event(&events_lost, PROG_EXECVE);
+        return 0;
+    }
+    count_event(&events_sent, PROG_EXECVE);
+
+    return 0;
+}
+
+SEC("tracepoint/syscalls/sys_enter_connect")
+int trace_connect(struct connect_args *ctx) {
+    // Only IP sockets are interesting; Unix sockets and friends are skipped before reserving
+    u16 family = 0;
+    if (bpf_probe_read_user(&family, sizeof(family), &ctx->uservaddr->sa_family) < 0) {
+        return 0;
+    }
+    if (family != AF_INET && family != AF_INET6) {
+        return 0;
+    }
+
+    struct connect_event *event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+    if (!event) {
+        count_event(&events_lost, PROG_CONNECT); // Buffer full, drop the event
+        return 0;
+    }
+
+    event->kind = EVENT_KIND_CONNECT;
+    event->pid = bpf_get_current_pid_tgid() >> 32;
     event->uid = bpf_get_current_uid_gid();
+    event->family = family;
+    event->port = 0;
+    __builtin_memset(event->addr, 0, sizeof(event->addr));
+    bpf_get_current_comm(&event->comm, sizeof(event->comm));
+
+    if (family == AF_INET) {
+        struct sockaddr_in *sin = (struct sockaddr_in *)ctx->uservaddr;
+        bpf_probe_read_user(&event->port, sizeof(event->port), &sin->sin_port);
+        bpf_probe_read_user(event->addr, 4, &sin->sin_addr);
+    } else {
+        struct sockaddr_in6 *sin6 = (struct sockaddr_in6 *)ctx->uservaddr;
+        bpf_probe_read_user(&event->port, sizeof(event->port), &sin6->sin6_port);
+        bpf_probe_read_user(event->addr, 16, &sin6->sin6_addr);
+    }
+
+    bpf_ringbuf_submit(event, 0);
+    count_event(&events_sent, PROG_CONNECT);
+
+    return 0;
+}
+
+// Returns 1 if the last `rule->suffix_len` bytes of the path (of length `len`) equal the suffix
+static __always_inline int path_has_suffix(const struct path_key *key, u32 len, const struct prefix_rule *rule) {
+    u32 suffix_len = rule->suffix_len;
+    if (suffix_len == 0) {
+        return 1;
+    }
+    if (suffix_len > MAX_SUFFIX_LEN || suffix_len > len) {
+        return 0;
+    }
+
+    u32 start = len - suffix_len;
+
+    #pragma unroll
+    for (int i = 0; i < MAX_SUFFIX_LEN; i++) {
+        if (i >= suffix_len) {
+            break;
+        }
+        if (key->path[(start + i) & (MAX_PATH_LEN - 1)] != rule->suffix[i]) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+// Shared body of the openat/openat2 programs: report opens of watched paths only
+static __always_inline int handle_open(const char *filename, u32 flags, u32 prog) {
+    u32 zero = 0;
+    struct path_key *key = bpf_map_lookup_elem(&path_scratch, &zero);
+    if (!key) {
+        return 0;
+    }
+
+    long len = bpf_probe_read_user_str(key->path, sizeof(key->path), filename);
+    if (len <= 1) {
+        return 0;
+    }
+    // Without the terminating NUL. Relative paths never match, as every watched prefix starts with '/'.
+    u32 path_len = (len - 1) & (MAX_PATH_LEN - 1);
+    key->prefixlen = path_len * 8;
+
+    struct prefix_rule *rule = bpf_map_lookup_elem(&watched_prefixes, key);
+    if (!rule || !path_has_suffix(key, path_len, rule)) {
+        return 0;
+    }
+
+    struct open_event *event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+    if (!event) {
+        count_event(&events_lost, prog); // Buffer full, drop the event
+        return 0;
+    }
 
-    // Grab the name of the command being executed
+    event->kind = EVENT_KIND_OPEN;
+    event->pid = bpf_get_current_pid_tgid() >> 32;
+    event->uid = bpf_get_current_uid_gid();
+    event->flags = flags;
     bpf_get_current_comm(&event->comm, sizeof(event->comm));
+    bpf_probe_read_kernel_str(event->path, sizeof(event->path), key->path);
+
+    bpf_ringbuf_submit(event, 0);
+    count_event(&events_sent, prog);
+
+    return 0;
+}
+
+SEC("tracepoint/syscalls/sys_enter_openat")
+int trace_openat(struct openat_args *ctx) {
+    return handle_open(ctx->filename, ctx->flags, PROG_OPENAT);
+}
+
+SEC("tracepoint/syscalls/sys_enter_openat2")
+int trace_openat2(struct openat2_args *ctx) {
+    u64 flags = 0;
+    bpf_probe_read_user(&flags, sizeof(flags), &ctx->how->flags);
+    return handle_open(ctx->filename, flags, PROG_OPENAT2);
+}
+
+// TP_PROTO(struct task_struct *parent, struct task_struct *child)
+SEC("raw_tracepoint/sched_process_fork")
+int trace_fork(struct bpf_raw_tracepoint_args *ctx) {
+    struct task_struct *parent = (struct task_struct *)ctx->args[0];
+    struct task_struct *child = (struct task_struct *)ctx->args[1];
+
+    // New threads share their creator's tgid; only whole processes go into the tree
+    u32 child_pid = BPF_CORE_READ(child, pid);
+    if (child_pid != (u32)BPF_CORE_READ(child, tgid)) {
+        return 0;
+    }
+
+    struct fork_event *event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+    if (!event) {
+        count_event(&events_lost, PROG_FORK); // Buffer full, drop the event
+        return 0;
+    }
+
+    event->kind = EVENT_KIND_FORK;
+    event->parent_pid = BPF_CORE_READ(parent, tgid);
+    event->child_pid = child_pid;
+    event->uid = bpf_get_current_uid_gid();
+    BPF_CORE_READ_STR_INTO(&event->comm, child, comm);
+
+    bpf_ringbuf_submit(event, 0);
+    count_event(&events_sent, PROG_FORK);
+
+    return 0;
+}
+
+// TP_PROTO(struct task_struct *p, ...)
+SEC("raw_tracepoint/sched_process_exit")
+int trace_exit(struct bpf_raw_tracepoint_args *ctx) {
+    struct task_struct *task = (struct task_struct *)ctx->args[0];
+
+    // Threads exiting don't end the process; wait for the group leader
+    u32 pid = BPF_CORE_READ(task, pid);
+    if (pid != (u32)BPF_CORE_READ(task, tgid)) {
+        return 0;
+    }
+
+    struct exit_event *event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+    if (!event) {
+        count_event(&events_lost, PROG_EXIT); // Buffer full, drop the event
+        return 0;
+    }
+
+    event->kind = EVENT_KIND_EXIT;
+    event->pid = pid;
+    event->exit_code = BPF_CORE_READ(task, exit_code);
+    event->_pad = 0;
+    BPF_CORE_READ_STR_INTO(&event->comm, task, comm);
 
-    // Send it to the Rust daemon!
     bpf_ringbuf_submit(event, 0);
+    count_event(&events_sent, PROG_EXIT);
 
     return 0;
 }
 
 // eBPF programs must be GPL licensed
-char LICENSE[] SEC("license") = "GPL";
\ No newline at end of file
+char LICENSE[] SEC("license") = "GPL";
@@ -1,44 +1,70 @@
-use sysrag_common::ipc::{DaemonRequest, DaemonResponse};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use sysrag_common::frame::{read_frame, write_frame};
+use sysrag_common::ipc::{ClientHello, DaemonRequest, DaemonResponse, ServerHello, PROTOCOL_VERSION};
 use tokio::net::UnixStream;
 
 /// The client responsible for communicating with the background daemon
 pub struct DaemonClient {
     socket_path: String,
+    stream: UnixStream,
 }
 
 impl DaemonClient {
-    /// Initializes a new client pointing to the daemon's socket file
-    pub fn new(socket_path: &str) -> Self {
-        Self {
-            socket_path: socket_path.to_string(),
+    /// Connects to the daemon's socket file and negotiates the protocol version
+    pub async fn connect(socket_path: &str) -> Result<Self, anyhow::Error> {
+        // 1. Connect to the daemon's Unix Domain Socket
+        let mut stream = UnixStream::connect(socket_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to daemon at {}. Is sysragd running? Error: {}", socket_path, e))?;
+
+        // 2. Introduce ourselves with the protocol version we were built against
+        let hello = ClientHello {
+            protocol_version: PROTOCOL_VERSION,
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+        write_frame(&mut stream, &hello).await?;
+
+        // 3. A daemon that predates framing answers with raw JSON (or nothing), which fails here
+        let reply: ServerHello = read_frame(&mut stream)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| anyhow::anyhow!(
+                "Daemon at {} did not complete the protocol handshake. It is probably an older sysragd \
+                that does not speak protocol v{}; upgrade the daemon to match this CLI.",
+                socket_path, PROTOCOL_VERSION
+            ))?;
+
+        match reply {
+            ServerHello::Accepted { protocol_version, .. } if protocol_version == PROTOCOL_VERSION => {
+                Ok(Self { socket_path: socket_path.to_string(), stream })
+            }
+            ServerHello::Accepted { protocol_version, daemon_version } => anyhow::bail!(
+                "Daemon {} speaks protocol v{}, but this CLI speaks v{}.",
+                daemon_version, protocol_version, PROTOCOL_VERSION
+            ),
+            ServerHello::Rejected { protocol_version, reason } => anyhow::bail!(
+                "Daemon (protocol v{}) rejected this CLI (protocol v{}): {}",
+                protocol_version, PROTOCOL_VERSION, reason
+            ),
         }
     }
 
-    /// Opens a connection, sends the request, waits for the response, and closes the connection
-    pub async fn send_request(&self, req: DaemonRequest) -> Result<DaemonResponse, anyhow::Error> {
-        // 1. Connect to the daemon's Unix Domain Socket
-        let mut stream = UnixStream::connect(&self.socket_path)
+    /// Sends one request over the open connection and waits for its response.
+    /// Can be called any number of times on the same client.
+    pub async fn send_request(&mut self, req: DaemonRequest) -> Result<DaemonResponse, anyhow::Error> {
+        write_frame(&mut self.stream, &req).await?;
+
+        read_frame(&mut self.stream)
+            .await
+            .map_err(|e| anyhow::anyhow!("Daemon sent invalid response format: {}", e))?
+            .ok_or_else(|| anyhow::anyhow!("Daemon at {} closed the connection before responding", self.socket_path))
+    }
+
+    /// Waits for the next frame the daemon pushes on its own, e.g. while subscribed.
+    /// Returns `None` once the daemon closes the connection.
+    pub async fn next_response(&mut self) -> Result<Option<DaemonResponse>, anyhow::Error> {
+        read_frame(&mut self.stream)
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to connect to daemon at {}. Is sysragd running? Error: {}", self.socket_path, e))?;
-
-        // 2. Serialize our Rust Enum request into a JSON byte array
-        let req_bytes = serde_json::to_vec(&req)?;
-        
-        // 3. Fire the bytes over the socket
-        stream.write_all(&req_bytes).await?;
-        
-        // Tell the daemon we are done writing so it can start processing
-        stream.shutdown().await?; 
-
-        // 4. Read the daemon's response back into a buffer
-        let mut response_bytes = Vec::new();
-        stream.read_to_end(&mut response_bytes).await?;
-
-        // 5. Deserialize the JSON bytes back into our expected Rust Enum
-        let response: DaemonResponse = serde_json::from_slice(&response_bytes)
-            .map_err(|e| anyhow::anyhow!("Daemon sent invalid response format: {}", e))?;
-        
-        Ok(response)
+            .map_err(|e| anyhow::anyhow!("Daemon sent invalid response format: {}", e))
     }
-}
\ No newline at end of file
+}
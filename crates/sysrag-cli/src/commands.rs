@@ -1,11 +1,21 @@
-use clap::{Parser, Subcommand};
-use sysrag_common::ipc::DaemonResponse;
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sysrag_common::events::Fidelity;
+use sysrag_common::ipc::{
+    AnomalyQuery, AnomalyReport, DaemonResponse, EngineMode, FilterRule, ModeStatus, ProcessInfo,
+    ProcessTreeNode, TriageState,
+};
 
 /// The "Systems RAG": OS-Level Log Anomaly Detector
 #[derive(Parser)]
 #[command(name = "sysrag")]
 #[command(about = "Interact with the sysragd background AI daemon", long_about = None)]
 pub struct Cli {
+    /// Path to the daemon's Unix socket (must match `server.socket_path` in sysragd.toml)
+    #[arg(long, global = true, default_value = "/tmp/sysrag.sock")]
+    pub socket: String,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -14,43 +24,248 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     Status,
+    /// Search the anomaly history
     Anomalies { 
         #[arg(short, long, default_value_t = 10)]
-        tail: usize 
+        tail: usize,
+        #[command(flatten)]
+        query: QueryArgs,
     }, 
     Investigate { 
         // THIS IS THE CRUCIAL FIX: Make the ID optional!
         id: Option<String>, 
     },
+    /// Stream new anomalies live as the daemon detects them
+    Watch {
+        /// Only show anomalies whose command contains this text
+        #[arg(short, long)]
+        command: Option<String>,
+        /// Only show anomalies raised by this PID
+        #[arg(short, long)]
+        pid: Option<u32>,
+        /// Only show anomalies at or below this score (the one reports show, lower is worse)
+        #[arg(short = 's', long)]
+        max_score: Option<f32>,
+    },
+    /// Show how a process was started and everything it has spawned
+    Tree {
+        pid: u32,
+    },
+    /// Manage the in-kernel exec filters
+    Filter {
+        #[command(subcommand)]
+        action: FilterAction,
+    },
+    /// Mark how far you got with an anomaly
+    Triage {
+        id: String,
+        /// new, acknowledged, resolved or false_positive
+        state: TriageState,
+    },
+    /// Show the engine's mode, or switch between learning and enforcement
+    Mode {
+        /// learning or enforcement; leave out to show the current mode
+        mode: Option<EngineMode>,
+        /// How long to learn before enforcing on its own, like 30m, 12h or 7d (default: rag.mode.learning_period_secs)
+        #[arg(long = "for", value_parser = parse_duration, requires = "mode")]
+        duration: Option<u64>,
+    },
+    /// Accept the command of an anomaly into the baseline, so it is no longer flagged
+    Approve {
+        id: String,
+    },
+}
+
+/// Filters for `sysrag anomalies`
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Only anomalies since this time: a Unix timestamp or an age like 30m, 12h or 7d
+    #[arg(long, value_parser = parse_time)]
+    since: Option<u64>,
+    /// Only anomalies up to this time, in the same format as --since
+    #[arg(long, value_parser = parse_time)]
+    until: Option<u64>,
+    /// Only anomalies of exactly this command
+    #[arg(short, long)]
+    command: Option<String>,
+    /// Only anomalies at or above this score (the one reports show, lower is worse)
+    #[arg(long)]
+    min_score: Option<f32>,
+    /// Only anomalies at or below this score (the one reports show, lower is worse)
+    #[arg(long)]
+    max_score: Option<f32>,
+}
+
+impl QueryArgs {
+    pub fn into_query(self) -> AnomalyQuery {
+        AnomalyQuery {
+            since: self.since,
+            until: self.until,
+            command: self.command,
+            min_score: self.min_score,
+            max_score: self.max_score,
+        }
+    }
+}
+
+/// Accepts a Unix timestamp, or an age such as "90s", "30m", "12h" or "7d" counted back from now
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(timestamp) = value.parse() {
+        return Ok(timestamp);
+    }
+
+    let age = parse_duration(value)
+        .map_err(|_| format!("{:?} is neither a Unix timestamp nor an age like 30m, 12h or 7d", value))?;
+    Ok(now().saturating_sub(age))
+}
+
+/// Accepts seconds, or a duration such as "90s", "30m", "12h" or "7d"
+fn parse_duration(value: &str) -> Result<u64, String> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+
+    let invalid = || format!("{:?} is not a duration like 30m, 12h or 7d", value);
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let seconds_per_unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(invalid()),
+    };
+    let amount: u64 = value[..value.len() - 1].parse().map_err(|_| invalid())?;
+    Ok(amount.saturating_mul(seconds_per_unit))
+}
+
+/// Renders seconds as its two largest units, e.g. "2d 3h" or "45m 10s"
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[derive(Subcommand)]
+pub enum FilterAction {
+    /// Show the active filters
+    List,
+    /// Drop matching execs in the kernel from now on
+    Add(FilterArgs),
+    /// Report matching execs again
+    Remove(FilterArgs),
+}
+
+/// Exactly one of the filter kinds
+#[derive(Args)]
+#[command(group(ArgGroup::new("rule").required(true).args(["comm", "uid", "cgroup"])))]
+pub struct FilterArgs {
+    /// A trusted program name (the last part of its path, at most 15 bytes); what it runs is still reported
+    #[arg(long)]
+    comm: Option<String>,
+    /// A uid whose execs are ignored
+    #[arg(long)]
+    uid: Option<u32>,
+    /// A cgroup v2 path relative to /sys/fs/cgroup whose execs are ignored
+    #[arg(long)]
+    cgroup: Option<String>,
+}
+
+impl FilterArgs {
+    pub fn into_rule(self) -> FilterRule {
+        match (self.comm, self.uid, self.cgroup) {
+            (Some(comm), _, _) => FilterRule::Comm(comm),
+            (_, Some(uid), _) => FilterRule::Uid(uid),
+            (_, _, Some(cgroup)) => FilterRule::Cgroup(cgroup),
+            // clap's required group guarantees one of them is set
+            (None, None, None) => unreachable!(),
+        }
+    }
 }
 
 /// Formats the daemon's JSON response into beautiful terminal output
 pub fn handle_response(res: DaemonResponse) {
     match res {
-        DaemonResponse::StatusOk { uptime_seconds, events_processed, db_size } => {
+        DaemonResponse::StatusOk {
+            uptime_seconds, events_processed, events_per_second, peak_events_per_second,
+            filter_drops, sensor, anomalies_detected, db_size,
+        } => {
             println!("🟢 SYSRAG DAEMON STATUS: ONLINE");
             println!("---------------------------------");
             println!("Uptime:           {} seconds", uptime_seconds);
             println!("Kernel Events:    {}", events_processed);
+            println!("Event Rate:       {}/s (peak {}/s)", events_per_second, peak_events_per_second);
+            println!("Filtered Execs:   comm {}, uid {}, cgroup {}",
+                filter_drops.comm, filter_drops.uid, filter_drops.cgroup);
+            println!("Anomalies:        {}", anomalies_detected);
             println!("Vector DB Size:   {} baselines", db_size);
+
+            let lost: u64 = sensor.iter().map(|p| p.lost).sum();
+            if lost > 0 {
+                println!("⚠️ Ring buffer overflowed, {} kernel events were lost. Visibility has gaps.", lost);
+            }
+            println!("Probe        Sent        Lost");
+            for program in &sensor {
+                println!("{:<12} {:<11} {}", program.program, program.sent, program.lost);
+            }
         }
         DaemonResponse::AnomaliesList(anomalies) => {
             if anomalies.is_empty() {
-                println!("✅ No anomalies detected recently. System is clean.");
+                println!("✅ No matching anomalies in the history.");
                 return;
             }
             
             println!("🚨 RECENT SYSTEM ANOMALIES 🚨");
             for anomaly in anomalies {
-                println!("--------------------------------------------------");
-                println!("ID:       {}", anomaly.id);
-                println!("Command:  {}", anomaly.command);
-                println!("PID:      {}", anomaly.pid);
-                println!("Score:    {:.2} (Lower is worse)", anomaly.similarity_score);
-                println!("Hint: Run `sysrag investigate {}` for AI analysis.", anomaly.id);
+                print_anomaly(&anomaly);
             }
             println!("--------------------------------------------------");
         }
+        DaemonResponse::ProcessTree { ancestry, tree } => {
+            println!("🌳 PROCESS TREE FOR PID {}", tree.process.pid);
+            println!("---------------------------------");
+            println!("Ancestry: {}", format_ancestry(&ancestry));
+            println!();
+            print_tree(&tree, 0);
+        }
+        DaemonResponse::Filters(rules) => {
+            if rules.is_empty() {
+                println!("No exec filters active. Every exec reaches the RAG engine.");
+                return;
+            }
+
+            println!("🧹 ACTIVE EXEC FILTERS");
+            println!("---------------------------------");
+            for rule in rules {
+                println!("{}", rule);
+            }
+        }
+        DaemonResponse::Triaged { id, triage } => {
+            println!("🏷️ Anomaly {} is now {}", id, triage);
+        }
+        DaemonResponse::Mode(status) => print_mode(&status),
+        DaemonResponse::Approved { id, command } => {
+            println!("✅ {} (anomaly {}) is now part of the baseline and won't be flagged again", command, id);
+        }
+        DaemonResponse::Subscribed => {
+            println!("👀 Watching for new anomalies (Ctrl+C to stop)...");
+        }
+        DaemonResponse::Anomaly(anomaly) => {
+            print_anomaly(&anomaly);
+        }
+        DaemonResponse::SubscriptionLagged { missed } => {
+            eprintln!("⚠️ Fell behind the daemon, {} anomalies were skipped.", missed);
+        }
         DaemonResponse::InvestigationResult(analysis) => {
             println!("🧠 LLM THREAT ANALYSIS 🧠");
             println!("---------------------------------");
@@ -61,4 +276,115 @@ pub fn handle_response(res: DaemonResponse) {
             eprintln!("❌ DAEMON ERROR: {}", err_msg);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Prints a single anomaly block
+fn print_anomaly(anomaly: &AnomalyReport) {
+    println!("--------------------------------------------------");
+    println!("ID:       {}", anomaly.id);
+    println!("Command:  {}", anomaly.command);
+    println!("Exe:      {}", anomaly.exe);
+    println!("Args:     {}", anomaly.argv.join(" "));
+    println!("PID:      {} (parent {})", anomaly.pid, anomaly.context.ppid);
+    println!("User:     uid {} gid {} login {}", anomaly.uid, anomaly.context.gid,
+        anomaly.context.loginuid.map_or("-".to_string(), |uid| uid.to_string()));
+    println!("TTY:      {}", anomaly.context.tty.as_deref().unwrap_or("-"));
+    println!("cgroup:   {} (mnt ns {}, pid ns {})", anomaly.context.cgroup_id,
+        anomaly.context.mnt_ns, anomaly.context.pid_ns);
+    if !anomaly.ancestry.is_empty() {
+        println!("Ancestry: {}", format_ancestry(&anomaly.ancestry));
+    }
+    for conn in &anomaly.connections {
+        println!("Network:  -> {}", SocketAddr::new(conn.address, conn.port));
+    }
+    for file in &anomaly.file_accesses {
+        println!("File:     {} ({})", file.path, if file.write { "write" } else { "read" });
+    }
+    if anomaly.fidelity == Fidelity::Sampled {
+        println!("Source:   /proc polling (lower fidelity, short-lived processes may be missing)");
+    }
+    match &anomaly.score {
+        Some(score) => {
+            // Reports from before executables were canonicalized only have the path handed to execve
+            let exe = if score.executable.is_empty() { &anomaly.exe } else { &score.executable };
+            println!("Score:    {:.2} (Lower is worse)", score.total);
+            println!(
+                "Parts:    similarity {:.2}, frequency {:+.2}, recency {:+.2}, uid spread {:+.2}",
+                score.similarity, score.frequency, score.recency, score.uid_spread
+            );
+            match score.last_seen {
+                Some(last_seen) => println!(
+                    "History:  {} seen {} times by {} uids, last {} ago",
+                    exe,
+                    score.sightings, score.distinct_uids, format_duration(now().saturating_sub(last_seen))
+                ),
+                None => println!("History:  {} never seen before", exe),
+            }
+        }
+        None => println!("Score:    {:.2} (Lower is worse)", anomaly.similarity_score),
+    }
+    if let Some(nearest) = &anomaly.nearest {
+        println!("Closest:  {} ({}, seen {} times)", nearest.command, nearest.origin, nearest.count);
+    }
+    println!("Baseline: {}", anomaly.scope.as_deref().unwrap_or("global"));
+    println!("Triage:   {}", anomaly.triage);
+    match &anomaly.investigation {
+        Some(analysis) => {
+            println!("Analysis:");
+            for line in analysis.lines() {
+                println!("  {}", line);
+            }
+        }
+        None => println!("Hint: Run `sysrag investigate {}` for AI analysis.", anomaly.id),
+    }
+}
+
+/// Prints the engine's mode and how anomalies get into the baseline
+fn print_mode(status: &ModeStatus) {
+    match status.mode {
+        EngineMode::Learning => println!("🎓 ENGINE MODE: LEARNING"),
+        EngineMode::Enforcement => println!("🛡️ ENGINE MODE: ENFORCEMENT"),
+    }
+    println!("---------------------------------");
+    match (status.mode, status.learning_until) {
+        (EngineMode::Learning, Some(until)) => {
+            println!("Baseline:     absorbs everything it sees, anomalies included");
+            println!("Enforcement:  starts on its own in {}", format_duration(until.saturating_sub(now())));
+        }
+        (EngineMode::Learning, None) => {
+            println!("Baseline:     absorbs everything it sees, anomalies included");
+            println!("Enforcement:  starts with `sysrag mode enforcement`");
+        }
+        (EngineMode::Enforcement, _) => {
+            if status.promote_after_sightings == 0 {
+                println!("Promotion:    only by `sysrag approve <id>`");
+            } else {
+                println!("Promotion:    after {} sightings within {}, or by `sysrag approve <id>`",
+                    status.promote_after_sightings, format_duration(status.promotion_window_secs));
+                println!("Pending:      {} anomalous commands", status.pending_promotions);
+            }
+        }
+    }
+}
+
+/// Renders an ancestry chain as "sshd → bash → curl → sh"
+fn format_ancestry(ancestry: &[ProcessInfo]) -> String {
+    ancestry.iter().map(|p| p.comm.as_str()).collect::<Vec<_>>().join(" → ")
+}
+
+/// Prints a process and its descendants, one indented line each
+fn print_tree(node: &ProcessTreeNode, depth: usize) {
+    let process = &node.process;
+    let state = match (process.exited_at, process.exit_status, process.exit_signal) {
+        (None, _, _) => "running".to_string(),
+        (Some(_), _, Some(signal)) => format!("killed by signal {}", signal),
+        (Some(_), Some(status), _) => format!("exited {}", status),
+        (Some(_), None, None) => "exited".to_string(),
+    };
+
+    println!("{}{} [{}] uid {} {} ({})", "  ".repeat(depth), process.comm, process.pid,
+        process.uid, process.exe, state);
+    for child in &node.children {
+        print_tree(child, depth + 1);
+    }
+}
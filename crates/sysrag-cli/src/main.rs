@@ -3,22 +3,20 @@ mod commands;
 
 use clap::Parser;
 use client::DaemonClient;
-use commands::{Cli, Commands, handle_response};
-use sysrag_common::ipc::{DaemonRequest, DaemonResponse};
+use commands::{Cli, Commands, FilterAction, handle_response};
+use sysrag_common::ipc::{AnomalyFilter, AnomalyQuery, DaemonRequest, DaemonResponse, EngineMode};
 use anyhow::{Result, Context};
 use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::time::Duration;
 
-const SOCKET_PATH: &str = "/tmp/sysrag.sock";
-
 #[tokio::main]
 async fn main() -> Result<()> {
     // 1. Parse terminal arguments
     let cli = Cli::parse();
 
-    // 2. Instantiate the socket client
-    let daemon_client = DaemonClient::new(SOCKET_PATH);
+    // 2. Connect to the daemon and negotiate the protocol version
+    let mut daemon_client = DaemonClient::connect(&cli.socket).await?;
 
     // 3. Handle Commands
     match cli.command {
@@ -26,8 +24,52 @@ async fn main() -> Result<()> {
             let resp = daemon_client.send_request(DaemonRequest::Status).await?;
             handle_response(resp);
         }
-        Commands::Anomalies { tail } => {
-            let resp = daemon_client.send_request(DaemonRequest::GetAnomalies { tail }).await?;
+        Commands::Anomalies { tail, query } => {
+            let query = query.into_query();
+            let resp = daemon_client.send_request(DaemonRequest::GetAnomalies { tail, query }).await?;
+            handle_response(resp);
+        }
+        Commands::Watch { command, pid, max_score } => {
+            let filter = AnomalyFilter { command, pid, max_score };
+            let resp = daemon_client.send_request(DaemonRequest::Subscribe { filter }).await?;
+            handle_response(resp);
+
+            // The daemon now pushes one frame per new anomaly until one of us hangs up
+            while let Some(resp) = daemon_client.next_response().await? {
+                handle_response(resp);
+            }
+            println!("Daemon closed the subscription.");
+        }
+        Commands::Tree { pid } => {
+            let resp = daemon_client.send_request(DaemonRequest::GetProcessTree { pid }).await?;
+            handle_response(resp);
+        }
+        Commands::Filter { action } => {
+            let req = match action {
+                FilterAction::List => DaemonRequest::ListFilters,
+                FilterAction::Add(args) => DaemonRequest::AddFilter { rule: args.into_rule() },
+                FilterAction::Remove(args) => DaemonRequest::RemoveFilter { rule: args.into_rule() },
+            };
+            let resp = daemon_client.send_request(req).await?;
+            handle_response(resp);
+        }
+        Commands::Triage { id, state } => {
+            let resp = daemon_client.send_request(DaemonRequest::SetTriage { id, triage: state }).await?;
+            handle_response(resp);
+        }
+        Commands::Mode { mode, duration } => {
+            let req = match mode {
+                None => DaemonRequest::GetMode,
+                Some(EngineMode::Enforcement) if duration.is_some() => {
+                    anyhow::bail!("--for only applies to learning mode");
+                }
+                Some(mode) => DaemonRequest::SetMode { mode, learning_secs: duration },
+            };
+            let resp = daemon_client.send_request(req).await?;
+            handle_response(resp);
+        }
+        Commands::Approve { id } => {
+            let resp = daemon_client.send_request(DaemonRequest::Approve { id }).await?;
             handle_response(resp);
         }
         Commands::Investigate { id } => {
@@ -35,7 +77,8 @@ async fn main() -> Result<()> {
             let target_id = match id {
                 Some(val) => val,
                 None => {
-                    let resp = daemon_client.send_request(DaemonRequest::GetAnomalies { tail: 1 }).await?;
+                    let req = DaemonRequest::GetAnomalies { tail: 1, query: AnomalyQuery::default() };
+                    let resp = daemon_client.send_request(req).await?;
                     if let DaemonResponse::AnomaliesList(list) = resp {
                         list.first()
                             .map(|a| a.id.clone())
@@ -84,7 +127,7 @@ async fn main() -> Result<()> {
                     .template("{spinner:.cyan} {msg}")
                     .unwrap(),
             );
-            spinner.set_message(format!("\"Hack the planet!\" (Analyzing kernel logs...)"));
+            spinner.set_message("\"Hack the planet!\" (Analyzing kernel logs...)");
             spinner.enable_steady_tick(Duration::from_millis(80));
 
             // Run the actual network investigation
@@ -94,7 +137,9 @@ async fn main() -> Result<()> {
             spinner.finish_and_clear();
 
             // The Output
-            if let DaemonResponse::InvestigationResult(analysis) = resp {
+            if let DaemonResponse::Error(err_msg) = resp {
+                println!("{} {}", "Error:".red(), err_msg);
+            } else if let DaemonResponse::InvestigationResult(analysis) = resp {
                 println!("{}", "\"Analysis Complete.\"".cyan());
                 println!("  - Llama 3");
                 println!();
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+/// A typed observation produced by a sensor and consumed by the RAG engine.
+/// Each kind of kernel activity gets its own variant, so new sensors never need string parsing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KernelEvent {
+    /// A process called `execve`
+    Exec(ExecEvent),
+    /// A process opened an outbound IPv4/IPv6 connection
+    Connect(ConnectEvent),
+    /// A process opened a watched sensitive path
+    FileOpen(FileOpenEvent),
+    /// A process forked a new process (threads are not reported)
+    Fork(ForkEvent),
+    /// A process exited
+    Exit(ExitEvent),
+}
+
+/// Longest task name the kernel keeps (`TASK_COMM_LEN` without the NUL)
+pub const COMM_LEN: usize = 15;
+
+/// Cuts a name down to what the kernel would keep as a comm, on a character boundary
+pub fn truncate_comm(name: &str) -> &str {
+    let mut end = name.len().min(COMM_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// How completely a sensor observed an event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fidelity {
+    /// Captured by a kernel probe as it happened
+    #[default]
+    Exact,
+    /// Inferred by polling, e.g. diffing `/proc`. Processes that start and exit between two polls
+    /// are never seen, and fields like the exit status are unknown.
+    Sampled,
+}
+
+impl Fidelity {
+    /// Marker appended to log lines of sampled events
+    fn suffix(self) -> &'static str {
+        match self {
+            Fidelity::Exact => "",
+            Fidelity::Sampled => " (sampled)",
+        }
+    }
+}
+
+/// A process execution captured at `sys_enter_execve`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecEvent {
+    pub pid: u32,
+    pub uid: u32,
+    /// The kernel task name, at most 15 bytes
+    pub comm: String,
+    /// Parent process, user, container and terminal details
+    pub context: ProcessContext,
+    /// The path handed to `execve`, e.g. "/usr/bin/nc"
+    pub filename: String,
+    /// The argument vector including argv[0], bounded by the sensor
+    pub argv: Vec<String>,
+    /// True if the sensor had to drop trailing arguments
+    pub argv_truncated: bool,
+    /// Older recordings predate this field and were all captured by the kernel sensor
+    #[serde(default)]
+    pub fidelity: Fidelity,
+}
+
+/// An outbound connection captured at `sys_enter_connect`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectEvent {
+    pub pid: u32,
+    pub uid: u32,
+    pub comm: String,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// An `openat`/`openat2` of a path under one of the watched prefixes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOpenEvent {
+    pub pid: u32,
+    pub uid: u32,
+    pub comm: String,
+    pub path: String,
+    /// Raw `O_*` flags passed to open
+    pub flags: u32,
+}
+
+impl FileOpenEvent {
+    /// True if the file was opened for writing (O_WRONLY or O_RDWR)
+    pub fn is_write(&self) -> bool {
+        self.flags & 0b11 != 0
+    }
+}
+
+/// A new process created by `fork`/`clone`, captured at `sched_process_fork`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkEvent {
+    pub parent_pid: u32,
+    pub child_pid: u32,
+    pub uid: u32,
+    /// The child starts out with its parent's comm until it execs
+    pub comm: String,
+}
+
+/// A process exit captured at `sched_process_exit`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExitEvent {
+    pub pid: u32,
+    pub comm: String,
+    /// Raw wait status as stored in `task_struct::exit_code`, meaningless for sampled exits
+    pub exit_code: u32,
+    #[serde(default)]
+    pub fidelity: Fidelity,
+}
+
+impl ExitEvent {
+    /// The status passed to `exit()`, if the process exited normally
+    pub fn status(&self) -> Option<i32> {
+        if self.fidelity == Fidelity::Sampled {
+            return None;
+        }
+        (self.exit_code & 0x7f == 0).then_some(((self.exit_code >> 8) & 0xff) as i32)
+    }
+
+    /// The signal that killed the process, if any
+    pub fn signal(&self) -> Option<i32> {
+        if self.fidelity == Fidelity::Sampled {
+            return None;
+        }
+        let signal = (self.exit_code & 0x7f) as i32;
+        (signal != 0).then_some(signal)
+    }
+}
+
+/// Where a process comes from and what it runs inside of.
+/// This is what separates a container workload from a host shell, or a user from a daemon.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessContext {
+    /// PID of the real parent
+    pub ppid: u32,
+    pub gid: u32,
+    /// Audit login uid of the session, `None` for processes never tied to a login
+    pub loginuid: Option<u32>,
+    /// cgroup v2 id
+    pub cgroup_id: u64,
+    /// Mount namespace inode number
+    pub mnt_ns: u32,
+    /// PID namespace inode number
+    pub pid_ns: u32,
+    /// Controlling terminal, e.g. "pts0"; `None` for daemons
+    pub tty: Option<String>,
+}
+
+impl ExecEvent {
+    /// The name the process carries once the exec succeeds, which the kernel takes from the last
+    /// component of the path handed to `execve`. `comm` may still be the caller's.
+    pub fn program_name(&self) -> &str {
+        match self.filename.rsplit('/').next() {
+            Some(name) if !name.is_empty() => truncate_comm(name),
+            _ => &self.comm,
+        }
+    }
+
+    /// The arguments joined back into a single shell-like line
+    pub fn command_line(&self) -> String {
+        let mut line = self.argv.join(" ");
+        if self.argv_truncated {
+            line.push_str(" …");
+        }
+        line
+    }
+}
+
+impl KernelEvent {
+    /// The process that caused the event
+    pub fn pid(&self) -> u32 {
+        match self {
+            KernelEvent::Exec(exec) => exec.pid,
+            KernelEvent::Connect(conn) => conn.pid,
+            KernelEvent::FileOpen(open) => open.pid,
+            KernelEvent::Fork(fork) => fork.child_pid,
+            KernelEvent::Exit(exit) => exit.pid,
+        }
+    }
+}
+
+/// Human-readable one-line form, used as the raw log in anomaly reports
+impl fmt::Display for KernelEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelEvent::Exec(exec) => write!(
+                f,
+                "execve: pid={} ppid={} uid={} gid={} loginuid={} tty={} cgroup={} mnt_ns={} pid_ns={} comm={:?} filename={:?} argv={:?}{}{}",
+                exec.pid,
+                exec.context.ppid,
+                exec.uid,
+                exec.context.gid,
+                exec.context.loginuid.map_or("unset".to_string(), |uid| uid.to_string()),
+                exec.context.tty.as_deref().unwrap_or("none"),
+                exec.context.cgroup_id,
+                exec.context.mnt_ns,
+                exec.context.pid_ns,
+                exec.comm,
+                exec.filename,
+                exec.argv,
+                if exec.argv_truncated { " (truncated)" } else { "" },
+                exec.fidelity.suffix()
+            ),
+            KernelEvent::Connect(conn) => write!(
+                f,
+                "connect: pid={} uid={} comm={:?} dest={}",
+                conn.pid,
+                conn.uid,
+                conn.comm,
+                std::net::SocketAddr::new(conn.address, conn.port)
+            ),
+            KernelEvent::FileOpen(open) => write!(
+                f,
+                "open: pid={} uid={} comm={:?} path={:?} flags={:#o}",
+                open.pid, open.uid, open.comm, open.path, open.flags
+            ),
+            KernelEvent::Fork(fork) => write!(
+                f,
+                "fork: parent={} child={} uid={} comm={:?}",
+                fork.parent_pid, fork.child_pid, fork.uid, fork.comm
+            ),
+            KernelEvent::Exit(exit) => write!(
+                f,
+                "exit: pid={} comm={:?} status={} signal={}{}",
+                exit.pid,
+                exit.comm,
+                exit.status().map_or("-".to_string(), |s| s.to_string()),
+                exit.signal().map_or("-".to_string(), |s| s.to_string()),
+                exit.fidelity.suffix()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec() -> ExecEvent {
+        ExecEvent {
+            pid: 42,
+            uid: 1000,
+            comm: "bash".to_string(),
+            context: ProcessContext { ppid: 7, tty: Some("pts0".to_string()), ..Default::default() },
+            filename: "/usr/bin/nc".to_string(),
+            argv: vec!["nc".to_string(), "-l".to_string()],
+            argv_truncated: false,
+            fidelity: Fidelity::Exact,
+        }
+    }
+
+    #[test]
+    fn every_event_names_the_process_it_is_about() {
+        let fork = ForkEvent { parent_pid: 1, child_pid: 2, uid: 0, comm: "init".to_string() };
+        let connect = ConnectEvent {
+            pid: 3,
+            uid: 0,
+            comm: "curl".to_string(),
+            address: "10.0.0.1".parse().unwrap(),
+            port: 443,
+        };
+
+        assert_eq!(KernelEvent::Exec(exec()).pid(), 42);
+        // A fork is about the new process, not the one that forked
+        assert_eq!(KernelEvent::Fork(fork).pid(), 2);
+        assert_eq!(KernelEvent::Connect(connect).pid(), 3);
+    }
+
+    #[test]
+    fn log_lines_carry_the_event_details() {
+        let line = KernelEvent::Exec(exec()).to_string();
+        assert!(line.starts_with("execve: pid=42 ppid=7 uid=1000"), "{}", line);
+        assert!(line.contains("loginuid=unset tty=pts0"), "{}", line);
+        assert!(line.ends_with(r#"filename="/usr/bin/nc" argv=["nc", "-l"]"#), "{}", line);
+
+        let connect = ConnectEvent {
+            pid: 3,
+            uid: 0,
+            comm: "curl".to_string(),
+            address: "::1".parse().unwrap(),
+            port: 443,
+        };
+        assert_eq!(KernelEvent::Connect(connect).to_string(), r#"connect: pid=3 uid=0 comm="curl" dest=[::1]:443"#);
+    }
+
+    #[test]
+    fn truncated_argv_is_marked_on_the_command_line() {
+        let mut exec = exec();
+        assert_eq!(exec.command_line(), "nc -l");
+        exec.argv_truncated = true;
+        assert_eq!(exec.command_line(), "nc -l …");
+        assert!(KernelEvent::Exec(exec).to_string().ends_with("(truncated)"));
+    }
+
+    #[test]
+    fn only_write_modes_count_as_writes() {
+        // O_* values from asm-generic/fcntl.h
+        const O_WRONLY: u32 = 0o1;
+        const O_RDWR: u32 = 0o2;
+        const O_CLOEXEC: u32 = 0o2000000;
+        let open = |flags| FileOpenEvent { pid: 1, uid: 0, comm: "cat".to_string(), path: "/etc/shadow".to_string(), flags };
+
+        assert!(!open(O_CLOEXEC).is_write());
+        assert!(open(O_WRONLY).is_write());
+        assert!(open(O_RDWR | O_CLOEXEC).is_write());
+    }
+
+    #[test]
+    fn exit_codes_decode_into_status_or_signal() {
+        let exit = |exit_code, fidelity| ExitEvent { pid: 1, comm: "sh".to_string(), exit_code, fidelity };
+
+        // exit(3)
+        let exited = exit(3 << 8, Fidelity::Exact);
+        assert_eq!((exited.status(), exited.signal()), (Some(3), None));
+        // Killed by SIGKILL, with the core dump bit set as it is for SIGSEGV
+        let killed = exit(9, Fidelity::Exact);
+        assert_eq!((killed.status(), killed.signal()), (None, Some(9)));
+        let dumped = exit(0x80 | 11, Fidelity::Exact);
+        assert_eq!(dumped.signal(), Some(11));
+        // Polling never learns how a process ended
+        let sampled = exit(0, Fidelity::Sampled);
+        assert_eq!((sampled.status(), sampled.signal()), (None, None));
+        assert!(KernelEvent::Exit(sampled).to_string().ends_with("status=- signal=- (sampled)"));
+    }
+}
@@ -0,0 +1,134 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Upper bound for a single frame, so a bogus length prefix can't make us allocate gigabytes
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// Writes one message as a 4-byte big-endian length prefix followed by its JSON body
+pub async fn write_frame<W, T>(writer: &mut W, message: &T) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let body = serde_json::to_vec(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame of {} bytes exceeds the {} byte limit", body.len(), MAX_FRAME_LEN),
+            )
+        })?;
+
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
+
+/// Reads one length-prefixed JSON message.
+/// Returns `Ok(None)` if the peer closed the connection cleanly between frames.
+pub async fn read_frame<R, T>(reader: &mut R) -> io::Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut len_bytes = [0u8; 4];
+    match reader.read_exact(&mut len_bytes).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len_bytes);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("peer announced a {} byte frame, limit is {}", len, MAX_FRAME_LEN),
+        ));
+    }
+
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body).await?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{ClientHello, ServerHello, PROTOCOL_VERSION};
+
+    #[tokio::test]
+    async fn frames_round_trip_back_to_back() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_frame(&mut client, &"first").await.unwrap();
+        write_frame(&mut client, &vec![1u32, 2, 3]).await.unwrap();
+        drop(client);
+
+        assert_eq!(read_frame::<_, String>(&mut server).await.unwrap().as_deref(), Some("first"));
+        assert_eq!(read_frame::<_, Vec<u32>>(&mut server).await.unwrap(), Some(vec![1, 2, 3]));
+        // A clean close between frames is not an error
+        assert!(read_frame::<_, String>(&mut server).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused_both_ways() {
+        let mut sink = Vec::new();
+        let huge = "x".repeat(MAX_FRAME_LEN as usize);
+        let err = write_frame(&mut sink, &huge).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(sink.is_empty());
+
+        // The length alone is enough to refuse, without waiting for the body
+        let announced = (MAX_FRAME_LEN + 1).to_be_bytes();
+        let err = read_frame::<_, String>(&mut &announced[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_are_errors() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &"truncated").await.unwrap();
+
+        // Cut inside the body
+        let err = read_frame::<_, String>(&mut &frame[..frame.len() - 1]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        // A partial length prefix reads like the peer leaving between frames
+        assert!(read_frame::<_, String>(&mut &frame[..2]).await.unwrap().is_none());
+
+        let garbage = [0, 0, 0, 2, b'{', b'{'];
+        let err = read_frame::<_, String>(&mut &garbage[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn handshake_round_trips() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let hello = ClientHello { protocol_version: PROTOCOL_VERSION, client_version: "0.1.0".to_string() };
+        write_frame(&mut client, &hello).await.unwrap();
+        let received: ClientHello = read_frame(&mut server).await.unwrap().unwrap();
+        assert_eq!(received.protocol_version, PROTOCOL_VERSION);
+
+        let reply = ServerHello::Rejected { protocol_version: PROTOCOL_VERSION, reason: "old".to_string() };
+        write_frame(&mut server, &reply).await.unwrap();
+        let received: ServerHello = read_frame(&mut client).await.unwrap().unwrap();
+        assert!(matches!(received, ServerHello::Rejected { reason, .. } if reason == "old"));
+    }
+
+    #[test]
+    fn client_hello_keeps_its_wire_format() {
+        // Any two versions must be able to read each other's hello
+        let hello = ClientHello { protocol_version: 3, client_version: "0.1.0".to_string() };
+        assert_eq!(
+            serde_json::to_string(&hello).unwrap(),
+            r#"{"protocol_version":3,"client_version":"0.1.0"}"#
+        );
+    }
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::events::{Fidelity, ProcessContext};
 
 /// Represents a flagged anomaly detected by the eBPF/RAG pipeline
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -6,9 +10,249 @@ pub struct AnomalyReport {
     pub id: String,             // Unique UUID for the anomaly
     pub timestamp: u64,         // Unix timestamp of occurrence
     pub pid: u32,               // The Process ID that triggered it
+    pub uid: u32,               // The user it ran as
     pub command: String,        // The command executed (e.g., "curl", "nc")
+    pub exe: String,            // The path handed to execve (e.g., "/usr/bin/nc")
+    pub argv: Vec<String>,      // The full argument vector, as far as the sensor could read it
+    pub context: ProcessContext, // Parent, groups, cgroup, namespaces and tty
+    pub connections: Vec<OutboundConnection>, // Outbound connections made after the flagged exec
+    pub file_accesses: Vec<FileAccess>, // Watched sensitive paths opened after the flagged exec
+    pub ancestry: Vec<ProcessInfo>, // Oldest ancestor first, ending with the flagged process itself
+    pub fidelity: Fidelity,     // Sampled if the exec was inferred by polling rather than caught by a probe
     pub similarity_score: f32,  // How close it was to the baseline (0.0 to 1.0)
+    #[serde(default)]
+    pub score: Option<ScoreBreakdown>, // What the anomaly score was made of, None in reports from before composite scoring
+    #[serde(default)]
+    pub nearest: Option<BaselineEntry>, // The baseline entry it came closest to, if the baseline wasn't empty
+    #[serde(default)]
+    pub scope: Option<String>,  // The scoped baseline that flagged it (e.g. "uid=33 parent=nginx"), None for the global one
     pub raw_log: String,        // The raw context log
+    #[serde(default)]
+    pub triage: TriageState,    // Where an operator is with it
+    #[serde(default)]
+    pub investigation: Option<String>, // The last LLM analysis, if anyone asked for one
+}
+
+impl AnomalyReport {
+    /// The score the anomaly was flagged by: the composite total, or the similarity alone for
+    /// reports from before composite scoring
+    pub fn final_score(&self) -> f32 {
+        self.score.as_ref().map_or(self.similarity_score, |score| score.total)
+    }
+}
+
+/// One distinct text in the baseline, with how often and when it was seen
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BaselineEntry {
+    /// The text that was embedded (the command name)
+    pub command: String,
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub origin: BaselineOrigin,
+}
+
+impl BaselineEntry {
+    /// An entry seen once, at `now`
+    pub fn new(command: &str, origin: BaselineOrigin, now: u64) -> Self {
+        Self { command: command.to_string(), count: 1, first_seen: now, last_seen: now, origin }
+    }
+}
+
+/// The parts of an anomaly score. The similarity contributes itself and every other signal takes
+/// away a share of it, so the contributions add up to `total`, which was below the threshold.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScoreBreakdown {
+    /// The executable the signals below describe: the file the exec ran, after resolving
+    /// relative paths and symlinks
+    #[serde(default)]
+    pub executable: String,
+    pub total: f32,
+    /// Similarity to the closest baseline entry (0.0 to 1.0)
+    pub similarity: f32,
+    /// Taken away for an executable seen rarely before (0.0 or less)
+    pub frequency: f32,
+    /// Taken away for an executable not seen lately
+    pub recency: f32,
+    /// Taken away for an executable run by few uids
+    pub uid_spread: f32,
+    /// Sightings of the executable before this one
+    pub sightings: u64,
+    /// When the executable was last seen before, `None` if never
+    pub last_seen: Option<u64>,
+    /// Distinct uids the executable ran as before
+    pub distinct_uids: u32,
+}
+
+/// How an entry got into the baseline
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BaselineOrigin {
+    /// One of the configured seed commands
+    Seed,
+    /// Absorbed from observed activity
+    Learned,
+    /// Accepted by an operator
+    OperatorApproved,
+}
+
+impl BaselineOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BaselineOrigin::Seed => "seed",
+            BaselineOrigin::Learned => "learned",
+            BaselineOrigin::OperatorApproved => "operator_approved",
+        }
+    }
+}
+
+impl fmt::Display for BaselineOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether the engine is still learning what normal looks like
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineMode {
+    /// Everything seen is absorbed into the baseline, anomalies included
+    Learning,
+    /// Anomalies only join the baseline once an operator approves them or they keep recurring
+    Enforcement,
+}
+
+impl EngineMode {
+    pub const ALL: [EngineMode; 2] = [EngineMode::Learning, EngineMode::Enforcement];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EngineMode::Learning => "learning",
+            EngineMode::Enforcement => "enforcement",
+        }
+    }
+}
+
+impl fmt::Display for EngineMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for EngineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str() == s)
+            .ok_or_else(|| format!("unknown mode {:?}", s))
+    }
+}
+
+/// The engine's mode and how it promotes anomalies into the baseline
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModeStatus {
+    pub mode: EngineMode,
+    /// When learning ends on its own, `None` if it lasts until an operator switches modes
+    pub learning_until: Option<u64>,
+    /// In enforcement mode, an anomalous command seen this many times within `promotion_window_secs`
+    /// joins the baseline. 0 means only operator approval promotes.
+    pub promote_after_sightings: u32,
+    pub promotion_window_secs: u64,
+    /// Anomalous commands seen within the window that haven't been promoted yet
+    pub pending_promotions: usize,
+}
+
+/// How far an operator got with an anomaly
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriageState {
+    /// Nobody has looked at it yet
+    #[default]
+    New,
+    /// Someone is on it
+    Acknowledged,
+    /// A real incident that has been dealt with
+    Resolved,
+    /// Harmless after all
+    FalsePositive,
+}
+
+impl TriageState {
+    pub const ALL: [TriageState; 4] = [
+        TriageState::New,
+        TriageState::Acknowledged,
+        TriageState::Resolved,
+        TriageState::FalsePositive,
+    ];
+
+    /// Name used on the command line and in the history database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriageState::New => "new",
+            TriageState::Acknowledged => "acknowledged",
+            TriageState::Resolved => "resolved",
+            TriageState::FalsePositive => "false_positive",
+        }
+    }
+}
+
+impl fmt::Display for TriageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for TriageState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| format!("unknown triage state {:?}", s))
+    }
+}
+
+/// One outbound connection attributed to an anomalous process
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutboundConnection {
+    pub timestamp: u64,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// One open of a watched sensitive path attributed to an anomalous process
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileAccess {
+    pub timestamp: u64,
+    pub path: String,
+    pub write: bool,
+}
+
+/// One entry of the daemon's process table
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub uid: u32,
+    pub comm: String,
+    /// Last executable the process exec'd, empty if we only saw it fork
+    pub exe: String,
+    pub started_at: u64,
+    pub exited_at: Option<u64>,
+    /// Status passed to `exit()`, for processes that exited normally
+    pub exit_status: Option<i32>,
+    /// Signal that killed the process
+    pub exit_signal: Option<i32>,
+}
+
+/// A process and everything it spawned
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessTreeNode {
+    pub process: ProcessInfo,
+    pub children: Vec<ProcessTreeNode>,
 }
 
 /// All possible commands the CLI can send to the Daemon
@@ -16,10 +260,102 @@ pub struct AnomalyReport {
 pub enum DaemonRequest {
     /// Ping the daemon to check health
     Status,
-    /// Fetch the most recent anomalies
-    GetAnomalies { tail: usize },
+    /// Fetch the most recent anomalies matching `query` from the anomaly history
+    GetAnomalies { tail: usize, query: AnomalyQuery },
     /// Ask the LLM to analyze a specific anomaly
     Investigate { id: String },
+    /// Keep the connection open and push every new anomaly matching the filter
+    Subscribe { filter: AnomalyFilter },
+    /// Fetch the ancestry and descendants of a process
+    GetProcessTree { pid: u32 },
+    /// List the active in-kernel exec filters
+    ListFilters,
+    /// Start dropping matching execs in the kernel
+    AddFilter { rule: FilterRule },
+    /// Stop dropping matching execs
+    RemoveFilter { rule: FilterRule },
+    /// Record how far an operator got with an anomaly
+    SetTriage { id: String, triage: TriageState },
+    /// Show the engine's mode
+    GetMode,
+    /// Switch the engine's mode. `learning_secs` bounds a switch to learning; `None` uses the configured period.
+    SetMode { mode: EngineMode, learning_secs: Option<u64> },
+    /// Accept the command of an anomaly into the baseline
+    Approve { id: String },
+}
+
+/// An in-kernel exec filter. Matching execs are dropped before they reach the ring buffer.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum FilterRule {
+    /// A trusted program, compared against the last component of the exec'd path cut to 15 bytes,
+    /// i.e. the comm it runs under
+    Comm(String),
+    /// Every exec by this uid
+    Uid(u32),
+    /// Every exec inside this cgroup v2 path, relative to `/sys/fs/cgroup` (e.g. `/system.slice/backup.service`)
+    Cgroup(String),
+}
+
+impl fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterRule::Comm(comm) => write!(f, "comm {}", comm),
+            FilterRule::Uid(uid) => write!(f, "uid {}", uid),
+            FilterRule::Cgroup(path) => write!(f, "cgroup {}", path),
+        }
+    }
+}
+
+/// Ring buffer totals of one kernel probe since the sensor was loaded
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProgramStats {
+    pub program: String,
+    /// Records written to the ring buffer
+    pub sent: u64,
+    /// Records thrown away because the ring buffer was full
+    pub lost: u64,
+}
+
+/// How many execs each kind of in-kernel filter dropped since the daemon started
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FilterDrops {
+    pub comm: u64,
+    pub uid: u64,
+    pub cgroup: u64,
+}
+
+/// Optional filters for a live anomaly subscription. Unset fields match everything.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnomalyFilter {
+    /// Only anomalies whose command contains this substring
+    pub command: Option<String>,
+    /// Only anomalies raised by this PID
+    pub pid: Option<u32>,
+    /// Only anomalies at or below this score (`AnomalyReport::final_score`)
+    pub max_score: Option<f32>,
+}
+
+impl AnomalyFilter {
+    pub fn matches(&self, anomaly: &AnomalyReport) -> bool {
+        self.command.as_ref().is_none_or(|c| anomaly.command.contains(c.as_str()))
+            && self.pid.is_none_or(|pid| anomaly.pid == pid)
+            && self.max_score.is_none_or(|max| anomaly.final_score() <= max)
+    }
+}
+
+/// Filters for a history lookup. Unset fields match everything.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnomalyQuery {
+    /// Only anomalies detected at or after this Unix timestamp
+    pub since: Option<u64>,
+    /// Only anomalies detected at or before this Unix timestamp
+    pub until: Option<u64>,
+    /// Only anomalies of exactly this command
+    pub command: Option<String>,
+    /// Only anomalies at or above this score (`AnomalyReport::final_score`)
+    pub min_score: Option<f32>,
+    /// Only anomalies at or below this score (`AnomalyReport::final_score`)
+    pub max_score: Option<f32>,
 }
 
 /// All possible responses the Daemon can send back to the CLI
@@ -29,12 +365,124 @@ pub enum DaemonResponse {
     StatusOk { 
         uptime_seconds: u64, 
         events_processed: u64,
+        /// Events processed during the last full second
+        events_per_second: u64,
+        /// Highest one-second event rate since the daemon started
+        peak_events_per_second: u64,
+        /// Execs dropped in the kernel by the exec filters
+        filter_drops: FilterDrops,
+        /// Ring buffer totals per probe, as of the last periodic read
+        sensor: Vec<ProgramStats>,
+        anomalies_detected: u64,
         db_size: usize 
     },
     /// Returning a list of anomalies
     AnomaliesList(Vec<AnomalyReport>),
     /// Returning the LLM's plain-English analysis
     InvestigationResult(String),
+    /// The ancestors (oldest first) and the subtree of the requested process
+    ProcessTree {
+        ancestry: Vec<ProcessInfo>,
+        tree: ProcessTreeNode,
+    },
+    /// The exec filters now in effect
+    Filters(Vec<FilterRule>),
+    /// The triage state of an anomaly was updated
+    Triaged { id: String, triage: TriageState },
+    /// The engine's current mode
+    Mode(ModeStatus),
+    /// The command of an anomaly is now part of the baseline
+    Approved { id: String, command: String },
+    /// The subscription is live; `Anomaly` frames follow until the connection closes
+    Subscribed,
+    /// A new anomaly pushed to a subscriber
+    Anomaly(Box<AnomalyReport>),
+    /// The subscriber fell behind and this many anomalies were skipped
+    SubscriptionLagged { missed: u64 },
     /// Something went wrong on the daemon side
     Error(String),
-}
\ No newline at end of file
+}
+
+/// Version of the framed IPC protocol spoken over the Unix socket.
+/// Bump this whenever `DaemonRequest` or `DaemonResponse` change shape.
+pub const PROTOCOL_VERSION: u32 = 17;
+
+/// First frame sent by the CLI on every new connection.
+/// This struct must never change shape, so any two versions can always negotiate.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    pub client_version: String,
+}
+
+/// The daemon's answer to a `ClientHello`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerHello {
+    /// Versions match, the connection is ready for requests
+    Accepted { protocol_version: u32, daemon_version: String },
+    /// Versions differ, the daemon closes the connection after sending this
+    Rejected { protocol_version: u32, reason: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anomaly(pid: u32, command: &str, similarity_score: f32) -> AnomalyReport {
+        AnomalyReport {
+            id: "a".to_string(),
+            timestamp: 0,
+            pid,
+            uid: 0,
+            command: command.to_string(),
+            exe: String::new(),
+            argv: Vec::new(),
+            context: Default::default(),
+            connections: Vec::new(),
+            file_accesses: Vec::new(),
+            ancestry: Vec::new(),
+            fidelity: Default::default(),
+            similarity_score,
+            score: None,
+            nearest: None,
+            scope: None,
+            raw_log: String::new(),
+            triage: TriageState::New,
+            investigation: None,
+        }
+    }
+
+    #[test]
+    fn an_empty_filter_matches_everything() {
+        assert!(AnomalyFilter::default().matches(&anomaly(1, "nc", 0.9)));
+    }
+
+    #[test]
+    fn every_set_field_has_to_match() {
+        let filter = AnomalyFilter { command: Some("curl".to_string()), pid: Some(42), max_score: Some(0.5) };
+
+        assert!(filter.matches(&anomaly(42, "/usr/bin/curl -s", 0.5)));
+        assert!(!filter.matches(&anomaly(43, "curl", 0.1)));
+        assert!(!filter.matches(&anomaly(42, "wget", 0.1)));
+        assert!(!filter.matches(&anomaly(42, "curl", 0.6)));
+    }
+
+    #[test]
+    fn max_score_compares_against_the_composite_score() {
+        let filter = AnomalyFilter { max_score: Some(0.3), ..Default::default() };
+        let mut report = anomaly(1, "nc", 0.9);
+        report.score = Some(ScoreBreakdown {
+            executable: String::new(),
+            total: 0.2,
+            similarity: 0.9,
+            frequency: -0.4,
+            recency: -0.2,
+            uid_spread: -0.1,
+            sightings: 0,
+            last_seen: None,
+            distinct_uids: 0,
+        });
+
+        assert!(filter.matches(&report));
+    }
+}
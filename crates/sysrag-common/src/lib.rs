@@ -1 +1,3 @@
+pub mod events;
+pub mod frame;
 pub mod ipc;
\ No newline at end of file
@@ -0,0 +1,101 @@
+//! Compares the exact linear scan with the HNSW index at growing baseline sizes.
+//!
+//! ```text
+//! cargo bench -p sysrag-daemon --bench baseline_search                  # 10k, 100k and 1M entries
+//! cargo bench -p sysrag-daemon --bench baseline_search -- 10000 50000   # custom sizes
+//! ```
+//!
+//! Vectors are synthetic (random points scattered around a few thousand centres, the way similar
+//! commands cluster) with the dimension of the daemon's embedding model, so no model is needed.
+//! Recall is the share of queries for which HNSW returned the same best match as the linear scan.
+
+#[allow(dead_code)]
+#[path = "../src/rag/index.rs"]
+mod index;
+
+use index::{FlatIndex, Hnsw, HnswParams};
+use std::time::{Duration, Instant};
+
+/// Dimension of all-MiniLM-L6-v2
+const DIMENSION: usize = 384;
+const CLUSTERS: usize = 2000;
+const QUERIES: usize = 200;
+const DEFAULT_SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+const EF_SEARCH: [usize; 3] = [16, 64, 256];
+
+fn main() {
+    // `cargo bench` passes flags such as --bench along with our own arguments
+    let sizes: Vec<usize> = std::env::args().skip(1).filter_map(|arg| arg.parse().ok()).collect();
+    let sizes = if sizes.is_empty() { DEFAULT_SIZES.to_vec() } else { sizes };
+
+    let mut rng = Rng(0x5EED);
+    let centres: Vec<Vec<f32>> = (0..CLUSTERS).map(|_| rng.vector(1.0)).collect();
+    let point = |rng: &mut Rng| {
+        let centre = &centres[rng.next() as usize % CLUSTERS];
+        let noise = rng.vector(0.35);
+        centre.iter().zip(noise).map(|(c, n)| c + n).collect::<Vec<f32>>()
+    };
+
+    println!("{:>9}  {:<22} {:>12} {:>14} {:>8}", "entries", "index", "build", "per lookup", "recall");
+    for size in sizes {
+        let vectors: Vec<Vec<f32>> = (0..size).map(|_| point(&mut rng)).collect();
+        let queries: Vec<Vec<f32>> = (0..QUERIES).map(|_| point(&mut rng)).collect();
+
+        let started = Instant::now();
+        let mut flat = FlatIndex::new(DIMENSION);
+        for vector in &vectors {
+            flat.insert(vector);
+        }
+        let flat_build = started.elapsed();
+
+        let started = Instant::now();
+        let exact: Vec<u32> = queries.iter().map(|q| flat.search(q, 1)[0].id).collect();
+        let flat_lookup = started.elapsed() / QUERIES as u32;
+        print_row(size, "linear", flat_build, flat_lookup, 1.0);
+        drop(flat);
+
+        let params = HnswParams { m: 16, ef_construction: 200, ef_search: EF_SEARCH[0] };
+        let started = Instant::now();
+        let mut hnsw = Hnsw::new(DIMENSION, params);
+        for vector in &vectors {
+            hnsw.insert(vector);
+        }
+        let hnsw_build = started.elapsed();
+
+        for ef_search in EF_SEARCH {
+            let started = Instant::now();
+            let found: Vec<u32> = queries.iter().map(|q| hnsw.search_with_ef(q, 1, ef_search)[0].id).collect();
+            let lookup = started.elapsed() / QUERIES as u32;
+
+            let hits = found.iter().zip(&exact).filter(|(a, b)| a == b).count();
+            let name = format!("hnsw (ef_search {})", ef_search);
+            print_row(size, &name, hnsw_build, lookup, hits as f64 / QUERIES as f64);
+        }
+    }
+}
+
+fn print_row(size: usize, index: &str, build: Duration, lookup: Duration, recall: f64) {
+    println!(
+        "{:>9}  {:<22} {:>11.2}s {:>12.1}µs {:>7.1}%",
+        size, index, build.as_secs_f64(), lookup.as_secs_f64() * 1e6, recall * 100.0
+    );
+}
+
+/// xorshift64*, so runs are repeatable without a rand dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in [-scale, scale) in every dimension
+    fn vector(&mut self, scale: f32) -> Vec<f32> {
+        (0..DIMENSION)
+            .map(|_| ((self.next() >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0) * scale)
+            .collect()
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Remembers the last few timestamped items (connections, file accesses, ...) of every process,
+/// so anomaly reports can show what a flagged process did after it was detected.
+pub struct ActivityTracker<T> {
+    per_process: usize,
+    max_processes: usize,
+    by_pid: HashMap<u32, TrackedProcess<T>>,
+}
+
+struct TrackedProcess<T> {
+    last_seen: u64,
+    items: VecDeque<(u64, T)>,
+}
+
+impl<T: Clone> ActivityTracker<T> {
+    pub fn new(per_process: usize, max_processes: usize) -> Self {
+        Self {
+            per_process,
+            max_processes,
+            by_pid: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, pid: u32, timestamp: u64, item: T) {
+        // Forget the quietest process if we are tracking too many
+        if !self.by_pid.contains_key(&pid) && self.by_pid.len() >= self.max_processes {
+            if let Some(oldest) = self
+                .by_pid
+                .iter()
+                .min_by_key(|(_, process)| process.last_seen)
+                .map(|(pid, _)| *pid)
+            {
+                self.by_pid.remove(&oldest);
+            }
+        }
+
+        let process = self.by_pid.entry(pid).or_insert_with(|| TrackedProcess {
+            last_seen: timestamp,
+            items: VecDeque::new(),
+        });
+
+        if process.items.len() >= self.per_process {
+            process.items.pop_front();
+        }
+        process.last_seen = timestamp;
+        process.items.push_back((timestamp, item));
+    }
+
+    /// Items `pid` produced at or after `since` (a Unix timestamp), oldest first
+    pub fn since(&self, pid: u32, since: u64) -> Vec<T> {
+        self.by_pid
+            .get(&pid)
+            .map(|process| {
+                process
+                    .items
+                    .iter()
+                    .filter(|(timestamp, _)| *timestamp >= since)
+                    .map(|(_, item)| item.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_items_per_process() {
+        let mut tracker = ActivityTracker::new(2, 8);
+        for (timestamp, item) in [(1, "a"), (2, "b"), (3, "c")] {
+            tracker.record(100, timestamp, item);
+        }
+
+        assert_eq!(tracker.since(100, 0), ["b", "c"]);
+        assert_eq!(tracker.since(100, 3), ["c"]);
+        assert!(tracker.since(200, 0).is_empty());
+    }
+
+    #[test]
+    fn the_quietest_process_is_forgotten_first() {
+        let mut tracker = ActivityTracker::new(4, 2);
+        tracker.record(100, 1, "old");
+        tracker.record(200, 2, "newer");
+        tracker.record(100, 3, "busy again");
+        tracker.record(300, 4, "new");
+
+        assert!(tracker.since(200, 0).is_empty());
+        assert_eq!(tracker.since(100, 0), ["old", "busy again"]);
+        assert_eq!(tracker.since(300, 0), ["new"]);
+    }
+}
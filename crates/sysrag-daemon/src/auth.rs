@@ -0,0 +1,244 @@
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use sysrag_common::ipc::DaemonRequest;
+use tokio::net::UnixStream;
+
+use crate::config::AccessConfig;
+
+/// What a caller on the control socket is allowed to do. Each role includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read-only access to status and anomalies
+    Viewer,
+    /// Can additionally spend LLM time on investigations and triage anomalies
+    Analyst,
+    /// Can additionally change how the daemon behaves
+    Responder,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+            Role::Responder => "responder",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Who is on the other end of a `UnixStream`, as reported by the kernel via `SO_PEERCRED` and `SO_PEERGROUPS`
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+    /// Primary and supplementary groups
+    pub groups: Vec<u32>,
+}
+
+impl Caller {
+    /// Asks the kernel who connected. Uid, gid and groups are all captured at connect time,
+    /// so a caller can't change them between connecting and being authorized.
+    pub fn from_stream(stream: &UnixStream) -> io::Result<Self> {
+        let cred = stream.peer_cred()?;
+        let mut groups = vec![cred.gid()];
+        for gid in peer_groups(stream)? {
+            if !groups.contains(&gid) {
+                groups.push(gid);
+            }
+        }
+
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+            groups,
+        })
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid={} gid={}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid={}", pid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps callers to roles using the `[access]` section of the config
+pub struct AccessPolicy {
+    config: AccessConfig,
+}
+
+impl AccessPolicy {
+    pub fn new(config: AccessConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns the strongest role granted to the caller, or `None` if it may not connect at all.
+    /// root and the daemon's own user are always responders.
+    pub fn role_for(&self, caller: &Caller) -> Option<Role> {
+        if caller.uid == 0 || caller.uid == daemon_uid() {
+            return Some(Role::Responder);
+        }
+
+        self.config
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.uids.contains(&caller.uid)
+                    || rule.gids.iter().any(|gid| caller.groups.contains(gid))
+            })
+            .map(|rule| rule.role)
+            .chain(self.config.default_role)
+            .max()
+    }
+}
+
+/// The minimum role needed to send each request
+pub fn required_role(req: &DaemonRequest) -> Role {
+    match req {
+        DaemonRequest::Status => Role::Viewer,
+        DaemonRequest::GetAnomalies { .. } => Role::Viewer,
+        DaemonRequest::Subscribe { .. } => Role::Viewer,
+        DaemonRequest::GetProcessTree { .. } => Role::Viewer,
+        DaemonRequest::ListFilters => Role::Viewer,
+        DaemonRequest::GetMode => Role::Viewer,
+        DaemonRequest::Investigate { .. } => Role::Analyst,
+        DaemonRequest::SetTriage { .. } => Role::Analyst,
+        DaemonRequest::AddFilter { .. } => Role::Responder,
+        DaemonRequest::RemoveFilter { .. } => Role::Responder,
+        DaemonRequest::SetMode { .. } => Role::Responder,
+        DaemonRequest::Approve { .. } => Role::Responder,
+    }
+}
+
+/// The caller's supplementary groups via `SO_PEERGROUPS` (Linux 4.13+)
+fn peer_groups(stream: &UnixStream) -> io::Result<Vec<u32>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut len = (groups.len() * mem::size_of::<libc::gid_t>()) as libc::socklen_t;
+        // SAFETY: the kernel writes at most `len` bytes into `groups` and reports how many it wrote
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            groups.truncate(len as usize / mem::size_of::<libc::gid_t>());
+            return Ok(groups);
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            // Too small: `len` now holds the size the kernel needs
+            Some(libc::ERANGE) if len as usize > groups.len() * mem::size_of::<libc::gid_t>() => {
+                groups.resize(len as usize / mem::size_of::<libc::gid_t>(), 0);
+            }
+            // Older kernels only know the primary group
+            Some(libc::ENOPROTOOPT) => return Ok(Vec::new()),
+            _ => return Err(err),
+        }
+    }
+}
+
+fn daemon_uid() -> u32 {
+    // SAFETY: geteuid can't fail and touches no memory of ours
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::AccessRule;
+    use sysrag_common::ipc::{AnomalyFilter, AnomalyQuery, EngineMode, FilterRule, TriageState};
+
+    /// Neither root nor the user running the tests
+    const STRANGER: u32 = 4242;
+
+    fn caller(uid: u32, groups: &[u32]) -> Caller {
+        Caller { uid, gid: groups[0], pid: None, groups: groups.to_vec() }
+    }
+
+    #[test]
+    fn roles_are_ordered_by_what_they_allow() {
+        assert!(Role::Viewer < Role::Analyst);
+        assert!(Role::Analyst < Role::Responder);
+    }
+
+    #[test]
+    fn required_role_matches_the_documented_table() {
+        let id = || "a1b2".to_string();
+        let rule = || FilterRule::Uid(STRANGER);
+        let table = [
+            (DaemonRequest::Status, Role::Viewer),
+            (DaemonRequest::GetAnomalies { tail: 10, query: AnomalyQuery::default() }, Role::Viewer),
+            (DaemonRequest::Subscribe { filter: AnomalyFilter::default() }, Role::Viewer),
+            (DaemonRequest::GetProcessTree { pid: 1 }, Role::Viewer),
+            (DaemonRequest::ListFilters, Role::Viewer),
+            (DaemonRequest::GetMode, Role::Viewer),
+            (DaemonRequest::Investigate { id: id() }, Role::Analyst),
+            (DaemonRequest::SetTriage { id: id(), triage: TriageState::Resolved }, Role::Analyst),
+            (DaemonRequest::AddFilter { rule: rule() }, Role::Responder),
+            (DaemonRequest::RemoveFilter { rule: rule() }, Role::Responder),
+            (DaemonRequest::SetMode { mode: EngineMode::Learning, learning_secs: None }, Role::Responder),
+            (DaemonRequest::Approve { id: id() }, Role::Responder),
+        ];
+        for (request, role) in table {
+            assert_eq!(required_role(&request), role, "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn role_for_takes_the_strongest_matching_rule() {
+        let policy = AccessPolicy::new(AccessConfig {
+            default_role: None,
+            rules: vec![
+                AccessRule { uids: Vec::new(), gids: vec![4], role: Role::Viewer },
+                AccessRule { uids: vec![STRANGER], gids: Vec::new(), role: Role::Analyst },
+            ],
+        });
+
+        assert_eq!(policy.role_for(&caller(STRANGER, &[100, 4])), Some(Role::Analyst));
+        // A supplementary group is enough
+        assert_eq!(policy.role_for(&caller(STRANGER + 1, &[100, 4])), Some(Role::Viewer));
+        assert_eq!(policy.role_for(&caller(STRANGER + 1, &[100])), None);
+        assert_eq!(policy.role_for(&caller(0, &[0])), Some(Role::Responder));
+        assert_eq!(policy.role_for(&caller(daemon_uid(), &[100])), Some(Role::Responder));
+    }
+
+    #[test]
+    fn default_role_applies_to_callers_matching_no_rule() {
+        let policy = AccessPolicy::new(AccessConfig { default_role: Some(Role::Viewer), rules: Vec::new() });
+        assert_eq!(policy.role_for(&caller(STRANGER, &[100])), Some(Role::Viewer));
+    }
+
+    #[tokio::test]
+    async fn caller_groups_come_from_the_socket() {
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let caller = Caller::from_stream(&ours).unwrap();
+
+        // SAFETY: getgroups only fills the buffer it is given
+        let mut expected: Vec<libc::gid_t> = vec![0; 256];
+        let count = unsafe { libc::getgroups(expected.len() as libc::c_int, expected.as_mut_ptr()) };
+        expected.truncate(count.max(0) as usize);
+
+        assert_eq!(caller.uid, unsafe { libc::getuid() });
+        assert_eq!(caller.groups[0], unsafe { libc::getgid() });
+        for gid in expected {
+            assert!(caller.groups.contains(&gid), "missing group {}", gid);
+        }
+    }
+}
@@ -1,83 +1,516 @@
 use anyhow::{Context, Result};
 use aya::{
+    maps::lpm_trie::{Key, LpmTrie},
     maps::RingBuf,
-    programs::TracePoint,
+    programs::{RawTracePoint, TracePoint},
     Bpf,
+    Pod,
     include_bytes_aligned,
 };
-use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use sysrag_common::events::{
+    ConnectEvent, ExecEvent, ExitEvent, FileOpenEvent, Fidelity, ForkEvent, KernelEvent,
+    ProcessContext,
+};
+use sysrag_common::ipc::FilterRule;
+use tokio::io::unix::AsyncFd;
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+
+use crate::counters::SensorCounters;
+use crate::files::{self, WatchedPath, MAX_PATH_LEN, MAX_SUFFIX_LEN};
+use crate::filters::KernelFilters;
+use crate::sources::{EventSource, SourceFuture};
+
+// Record tags, these MUST match the `EVENT_KIND_*` defines in `sysrag.bpf.c`
+const EVENT_KIND_EXEC: u32 = 1;
+const EVENT_KIND_CONNECT: u32 = 2;
+const EVENT_KIND_OPEN: u32 = 3;
+const EVENT_KIND_FORK: u32 = 4;
+const EVENT_KIND_EXIT: u32 = 5;
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+// These MUST match the limits in `sysrag.bpf.c`
+const TTY_NAME_LEN: usize = 64;
+const MAX_FILENAME_LEN: usize = 256;
+const MAX_ARG_LEN: usize = 128;
+const MAX_ARGS: usize = 20;
+const ARGS_BUF_SIZE: usize = MAX_ARG_LEN * MAX_ARGS;
 
 /// This struct MUST match the exact memory layout of the C struct in `sysrag.bpf.c`
+/// up to (not including) its `args` field. The record is variable-size: `args_size`
+/// bytes of NUL-separated argv follow it in the ring buffer.
 #[repr(C)]
 struct ProcessEvent {
+    kind: u32,
+    pid: u32,
+    uid: u32,
+    ppid: u32,
+    gid: u32,
+    loginuid: u32,
+    mnt_ns: u32,
+    pid_ns: u32,
+    cgroup_id: u64,
+    comm: [u8; 16],
+    tty: [u8; TTY_NAME_LEN],
+    filename: [u8; MAX_FILENAME_LEN],
+    argc: u32,
+    args_size: u32,
+    args_truncated: u8,
+    _pad: [u8; 7],
+}
+
+/// The kernel's "no login uid" marker (AUDIT_UID_UNSET)
+const LOGINUID_UNSET: u32 = u32::MAX;
+
+impl ProcessEvent {
+    /// Parses one variable-size record from the ring buffer
+    fn parse(record: &[u8]) -> Option<ExecEvent> {
+        let header_len = std::mem::size_of::<ProcessEvent>();
+        if record.len() < header_len {
+            return None;
+        }
+
+        // Safely read the raw bytes from kernel memory into our Rust struct
+        let header = unsafe { std::ptr::read_unaligned(record.as_ptr() as *const ProcessEvent) };
+
+        let args_size = (header.args_size as usize).min(ARGS_BUF_SIZE);
+        let args = record.get(header_len..header_len + args_size)?;
+        let argv = args
+            .split(|b| *b == 0)
+            .take(header.argc as usize)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+
+        Some(ExecEvent {
+            pid: header.pid,
+            uid: header.uid,
+            comm: c_string(&header.comm),
+            context: ProcessContext {
+                ppid: header.ppid,
+                gid: header.gid,
+                loginuid: (header.loginuid != LOGINUID_UNSET).then_some(header.loginuid),
+                cgroup_id: header.cgroup_id,
+                mnt_ns: header.mnt_ns,
+                pid_ns: header.pid_ns,
+                tty: Some(c_string(&header.tty)).filter(|tty| !tty.is_empty()),
+            },
+            filename: c_string(&header.filename),
+            argv,
+            argv_truncated: header.args_truncated != 0,
+            fidelity: Fidelity::Exact,
+        })
+    }
+}
+
+/// This struct MUST match the exact memory layout of `struct connect_event` in `sysrag.bpf.c`
+#[repr(C)]
+struct ConnectRecord {
+    kind: u32,
+    pid: u32,
+    uid: u32,
+    family: u16,
+    port_be: u16,
+    addr: [u8; 16],
+    comm: [u8; 16],
+}
+
+impl ConnectRecord {
+    fn parse(record: &[u8]) -> Option<ConnectEvent> {
+        if record.len() < std::mem::size_of::<ConnectRecord>() {
+            return None;
+        }
+        let raw = unsafe { std::ptr::read_unaligned(record.as_ptr() as *const ConnectRecord) };
+
+        let address = match raw.family {
+            AF_INET => IpAddr::V4(Ipv4Addr::new(raw.addr[0], raw.addr[1], raw.addr[2], raw.addr[3])),
+            AF_INET6 => IpAddr::V6(Ipv6Addr::from(raw.addr)),
+            _ => return None,
+        };
+
+        Some(ConnectEvent {
+            pid: raw.pid,
+            uid: raw.uid,
+            comm: c_string(&raw.comm),
+            address,
+            port: u16::from_be(raw.port_be),
+        })
+    }
+}
+
+/// This struct MUST match the exact memory layout of `struct open_event` in `sysrag.bpf.c`
+#[repr(C)]
+struct OpenRecord {
+    kind: u32,
     pid: u32,
     uid: u32,
+    flags: u32,
+    comm: [u8; 16],
+    path: [u8; MAX_PATH_LEN],
+}
+
+impl OpenRecord {
+    fn parse(record: &[u8]) -> Option<FileOpenEvent> {
+        if record.len() < std::mem::size_of::<OpenRecord>() {
+            return None;
+        }
+        let raw = unsafe { std::ptr::read_unaligned(record.as_ptr() as *const OpenRecord) };
+
+        Some(FileOpenEvent {
+            pid: raw.pid,
+            uid: raw.uid,
+            comm: c_string(&raw.comm),
+            path: c_string(&raw.path),
+            flags: raw.flags,
+        })
+    }
+}
+
+/// This struct MUST match the exact memory layout of `struct fork_event` in `sysrag.bpf.c`
+#[repr(C)]
+struct ForkRecord {
+    kind: u32,
+    parent_pid: u32,
+    child_pid: u32,
+    uid: u32,
+    comm: [u8; 16],
+}
+
+impl ForkRecord {
+    fn parse(record: &[u8]) -> Option<ForkEvent> {
+        if record.len() < std::mem::size_of::<ForkRecord>() {
+            return None;
+        }
+        let raw = unsafe { std::ptr::read_unaligned(record.as_ptr() as *const ForkRecord) };
+
+        Some(ForkEvent {
+            parent_pid: raw.parent_pid,
+            child_pid: raw.child_pid,
+            uid: raw.uid,
+            comm: c_string(&raw.comm),
+        })
+    }
+}
+
+/// This struct MUST match the exact memory layout of `struct exit_event` in `sysrag.bpf.c`
+#[repr(C)]
+struct ExitRecord {
+    kind: u32,
+    pid: u32,
+    exit_code: u32,
+    _pad: u32,
     comm: [u8; 16],
 }
 
+impl ExitRecord {
+    fn parse(record: &[u8]) -> Option<ExitEvent> {
+        if record.len() < std::mem::size_of::<ExitRecord>() {
+            return None;
+        }
+        let raw = unsafe { std::ptr::read_unaligned(record.as_ptr() as *const ExitRecord) };
+
+        Some(ExitEvent {
+            pid: raw.pid,
+            comm: c_string(&raw.comm),
+            exit_code: raw.exit_code,
+            fidelity: Fidelity::Exact,
+        })
+    }
+}
+
+/// Value of the `watched_prefixes` map, MUST match `struct prefix_rule` in `sysrag.bpf.c`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PrefixRule {
+    suffix_len: u32,
+    suffix: [u8; MAX_SUFFIX_LEN],
+}
+
+unsafe impl Pod for PrefixRule {}
+
+/// Decodes one ring buffer record by its leading kind tag
+fn parse_record(record: &[u8]) -> Option<KernelEvent> {
+    let kind = u32::from_ne_bytes(record.get(..4)?.try_into().ok()?);
+    match kind {
+        EVENT_KIND_EXEC => ProcessEvent::parse(record).map(KernelEvent::Exec),
+        EVENT_KIND_CONNECT => ConnectRecord::parse(record).map(KernelEvent::Connect),
+        EVENT_KIND_OPEN => OpenRecord::parse(record).map(KernelEvent::FileOpen),
+        EVENT_KIND_FORK => ForkRecord::parse(record).map(KernelEvent::Fork),
+        EVENT_KIND_EXIT => ExitRecord::parse(record).map(KernelEvent::Exit),
+        _ => None,
+    }
+}
+
+/// Converts a C string (null-terminated byte array) to a Rust String
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
 /// Manages the lifecycle of the eBPF program to ensure it stays loaded in the kernel
 pub struct BpfManager {
     bpf: Bpf,
+    watched_paths: Vec<WatchedPath>,
+}
+
+/// The maps user space keeps using once the probes are attached, split off from the listener task
+pub struct SensorMaps {
+    pub filters: KernelFilters,
+    pub counters: SensorCounters,
 }
 
 impl BpfManager {
-    /// Loads the compiled eBPF object file and injects it into the kernel
-    pub fn new() -> Result<Self> {
+    /// Loads the compiled eBPF object file and injects it into the kernel.
+    /// `watched_paths` and the exec filters are loaded into the kernel before the probes start firing.
+    /// The filter and counter maps are handed back so they can be used while the sensor runs.
+    pub fn new(watched_paths: Vec<WatchedPath>, filter_rules: Vec<FilterRule>) -> Result<(Self, SensorMaps)> {
         // Use Aya's aligned memory macro to safely embed the fresh bytecode
         let bpf_data = include_bytes_aligned!("../../../bpf/sysrag.bpf.o");
         let mut bpf = Bpf::load(bpf_data)
             .context("Failed to load embedded eBPF object bytes.")?;
 
-        // Find the 'trace_execve' program and attach it...
-        let program: &mut TracePoint = bpf
-            .program_mut("trace_execve")
-            .context("Failed to find 'trace_execve' in bytecode")?
-            .try_into()?;
-            
-        program.load()?;
-        program.attach("syscalls", "sys_enter_execve")
-            .context("Failed to attach to sys_enter_execve tracepoint")?;
+        // Tell the open probes which paths are sensitive
+        let mut prefixes: LpmTrie<_, [u8; MAX_PATH_LEN], PrefixRule> = LpmTrie::try_from(
+            bpf.map_mut("watched_prefixes").context("Failed to find 'watched_prefixes' map")?,
+        )?;
+        let rules = files::kernel_rules(&watched_paths);
+        for watched in &rules {
+            let mut path = [0u8; MAX_PATH_LEN];
+            path[..watched.prefix.len()].copy_from_slice(watched.prefix.as_bytes());
+            let mut rule = PrefixRule { suffix_len: watched.suffix.len() as u32, suffix: [0; MAX_SUFFIX_LEN] };
+            rule.suffix[..watched.suffix.len()].copy_from_slice(watched.suffix.as_bytes());
 
-        Ok(Self { bpf })
+            let key = Key::new(watched.prefix.len() as u32 * 8, path);
+            prefixes.insert(&key, rule, 0)
+                .with_context(|| format!("Failed to watch {}*{}", watched.prefix, watched.suffix))?;
+        }
+        println!("BPF Manager: Watching {} sensitive path prefixes", rules.len());
+
+        let mut filters = KernelFilters::from_bpf(&mut bpf)?;
+        for rule in filter_rules {
+            filters.add(rule.clone()).with_context(|| format!("Failed to add exec filter {}", rule))?;
+        }
+        println!("BPF Manager: Dropping execs matching {} in-kernel filters", filters.rules().len());
+
+        let counters = SensorCounters::from_bpf(&mut bpf)?;
+
+        // Find each program and attach it to its tracepoint...
+        attach_tracepoint(&mut bpf, "trace_execve", "syscalls", "sys_enter_execve")?;
+        attach_tracepoint(&mut bpf, "trace_connect", "syscalls", "sys_enter_connect")?;
+        attach_tracepoint(&mut bpf, "trace_openat", "syscalls", "sys_enter_openat")?;
+        // openat2 only exists since Linux 5.6; older kernels still have every other probe
+        if let Err(e) = attach_tracepoint(&mut bpf, "trace_openat2", "syscalls", "sys_enter_openat2") {
+            eprintln!("⚠️ BPF Manager: openat2 opens won't be seen ({:#})", e);
+        }
+        attach_raw_tracepoint(&mut bpf, "trace_fork", "sched_process_fork")?;
+        attach_raw_tracepoint(&mut bpf, "trace_exit", "sched_process_exit")?;
+
+        Ok((Self { bpf, watched_paths }, SensorMaps { filters, counters }))
     }
 
-    /// Starts an asynchronous loop to read the Ring Buffer and send logs to the RAG engine
-    pub async fn start_listening(&mut self, log_sender: mpsc::Sender<String>) -> Result<()> {
+    /// Starts an asynchronous loop to read the Ring Buffer and send typed events to the RAG engine.
+    /// The ring buffer fd is registered with epoll, so the task only wakes up when the kernel has submitted records.
+    pub async fn start_listening(&mut self, event_sender: mpsc::Sender<KernelEvent>) -> Result<()> {
         // Find the shared memory ring buffer established by the C code
         let map = self.bpf.map_mut("events").context("Failed to find 'events' map")?;
-        let mut ring_buf = RingBuf::try_from(map)?;
+        let ring_buf = RingBuf::try_from(map)?;
+        let mut ring_fd = AsyncFd::new(ring_buf).context("Failed to register the ring buffer with epoll")?;
 
         println!("BPF Manager: Successfully hooked into kernel. Listening for events...");
 
-        // Continuously poll the buffer without blocking the main thread
         loop {
+            let mut guard = ring_fd.readable_mut().await?;
+            let ring_buf = guard.get_inner_mut();
+
+            // Drain everything that is ready; epoll is edge-style here, so stopping early would lose the wakeup
             while let Some(item) = ring_buf.next() {
-                // Safely read the raw bytes from kernel memory into our Rust struct
-                let event = unsafe { std::ptr::read_unaligned(item.as_ptr() as *const ProcessEvent) };
-                
-                // Convert the C string (null-terminated byte array) to a Rust String
-                let comm_string = String::from_utf8_lossy(&event.comm)
-                    .trim_end_matches('\0')
-                    .to_string();
-
-                // Format the raw OS log
-                let raw_log = format!(
-                    "execve: pid={} uid={} comm={}", 
-                    event.pid, event.uid, comm_string
-                );
-
-                // Send the log over the channel to the RAG engine.
+                let Some(kernel_event) = parse_record(&item) else {
+                    eprintln!("BPF Manager: Dropping malformed {} byte record", item.len());
+                    continue;
+                };
+
+                // The kernel only matched the prefix of a possibly truncated path, so re-check the full pattern
+                if let KernelEvent::FileOpen(open) = &kernel_event {
+                    if !self.watched_paths.iter().any(|w| w.matches(&open.path)) {
+                        continue;
+                    }
+                }
+
+                // Send the event over the channel to the RAG engine.
                 // If the channel is closed (e.g., daemon shutting down), we exit cleanly.
-                if log_sender.send(raw_log).await.is_err() {
-                    eprintln!("BPF Manager: Log channel closed, stopping listener.");
+                if event_sender.send(kernel_event).await.is_err() {
+                    eprintln!("BPF Manager: Event channel closed, stopping listener.");
                     return Ok(());
                 }
             }
 
-            // Yield control back to the Tokio scheduler so other tasks (like IPC) can run
-            sleep(Duration::from_millis(50)).await;
+            // The buffer is empty, so sleep until the kernel signals new data
+            guard.clear_ready();
+        }
+    }
+}
+
+impl EventSource for BpfManager {
+    fn name(&self) -> &'static str {
+        "bpf"
+    }
+
+    fn run(mut self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture {
+        Box::pin(async move { self.start_listening(event_sender).await })
+    }
+}
+
+/// Loads the named program from the object and attaches it to `category:name`
+fn attach_tracepoint(bpf: &mut Bpf, program: &str, category: &str, name: &str) -> Result<()> {
+    let tracepoint: &mut TracePoint = bpf
+        .program_mut(program)
+        .with_context(|| format!("Failed to find '{}' in bytecode", program))?
+        .try_into()?;
+
+    tracepoint.load()?;
+    tracepoint
+        .attach(category, name)
+        .with_context(|| format!("Failed to attach to {} tracepoint", name))?;
+    Ok(())
+}
+
+/// Loads the named program from the object and attaches it to the raw tracepoint `name`
+fn attach_raw_tracepoint(bpf: &mut Bpf, program: &str, name: &str) -> Result<()> {
+    let tracepoint: &mut RawTracePoint = bpf
+        .program_mut(program)
+        .with_context(|| format!("Failed to find '{}' in bytecode", program))?
+        .try_into()?;
+
+    tracepoint.load()?;
+    tracepoint
+        .attach(name)
+        .with_context(|| format!("Failed to attach to {} raw tracepoint", name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The raw bytes of a record as the probe would write it
+    fn bytes_of<T>(record: &T) -> Vec<u8> {
+        // SAFETY: the record structs are plain integers and byte arrays without implicit padding
+        unsafe { std::slice::from_raw_parts(record as *const T as *const u8, std::mem::size_of::<T>()) }.to_vec()
+    }
+
+    fn c_array<const N: usize>(text: &str) -> [u8; N] {
+        let mut array = [0; N];
+        array[..text.len()].copy_from_slice(text.as_bytes());
+        array
+    }
+
+    fn exec_record(args: &[u8], argc: u32, args_truncated: u8) -> Vec<u8> {
+        with_args(&exec_header(args, argc, args_truncated), args)
+    }
+
+    fn exec_header(args: &[u8], argc: u32, args_truncated: u8) -> ProcessEvent {
+        ProcessEvent {
+            kind: EVENT_KIND_EXEC,
+            pid: 42,
+            uid: 1000,
+            ppid: 7,
+            gid: 1000,
+            loginuid: LOGINUID_UNSET,
+            mnt_ns: 1,
+            pid_ns: 2,
+            cgroup_id: 3,
+            comm: c_array("bash"),
+            tty: c_array(""),
+            filename: c_array("/usr/bin/nc"),
+            argc,
+            args_size: args.len() as u32,
+            args_truncated,
+            _pad: [0; 7],
         }
     }
-}
\ No newline at end of file
+
+    fn with_args(header: &ProcessEvent, args: &[u8]) -> Vec<u8> {
+        let mut record = bytes_of(header);
+        record.extend_from_slice(args);
+        record
+    }
+
+    #[test]
+    fn exec_records_carry_their_argv() {
+        let Some(KernelEvent::Exec(exec)) = parse_record(&exec_record(b"nc\0-l\0", 2, 0)) else {
+            panic!("Expected an exec");
+        };
+
+        assert_eq!((exec.pid, exec.context.ppid, exec.comm.as_str()), (42, 7, "bash"));
+        assert_eq!(exec.filename, "/usr/bin/nc");
+        assert_eq!(exec.argv, ["nc", "-l"]);
+        assert!(!exec.argv_truncated);
+        assert_eq!((exec.context.loginuid, exec.context.tty), (None, None));
+    }
+
+    #[test]
+    fn argv_stops_at_argc_and_keeps_the_truncation_flag() {
+        let Some(KernelEvent::Exec(exec)) = parse_record(&exec_record(b"sh\0-c\0junk", 2, 1)) else {
+            panic!("Expected an exec");
+        };
+
+        assert_eq!(exec.argv, ["sh", "-c"]);
+        assert!(exec.argv_truncated);
+    }
+
+    #[test]
+    fn short_records_are_dropped() {
+        let record = exec_record(b"nc\0-l\0", 2, 0);
+
+        // Cut inside the argv the header announces
+        assert!(parse_record(&record[..record.len() - 1]).is_none());
+        assert!(parse_record(&record[..16]).is_none());
+        assert!(parse_record(&[]).is_none());
+    }
+
+    fn connect_record(family: u16, addr: [u8; 16]) -> Vec<u8> {
+        bytes_of(&ConnectRecord {
+            kind: EVENT_KIND_CONNECT,
+            pid: 42,
+            uid: 0,
+            family,
+            port_be: 443u16.to_be(),
+            addr,
+            comm: c_array("curl"),
+        })
+    }
+
+    #[test]
+    fn connect_records_decode_both_address_families() {
+        let mut v4 = [0; 16];
+        v4[..4].copy_from_slice(&[10, 0, 0, 1]);
+        let v6 = Ipv6Addr::LOCALHOST.octets();
+
+        let dest = |record: Vec<u8>| match parse_record(&record) {
+            Some(KernelEvent::Connect(conn)) => Some(std::net::SocketAddr::new(conn.address, conn.port).to_string()),
+            _ => None,
+        };
+        assert_eq!(dest(connect_record(AF_INET, v4)).as_deref(), Some("10.0.0.1:443"));
+        assert_eq!(dest(connect_record(AF_INET6, v6)).as_deref(), Some("[::1]:443"));
+        // Unix sockets and the like are not outbound connections
+        assert_eq!(dest(connect_record(1, v4)), None);
+    }
+
+    #[test]
+    fn exec_records_carry_the_process_context() {
+        let header = ProcessEvent {
+            loginuid: 1000,
+            tty: c_array("pts0"),
+            ..exec_header(b"", 0, 0)
+        };
+        let Some(KernelEvent::Exec(exec)) = parse_record(&with_args(&header, b"")) else {
+            panic!("Expected an exec");
+        };
+
+        let context = exec.context;
+        assert_eq!((context.ppid, context.gid, context.loginuid), (7, 1000, Some(1000)));
+        assert_eq!((context.mnt_ns, context.pid_ns, context.cgroup_id), (1, 2, 3));
+        assert_eq!(context.tty.as_deref(), Some("pts0"));
+    }
+}
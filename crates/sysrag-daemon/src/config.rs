@@ -0,0 +1,869 @@
+//! Runtime configuration for `sysrag-daemon`.
+//!
+//! Every setting is resolved in this order, later sources winning:
+//!
+//! 1. Built-in defaults (the values in the `Default` impls below)
+//! 2. The TOML config file: the `--config` path if given, otherwise
+//!    `/etc/sysrag/sysragd.toml` if it exists. Keys missing from the file keep their default.
+//! 3. Command-line overrides such as `--socket` or `--threshold`
+//!
+//! The merged result is validated once, before anything is started.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use sysrag_common::ipc::EngineMode;
+
+use crate::auth::Role;
+use crate::{files, filters};
+
+/// Config file picked up automatically when `--config` is not given
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/sysrag/sysragd.toml";
+
+const DEFAULT_REPLAY_SPEED: f64 = 1.0;
+
+/// Where auditd writes its log by default
+const DEFAULT_AUDIT_LOG: &str = "/var/log/audit/audit.log";
+
+/// Command-line arguments of the daemon
+#[derive(Parser, Debug)]
+#[command(name = "sysrag-daemon")]
+#[command(about = "The sysragd background AI daemon", long_about = None)]
+pub struct DaemonArgs {
+    /// Path to a TOML config file (defaults to /etc/sysrag/sysragd.toml if present)
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Override `server.socket_path`
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+
+    /// Override `rag.anomaly_threshold`
+    #[arg(long)]
+    pub threshold: Option<f32>,
+
+    /// Override `llm.url`
+    #[arg(long)]
+    pub ollama_url: Option<String>,
+
+    /// Override `llm.model`
+    #[arg(long)]
+    pub ollama_model: Option<String>,
+
+    /// Write every kernel event to this file for later replay
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replace `sources` with a single replay of this recording, then exit when it is used up
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Replay speed: 1 keeps the original pacing, 10 is ten times faster, 0 is as fast as possible
+    #[arg(long, default_value_t = DEFAULT_REPLAY_SPEED, requires = "replay")]
+    pub replay_speed: f64,
+
+    /// Replace `sources` with a single read of this audit log, then exit at its end
+    #[arg(long, conflicts_with = "replay")]
+    pub audit_log: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub sources: Sources,
+    pub server: ServerConfig,
+    pub pipeline: PipelineConfig,
+    pub rag: RagConfig,
+    pub llm: LlmConfig,
+    pub access: AccessConfig,
+    pub network: NetworkConfig,
+    pub files: FilesConfig,
+    pub processes: ProcessesConfig,
+    pub filters: FiltersConfig,
+    pub history: HistoryConfig,
+}
+
+/// The event sources to run, as `[[sources]]` tables. Defaults to the BPF sensor alone.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Sources(pub Vec<SourceConfig>);
+
+/// One producer of kernel events, selected by its `kind`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SourceConfig {
+    /// The eBPF kernel sensor, needs root or CAP_BPF
+    Bpf,
+    /// A file written with `--record`
+    Replay {
+        path: PathBuf,
+        #[serde(default = "default_replay_speed")]
+        speed: f64,
+    },
+    /// Unprivileged `/proc` polling for hosts without BTF or CAP_BPF. Lower fidelity.
+    Proc(ProcPollConfig),
+    /// Records written by auditd, for hosts that already run audit rules
+    Audit(AuditConfig),
+    /// Synthetic execs for tests and CI
+    Generator(GeneratorConfig),
+}
+
+impl SourceConfig {
+    /// True for sources watching this host as it runs, false for replayed or invented events.
+    /// An audit log read once from the start is as old as a replay.
+    pub fn observes_host(&self) -> bool {
+        match self {
+            SourceConfig::Bpf | SourceConfig::Proc(_) => true,
+            SourceConfig::Audit(audit) => audit.follow,
+            SourceConfig::Replay { .. } | SourceConfig::Generator(_) => false,
+        }
+    }
+}
+
+/// Settings of a `kind = "proc"` source
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcPollConfig {
+    /// Time between two scans of /proc; processes living shorter than this can be missed
+    pub interval_ms: u64,
+}
+
+/// Settings of a `kind = "audit"` source
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Tail the log from its current end and follow rotations; false reads it once from the start
+    pub follow: bool,
+}
+
+/// Settings of a `kind = "generator"` source
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    /// Commands exec'd in turn
+    pub commands: Vec<String>,
+    pub events_per_second: u32,
+    /// Stop after this many events; unset runs forever
+    pub count: Option<u64>,
+    pub uid: u32,
+}
+
+/// The Unix socket the CLI talks to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub socket_path: PathBuf,
+}
+
+/// The queue between the kernel sensor and the RAG engine
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub channel_capacity: usize,
+}
+
+/// Anomaly detection settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RagConfig {
+    /// Anything whose score (its best similarity, discounted by `[rag.scoring]`) is below this is flagged
+    pub anomaly_threshold: f32,
+    /// Commands pre-loaded into the baseline so the engine doesn't freak out on boot.
+    /// Only used when there is no saved baseline to restore.
+    pub seed_commands: Vec<String>,
+    /// Keep the learned baseline across restarts instead of starting from the seeds every time
+    pub persist_baseline: bool,
+    pub baseline_path: PathBuf,
+    /// How often the baseline is written to `baseline_path`, besides on shutdown
+    pub checkpoint_interval_secs: u64,
+    /// How events are matched against the baseline, as `[rag.index]`
+    pub index: IndexConfig,
+    /// Learning and enforcement, as `[rag.mode]`
+    pub mode: ModeConfig,
+    /// Per-user, per-parent or per-cgroup baselines, as `[rag.scope]`
+    pub scope: ScopeConfig,
+    /// How much an unfamiliar executable lowers the score, as `[rag.scoring]`
+    pub scoring: ScoringConfig,
+}
+
+/// Signals besides similarity that go into the anomaly score. Each weight is the share of the
+/// similarity an executable loses when the signal is at its worst, so all zeros scores by
+/// similarity alone.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoringConfig {
+    /// How often the executable was seen before
+    pub frequency_weight: f32,
+    /// How long ago the executable was last seen
+    pub recency_weight: f32,
+    /// How many distinct uids ran the executable
+    pub uid_weight: f32,
+    /// Sightings after which an executable counts as fully familiar
+    pub familiar_after_sightings: u64,
+    /// Time since the last sighting after which the recency signal has halved
+    pub recency_half_life_secs: u64,
+    /// Distinct uids after which an executable counts as widely used
+    pub common_after_uids: u32,
+}
+
+/// Which baselines an exec is judged by, besides the global one
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScopeConfig {
+    /// What a scope is made of, combined in this order. Empty keeps the global baseline only.
+    pub by: Vec<ScopeField>,
+    /// Distinct commands a scope needs before it judges execs instead of the global baseline
+    pub min_entries: usize,
+    /// Scoped baselines kept at most; execs in further scopes are judged by the global baseline
+    pub max_scopes: usize,
+}
+
+/// One part of a scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScopeField {
+    /// The uid the exec ran as
+    Uid,
+    /// The command name of the exec's parent
+    Parent,
+    /// The cgroup (e.g. the service or container) the exec ran in. Audit records carry no
+    /// cgroup, so this can't be combined with a `kind = "audit"` source.
+    Cgroup,
+}
+
+impl ScopeField {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScopeField::Uid => "uid",
+            ScopeField::Parent => "parent",
+            ScopeField::Cgroup => "cgroup",
+        }
+    }
+}
+
+/// When anomalies are allowed into the baseline
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModeConfig {
+    /// Mode of a fresh baseline. A restored baseline keeps the mode it was saved in.
+    pub initial: EngineMode,
+    /// How long learning lasts before enforcement starts on its own, 0 = until an operator switches
+    pub learning_period_secs: u64,
+    /// In enforcement mode, an anomalous command seen this often within `promotion_window_secs`
+    /// joins the baseline. 0 = only operator approval promotes.
+    pub promote_after_sightings: u32,
+    pub promotion_window_secs: u64,
+}
+
+/// The search structure behind the baseline, selected by its `kind`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum IndexConfig {
+    /// Exact: every event is compared with every baseline vector, so it slows down as the baseline grows
+    Linear,
+    /// Approximate nearest neighbour graph that stays fast at millions of entries
+    Hnsw(HnswConfig),
+}
+
+/// Settings of `kind = "hnsw"`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HnswConfig {
+    /// Links per node; more improves recall at the cost of memory and insert time
+    pub m: usize,
+    /// Candidates weighed when inserting; more builds a better graph, more slowly
+    pub ef_construction: usize,
+    /// Candidates weighed per lookup: the recall/latency tradeoff. Can be changed without rebuilding.
+    pub ef_search: usize,
+}
+
+/// Where anomalies, their investigations and triage states are kept
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// SQLite database, created if missing
+    pub path: PathBuf,
+    /// Anomalies kept at most; the oldest go first
+    pub max_anomalies: u64,
+    /// Anomalies older than this are dropped, 0 = kept until `max_anomalies` pushes them out
+    pub max_age_secs: u64,
+}
+
+/// The local Ollama inference engine
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    pub url: String,
+    pub model: String,
+    pub timeout_secs: u64,
+}
+
+/// Outbound connection tracking
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// How many recent connections are kept for each process
+    pub connections_per_process: usize,
+    /// How many processes are tracked before the quietest one is forgotten
+    pub max_tracked_processes: usize,
+}
+
+/// Sensitive file access tracing
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    /// Path prefixes whose opens are reported, see `files::WatchedPath` for the syntax
+    pub watched_paths: Vec<String>,
+    /// How many recent accesses are kept for each process
+    pub accesses_per_process: usize,
+    /// How many processes are tracked before the quietest one is forgotten
+    pub max_tracked_processes: usize,
+}
+
+/// The fork/exec/exit process table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessesConfig {
+    /// How many exited processes are kept so ancestry of recent anomalies stays complete
+    pub max_exited: usize,
+}
+
+/// Execs dropped inside the kernel, before they cost a ring buffer record or an embedding.
+/// More can be added at runtime with `sysrag filter add`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiltersConfig {
+    /// Trusted programs by the name they run under: the last component of the exec'd path, at most
+    /// 15 bytes. Only execs of these are dropped, not what they run in turn.
+    pub allowed_comms: Vec<String>,
+    pub ignored_uids: Vec<u32>,
+    /// cgroup v2 paths relative to /sys/fs/cgroup, e.g. "/system.slice/backup.service"
+    pub ignored_cgroups: Vec<String>,
+}
+
+/// Who may use the control socket. Callers are identified by `SO_PEERCRED`;
+/// root and the daemon's own user are always responders.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Role for callers that match no rule. Unset means they are refused.
+    pub default_role: Option<Role>,
+    pub rules: Vec<AccessRule>,
+}
+
+/// Grants `role` to every listed uid and to every member of a listed gid
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    #[serde(default)]
+    pub uids: Vec<u32>,
+    #[serde(default)]
+    pub gids: Vec<u32>,
+    pub role: Role,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self(vec![SourceConfig::Bpf])
+    }
+}
+
+impl std::ops::Deref for Sources {
+    type Target = [SourceConfig];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn default_replay_speed() -> f64 {
+    DEFAULT_REPLAY_SPEED
+}
+
+impl Default for ProcPollConfig {
+    fn default() -> Self {
+        Self { interval_ms: 1000 }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(DEFAULT_AUDIT_LOG),
+            follow: true,
+        }
+    }
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        let commands = ["bash", "ls", "cat", "grep", "python3"];
+
+        Self {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            events_per_second: 10,
+            count: None,
+            uid: 1000,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            // Use /var/run/sysrag.sock in production
+            socket_path: PathBuf::from("/tmp/sysrag.sock"),
+        }
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self { channel_capacity: 1000 }
+    }
+}
+
+impl Default for RagConfig {
+    fn default() -> Self {
+        let seed_commands = [
+            "systemd", "crond", "NetworkManager", "bash", "sh",
+            "python3", "gnome-shell", "polkitd", "Xwayland", "env",
+            "spotify", "flatpak", "gio-launch-desktop", "(sd-worker)",
+            "run-parts", "0anacron", "ThreadPoolSingl", "(tmpfiles)",
+            "gsd-xsettings", "p11-kit", "get-scale-facto", "ollama", "sh",
+        ];
+
+        Self {
+            anomaly_threshold: 0.2,
+            seed_commands: seed_commands.iter().map(|c| c.to_string()).collect(),
+            persist_baseline: true,
+            baseline_path: PathBuf::from("/var/lib/sysrag/baseline.bin"),
+            checkpoint_interval_secs: 300,
+            index: IndexConfig::default(),
+            mode: ModeConfig::default(),
+            scope: ScopeConfig::default(),
+            scoring: ScoringConfig::default(),
+        }
+    }
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            frequency_weight: 0.55,
+            recency_weight: 0.15,
+            uid_weight: 0.15,
+            familiar_after_sightings: 20,
+            recency_half_life_secs: 604800,
+            common_after_uids: 3,
+        }
+    }
+}
+
+impl Default for ScopeConfig {
+    fn default() -> Self {
+        Self {
+            by: Vec::new(),
+            min_entries: 20,
+            max_scopes: 1024,
+        }
+    }
+}
+
+impl Default for ModeConfig {
+    fn default() -> Self {
+        Self {
+            initial: EngineMode::Learning,
+            learning_period_secs: 86400,
+            promote_after_sightings: 10,
+            promotion_window_secs: 7 * 86400,
+        }
+    }
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self::Hnsw(HnswConfig::default())
+    }
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_process: 16,
+            max_tracked_processes: 4096,
+        }
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        let watched_paths = [
+            "/etc/shadow", "/etc/gshadow", "/etc/sudoers", "~/.ssh/", "/root/", "/proc/*/mem",
+        ];
+
+        Self {
+            watched_paths: watched_paths.iter().map(|p| p.to_string()).collect(),
+            accesses_per_process: 16,
+            max_tracked_processes: 4096,
+        }
+    }
+}
+
+impl Default for ProcessesConfig {
+    fn default() -> Self {
+        Self { max_exited: 4096 }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("/var/lib/sysrag/anomalies.db"),
+            max_anomalies: 100_000,
+            max_age_secs: 90 * 86400,
+        }
+    }
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:11434/api/generate".to_string(),
+            model: "llama3".to_string(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Resolves defaults, the config file and CLI overrides (in that order) and validates the result
+    pub fn load(args: &DaemonArgs) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        if let Some(socket) = &args.socket {
+            config.server.socket_path = socket.clone();
+        }
+        if let Some(threshold) = args.threshold {
+            config.rag.anomaly_threshold = threshold;
+        }
+        if let Some(url) = &args.ollama_url {
+            config.llm.url = url.clone();
+        }
+        if let Some(model) = &args.ollama_model {
+            config.llm.model = model.clone();
+        }
+        if let Some(path) = &args.replay {
+            config.sources = Sources(vec![SourceConfig::Replay {
+                path: path.clone(),
+                speed: args.replay_speed,
+            }]);
+        }
+        if let Some(path) = &args.audit_log {
+            config.sources = Sources(vec![SourceConfig::Audit(AuditConfig {
+                path: path.clone(),
+                follow: false,
+            })]);
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config = toml::from_str(&raw)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        println!("📄 Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    /// Checks every value and reports all problems at once instead of stopping at the first
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if !self.server.socket_path.is_absolute() {
+            problems.push(format!(
+                "server.socket_path must be an absolute path (got {:?})",
+                self.server.socket_path
+            ));
+        } else if let Some(dir) = self.server.socket_path.parent() {
+            if !dir.is_dir() {
+                problems.push(format!(
+                    "server.socket_path: directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+
+        if self.sources.is_empty() {
+            problems.push("sources must list at least one event source".to_string());
+        }
+        if self.sources.iter().filter(|s| matches!(s, SourceConfig::Bpf)).count() > 1 {
+            problems.push("sources: the bpf source can only be listed once".to_string());
+        }
+        // Replayed or invented events would otherwise be learned and kept as if this host produced them
+        let observing = self.sources.iter().filter(|s| s.observes_host()).count();
+        if observing > 0 && observing < self.sources.len() {
+            problems.push(
+                "sources: replay, generator and non-following audit sources can't run next to sources observing this host"
+                    .to_string(),
+            );
+        }
+        for (i, source) in self.sources.iter().enumerate() {
+            match source {
+                SourceConfig::Bpf => {}
+                SourceConfig::Replay { path, speed } => {
+                    if !path.is_file() {
+                        problems.push(format!("sources[{}]: recording {} does not exist", i, path.display()));
+                    }
+                    if !(speed.is_finite() && *speed >= 0.0) {
+                        problems.push(format!("sources[{}].speed must be 0 or more (got {})", i, speed));
+                    }
+                }
+                SourceConfig::Proc(proc_poll) => {
+                    if proc_poll.interval_ms < 10 {
+                        problems.push(format!("sources[{}].interval_ms must be at least 10", i));
+                    }
+                }
+                SourceConfig::Audit(audit) => {
+                    if !audit.path.is_file() {
+                        problems.push(format!("sources[{}]: audit log {} does not exist", i, audit.path.display()));
+                    }
+                }
+                SourceConfig::Generator(generator) => {
+                    if generator.commands.is_empty() || generator.commands.iter().any(|c| c.trim().is_empty()) {
+                        problems.push(format!("sources[{}].commands must list at least one command and no empty entries", i));
+                    }
+                    if !(1..=1_000_000).contains(&generator.events_per_second) {
+                        problems.push(format!(
+                            "sources[{}].events_per_second must be between 1 and 1000000 (got {})",
+                            i, generator.events_per_second
+                        ));
+                    }
+                }
+            }
+        }
+
+        if self.pipeline.channel_capacity == 0 {
+            problems.push("pipeline.channel_capacity must be at least 1".to_string());
+        }
+
+        if !(0.0..=1.0).contains(&self.rag.anomaly_threshold) {
+            problems.push(format!(
+                "rag.anomaly_threshold must be between 0.0 and 1.0 (got {})",
+                self.rag.anomaly_threshold
+            ));
+        }
+        if self.history.max_anomalies == 0 {
+            problems.push("history.max_anomalies must be at least 1".to_string());
+        }
+
+        if self.rag.seed_commands.iter().any(|c| c.trim().is_empty()) {
+            problems.push("rag.seed_commands must not contain empty entries".to_string());
+        }
+        if self.rag.checkpoint_interval_secs == 0 {
+            problems.push("rag.checkpoint_interval_secs must be at least 1".to_string());
+        }
+        if let IndexConfig::Hnsw(hnsw) = &self.rag.index {
+            if !(2..=256).contains(&hnsw.m) {
+                problems.push(format!("rag.index.m must be between 2 and 256 (got {})", hnsw.m));
+            }
+            if hnsw.ef_construction < hnsw.m {
+                problems.push(format!("rag.index.ef_construction must be at least m ({})", hnsw.m));
+            }
+            if hnsw.ef_search == 0 {
+                problems.push("rag.index.ef_search must be at least 1".to_string());
+            }
+        }
+        if self.rag.mode.promote_after_sightings > 0 && self.rag.mode.promotion_window_secs == 0 {
+            problems.push("rag.mode.promotion_window_secs must be at least 1".to_string());
+        }
+        let scope_fields = &self.rag.scope.by;
+        if scope_fields.iter().enumerate().any(|(i, field)| scope_fields[..i].contains(field)) {
+            problems.push("rag.scope.by must not list a field twice".to_string());
+        }
+        let audited = self.sources.0.iter().any(|source| matches!(source, SourceConfig::Audit(_)));
+        if audited && scope_fields.contains(&ScopeField::Cgroup) {
+            problems.push("rag.scope.by can't include \"cgroup\" with an audit source, audit records carry no cgroup".to_string());
+        }
+        if self.rag.scope.min_entries == 0 {
+            problems.push("rag.scope.min_entries must be at least 1".to_string());
+        }
+        if self.rag.scope.max_scopes == 0 {
+            problems.push("rag.scope.max_scopes must be at least 1".to_string());
+        }
+        let scoring = &self.rag.scoring;
+        let weights = [
+            ("frequency_weight", scoring.frequency_weight),
+            ("recency_weight", scoring.recency_weight),
+            ("uid_weight", scoring.uid_weight),
+        ];
+        for (name, weight) in weights {
+            if !(0.0..=1.0).contains(&weight) {
+                problems.push(format!("rag.scoring.{} must be between 0.0 and 1.0 (got {})", name, weight));
+            }
+        }
+        let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        if total > 1.0 + 1e-6 {
+            problems.push(format!("rag.scoring weights must add up to at most 1.0 (got {})", total));
+        }
+        if scoring.familiar_after_sightings == 0 {
+            problems.push("rag.scoring.familiar_after_sightings must be at least 1".to_string());
+        }
+        if scoring.recency_half_life_secs == 0 {
+            problems.push("rag.scoring.recency_half_life_secs must be at least 1".to_string());
+        }
+        if scoring.common_after_uids == 0 {
+            problems.push("rag.scoring.common_after_uids must be at least 1".to_string());
+        }
+
+        if !(self.llm.url.starts_with("http://") || self.llm.url.starts_with("https://")) {
+            problems.push(format!(
+                "llm.url must start with http:// or https:// (got {:?})",
+                self.llm.url
+            ));
+        }
+        if self.llm.model.trim().is_empty() {
+            problems.push("llm.model must not be empty".to_string());
+        }
+        if self.llm.timeout_secs == 0 {
+            problems.push("llm.timeout_secs must be at least 1".to_string());
+        }
+
+        if self.network.connections_per_process == 0 {
+            problems.push("network.connections_per_process must be at least 1".to_string());
+        }
+        if self.network.max_tracked_processes == 0 {
+            problems.push("network.max_tracked_processes must be at least 1".to_string());
+        }
+
+        match files::parse_patterns(&self.files.watched_paths) {
+            Ok(watched) if watched.len() > files::MAX_WATCHED_PATHS => problems.push(format!(
+                "files.watched_paths expands to {} entries, at most {} are supported",
+                watched.len(),
+                files::MAX_WATCHED_PATHS
+            )),
+            Ok(_) => {}
+            Err(e) => problems.push(e.to_string()),
+        }
+        if self.files.accesses_per_process == 0 {
+            problems.push("files.accesses_per_process must be at least 1".to_string());
+        }
+        if self.files.max_tracked_processes == 0 {
+            problems.push("files.max_tracked_processes must be at least 1".to_string());
+        }
+
+        if self.processes.max_exited == 0 {
+            problems.push("processes.max_exited must be at least 1".to_string());
+        }
+
+        for rule in filters::rules_from_config(&self.filters) {
+            if let Err(e) = filters::validate_rule(&rule) {
+                problems.push(format!("filters: {:#}", e));
+            }
+        }
+        for (name, count) in [
+            ("allowed_comms", self.filters.allowed_comms.len()),
+            ("ignored_uids", self.filters.ignored_uids.len()),
+            ("ignored_cgroups", self.filters.ignored_cgroups.len()),
+        ] {
+            if count > filters::MAX_FILTER_ENTRIES {
+                problems.push(format!(
+                    "filters.{} has {} entries, at most {} are supported",
+                    name, count, filters::MAX_FILTER_ENTRIES
+                ));
+            }
+        }
+
+        for (i, rule) in self.access.rules.iter().enumerate() {
+            if rule.uids.is_empty() && rule.gids.is_empty() {
+                problems.push(format!("access.rules[{}] must list at least one uid or gid", i));
+            }
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> DaemonConfig {
+        toml::from_str(raw).expect("A valid config file")
+    }
+
+    fn problems(config: &DaemonConfig) -> String {
+        config.validate().map_or_else(|e| e.to_string(), |()| String::new())
+    }
+
+    #[test]
+    fn cli_flags_override_the_file_which_overrides_defaults() {
+        let path = std::env::temp_dir().join(format!("sysrag-config-{}.toml", std::process::id()));
+        fs::write(&path, "[rag]\nanomaly_threshold = 0.3\n\n[llm]\nmodel = \"mistral\"\n").unwrap();
+        let args = DaemonArgs::parse_from(["sysrag-daemon", "--config", path.to_str().unwrap(), "--threshold", "0.5"]);
+        let config = DaemonConfig::load(&args);
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.rag.anomaly_threshold, 0.5);
+        assert_eq!(config.llm.model, "mistral");
+        assert_eq!(config.llm.timeout_secs, LlmConfig::default().timeout_secs);
+    }
+
+    #[test]
+    fn invalid_values_are_all_reported() {
+        let config = parse(
+            "[pipeline]\nchannel_capacity = 0\n\n[rag]\nanomaly_threshold = 1.5\n\n\
+             [[sources]]\nkind = \"generator\"\nevents_per_second = 0\n",
+        );
+        let found = problems(&config);
+        assert!(found.contains("pipeline.channel_capacity must be at least 1"), "{}", found);
+        assert!(found.contains("rag.anomaly_threshold must be between 0.0 and 1.0"), "{}", found);
+        assert!(found.contains("sources[0].events_per_second must be between 1 and 1000000"), "{}", found);
+
+        assert_eq!(problems(&DaemonConfig::default()), "");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<DaemonConfig>("[rag]\nanomaly_treshold = 0.3\n").is_err());
+        assert!(toml::from_str::<DaemonConfig>("[[sources]]\nkind = \"ebpf\"\n").is_err());
+    }
+
+    #[test]
+    fn synthetic_sources_cannot_join_host_sources() {
+        let mixed = parse("[[sources]]\nkind = \"bpf\"\n\n[[sources]]\nkind = \"generator\"\n");
+        assert!(problems(&mixed).contains("can't run next to sources observing this host"));
+
+        let synthetic = parse("[[sources]]\nkind = \"generator\"\n\n[[sources]]\nkind = \"generator\"\n");
+        assert_eq!(problems(&synthetic), "");
+        let host = parse("[[sources]]\nkind = \"bpf\"\n\n[[sources]]\nkind = \"proc\"\n");
+        assert_eq!(problems(&host), "");
+    }
+}
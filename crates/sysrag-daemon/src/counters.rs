@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use aya::{
+    maps::{MapData, PerCpuArray},
+    Bpf,
+};
+use sysrag_common::ipc::ProgramStats;
+
+/// Names of the counter slots, in `PROG_*` order. This MUST match `sysrag.bpf.c`.
+const SENSOR_PROGRAMS: [&str; 6] = ["execve", "connect", "openat", "openat2", "fork", "exit"];
+
+/// Per-program ring buffer totals kept by the probes.
+/// A growing `lost` count means the ring buffer was full and our view of the host has gaps.
+pub struct SensorCounters {
+    sent: PerCpuArray<MapData, u64>,
+    lost: PerCpuArray<MapData, u64>,
+}
+
+impl SensorCounters {
+    /// Takes ownership of the counter maps so they can be read outside the listener task
+    pub fn from_bpf(bpf: &mut Bpf) -> Result<Self> {
+        let mut take = |name: &str| {
+            bpf.take_map(name).with_context(|| format!("Failed to find '{}' map", name))
+        };
+
+        Ok(Self {
+            sent: PerCpuArray::try_from(take("events_sent")?)?,
+            lost: PerCpuArray::try_from(take("events_lost")?)?,
+        })
+    }
+
+    /// Sums every program's counters over all CPUs
+    pub fn read(&self) -> Result<Vec<ProgramStats>> {
+        SENSOR_PROGRAMS
+            .iter()
+            .enumerate()
+            .map(|(slot, program)| {
+                let slot = slot as u32;
+                Ok(ProgramStats {
+                    program: program.to_string(),
+                    sent: self.sent.get(&slot, 0)?.iter().sum(),
+                    lost: self.lost.get(&slot, 0)?.iter().sum(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_names_follow_the_probe_numbering() {
+        let source = include_str!("../../../bpf/src/sysrag.bpf.c");
+        let slots: Vec<(usize, String)> = source
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.strip_prefix("#define PROG_")?.split_whitespace();
+                let name = words.next()?.to_lowercase();
+                let slot = words.next()?.parse().ok()?;
+                (name != "slots").then_some((slot, name))
+            })
+            .collect();
+
+        assert_eq!(slots.len(), SENSOR_PROGRAMS.len());
+        for (slot, name) in slots {
+            assert_eq!(SENSOR_PROGRAMS[slot], name);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+// These MUST match the limits in `sysrag.bpf.c`
+pub const MAX_PATH_LEN: usize = 256;
+pub const MAX_SUFFIX_LEN: usize = 32;
+/// Capacity of the `watched_prefixes` map
+pub const MAX_WATCHED_PATHS: usize = 256;
+
+/// One entry of `files.watched_paths`, ready to be loaded into the `watched_prefixes` BPF map.
+///
+/// Patterns are absolute path prefixes (`/etc/shadow`, `/root`). A leading `~/` is expanded
+/// to every home directory in `/etc/passwd`, and a single `*` matches any run of characters,
+/// so `/proc/*/mem` becomes the prefix `/proc/` plus the required suffix `/mem`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchedPath {
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl WatchedPath {
+    /// Re-checks a path reported by the kernel against the full pattern
+    pub fn matches(&self, path: &str) -> bool {
+        path.len() >= self.prefix.len() + self.suffix.len()
+            && path.starts_with(&self.prefix)
+            && path.ends_with(&self.suffix)
+    }
+}
+
+/// Parses and expands the configured patterns, reporting the first invalid one
+pub fn parse_patterns(patterns: &[String]) -> Result<Vec<WatchedPath>> {
+    let mut watched = Vec::new();
+
+    for pattern in patterns {
+        let expanded = match pattern.strip_prefix("~/") {
+            Some(rest) => home_directories()
+                .into_iter()
+                .map(|home| format!("{}/{}", home.trim_end_matches('/'), rest))
+                .collect(),
+            None => vec![pattern.clone()],
+        };
+
+        for path in expanded {
+            let parsed = parse_one(&path)
+                .map_err(|reason| anyhow::anyhow!("files.watched_paths entry {:?}: {}", pattern, reason))?;
+            if !watched.contains(&parsed) {
+                watched.push(parsed);
+            }
+        }
+    }
+
+    Ok(watched)
+}
+
+/// The rules to load into the `watched_prefixes` LPM trie, one per distinct prefix.
+///
+/// The trie holds a single rule per prefix and only returns the longest prefix of a path, so that
+/// rule must also accept whatever the shorter patterns above it accept. When those disagree on the
+/// suffix, the rule accepts any path under the prefix and `WatchedPath::matches` sorts the opens out.
+pub fn kernel_rules(watched: &[WatchedPath]) -> Vec<WatchedPath> {
+    let mut prefixes: Vec<&str> = watched.iter().map(|w| w.prefix.as_str()).collect();
+    prefixes.sort_unstable();
+    prefixes.dedup();
+
+    prefixes
+        .into_iter()
+        .map(|prefix| {
+            let mut covering = watched.iter().filter(|w| prefix.starts_with(&w.prefix));
+            let first = covering.next().expect("A prefix covers itself");
+            let suffix = if covering.all(|w| w.suffix == first.suffix) { first.suffix.clone() } else { String::new() };
+            WatchedPath { prefix: prefix.to_string(), suffix }
+        })
+        .collect()
+}
+
+fn parse_one(path: &str) -> Result<WatchedPath, String> {
+    if !path.starts_with('/') {
+        return Err("must be an absolute path or start with ~/".to_string());
+    }
+
+    let (prefix, suffix) = match path.split_once('*') {
+        Some((_, suffix)) if suffix.contains('*') => {
+            return Err("only a single * is supported".to_string());
+        }
+        Some((prefix, suffix)) => (prefix, suffix),
+        None => (path, ""),
+    };
+
+    if prefix.len() >= MAX_PATH_LEN {
+        return Err(format!("prefix is longer than {} bytes", MAX_PATH_LEN - 1));
+    }
+    if suffix.len() > MAX_SUFFIX_LEN {
+        return Err(format!("the part after * is longer than {} bytes", MAX_SUFFIX_LEN));
+    }
+
+    Ok(WatchedPath {
+        prefix: prefix.to_string(),
+        suffix: suffix.to_string(),
+    })
+}
+
+/// Home directories of every account in `/etc/passwd` that actually exist on this host
+fn home_directories() -> Vec<String> {
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let mut homes: Vec<String> = passwd
+        .lines()
+        .filter_map(|line| line.split(':').nth(5))
+        .filter(|home| home.starts_with('/') && *home != "/" && Path::new(home).is_dir())
+        .map(|home| home.to_string())
+        .collect();
+
+    homes.sort();
+    homes.dedup();
+    homes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(patterns: &[&str]) -> Result<Vec<WatchedPath>> {
+        parse_patterns(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+    }
+
+    fn watched(prefix: &str, suffix: &str) -> WatchedPath {
+        WatchedPath { prefix: prefix.to_string(), suffix: suffix.to_string() }
+    }
+
+    #[test]
+    fn patterns_split_at_the_star() {
+        let parsed = parse(&["/etc/shadow", "/proc/*/mem", "/etc/ssh/*"]).unwrap();
+        assert_eq!(parsed, [watched("/etc/shadow", ""), watched("/proc/", "/mem"), watched("/etc/ssh/", "")]);
+        assert!(parsed[1].matches("/proc/42/mem"));
+        assert!(!parsed[1].matches("/proc/42/maps"));
+        // The star needs the prefix and the suffix to fit without overlapping
+        assert!(!parsed[1].matches("/proc/mem"));
+    }
+
+    #[test]
+    fn duplicate_patterns_are_kept_once() {
+        assert_eq!(parse(&["/root", "/root"]).unwrap(), [watched("/root", "")]);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(parse(&["etc/shadow"]).is_err());
+        assert!(parse(&["/proc/*/task/*/mem"]).is_err());
+        assert!(parse(&[&format!("/{}", "a".repeat(MAX_PATH_LEN))]).is_err());
+        assert!(parse(&[&format!("/tmp/*{}", "a".repeat(MAX_SUFFIX_LEN + 1))]).is_err());
+        assert!(parse(&[&format!("/tmp/*{}", "a".repeat(MAX_SUFFIX_LEN))]).is_ok());
+    }
+
+    #[test]
+    fn home_patterns_expand_to_every_home() {
+        let parsed = parse(&["~/.ssh"]).unwrap();
+        let homes = home_directories();
+        assert_eq!(parsed.len(), homes.len());
+        for (watched, home) in parsed.iter().zip(&homes) {
+            assert_eq!(watched.prefix, format!("{}/.ssh", home));
+        }
+    }
+
+    #[test]
+    fn kernel_rules_keep_one_rule_per_prefix() {
+        let rules = kernel_rules(&[watched("/proc/", "/mem"), watched("/proc/", "/environ"), watched("/etc/shadow", "")]);
+        // The two suffixes can't share one rule, so the kernel passes anything under /proc/
+        assert_eq!(rules, [watched("/etc/shadow", ""), watched("/proc/", "")]);
+
+        let rules = kernel_rules(&[watched("/proc/", "/mem"), watched("/proc/", "/mem")]);
+        assert_eq!(rules, [watched("/proc/", "/mem")]);
+    }
+
+    #[test]
+    fn kernel_rules_let_broader_patterns_through_longer_prefixes() {
+        let rules = kernel_rules(&[watched("/etc/", ""), watched("/etc/ssh/", "_key")]);
+        // /etc/ssh/sshd_config only matches the /etc/ rule, but the trie answers with /etc/ssh/
+        assert_eq!(rules, [watched("/etc/", ""), watched("/etc/ssh/", "")]);
+
+        let rules = kernel_rules(&[watched("/home/", "/.bashrc"), watched("/home/dev/", "/.bashrc")]);
+        assert_eq!(rules, [watched("/home/", "/.bashrc"), watched("/home/dev/", "/.bashrc")]);
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use aya::{
+    maps::{HashMap, MapData, PerCpuArray},
+    Bpf,
+};
+use sysrag_common::ipc::{FilterDrops, FilterRule};
+
+use crate::config::FiltersConfig;
+use crate::procfs::{self, CGROUP_ROOT};
+
+// These MUST match the limits and `FILTER_DROP_*` slots in `sysrag.bpf.c`
+pub const COMM_LEN: usize = 16;
+pub const MAX_FILTER_ENTRIES: usize = 1024;
+const FILTER_DROP_COMM: u32 = 0;
+const FILTER_DROP_UID: u32 = 1;
+const FILTER_DROP_CGROUP: u32 = 2;
+
+/// The in-kernel exec filters. The maps are checked by `trace_execve` before anything is copied,
+/// so a matching exec costs a few hash lookups instead of a ring buffer record and an embedding.
+pub struct KernelFilters {
+    allowed_comms: HashMap<MapData, [u8; COMM_LEN], u8>,
+    ignored_uids: HashMap<MapData, u32, u8>,
+    ignored_cgroups: HashMap<MapData, u64, u8>,
+    drops: PerCpuArray<MapData, u64>,
+    rules: Vec<ActiveRule>,
+}
+
+/// A rule in force, with the map key it was added under. Cgroup paths are resolved once, since
+/// the cgroup may be gone or recreated under another id by the time the rule is removed.
+struct ActiveRule {
+    rule: FilterRule,
+    key: MapKey,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MapKey {
+    Comm([u8; COMM_LEN]),
+    Uid(u32),
+    Cgroup(u64),
+}
+
+impl KernelFilters {
+    /// Takes ownership of the filter maps so they can be updated while the sensor keeps running
+    pub fn from_bpf(bpf: &mut Bpf) -> Result<Self> {
+        let mut take = |name: &str| {
+            bpf.take_map(name).with_context(|| format!("Failed to find '{}' map", name))
+        };
+
+        Ok(Self {
+            allowed_comms: HashMap::try_from(take("allowed_comms")?)?,
+            ignored_uids: HashMap::try_from(take("ignored_uids")?)?,
+            ignored_cgroups: HashMap::try_from(take("ignored_cgroups")?)?,
+            drops: PerCpuArray::try_from(take("filter_drops")?)?,
+            rules: Vec::new(),
+        })
+    }
+
+    pub fn rules(&self) -> Vec<FilterRule> {
+        self.rules.iter().map(|active| active.rule.clone()).collect()
+    }
+
+    /// Starts dropping execs that match `rule`. Adding an active rule again is a no-op.
+    pub fn add(&mut self, rule: FilterRule) -> Result<()> {
+        if self.rules.iter().any(|active| active.rule == rule) {
+            return Ok(());
+        }
+        if self.rules.iter().filter(|active| same_kind(&active.rule, &rule)).count() >= MAX_FILTER_ENTRIES {
+            anyhow::bail!("At most {} filters of this kind are supported", MAX_FILTER_ENTRIES);
+        }
+
+        let key = match &rule {
+            FilterRule::Comm(comm) => MapKey::Comm(comm_key(comm)?),
+            FilterRule::Uid(uid) => MapKey::Uid(*uid),
+            FilterRule::Cgroup(path) => MapKey::Cgroup(cgroup_id(path)?),
+        };
+        match key {
+            MapKey::Comm(comm) => self.allowed_comms.insert(comm, 1, 0)?,
+            MapKey::Uid(uid) => self.ignored_uids.insert(uid, 1, 0)?,
+            MapKey::Cgroup(id) => self.ignored_cgroups.insert(id, 1, 0)?,
+        }
+        self.rules.push(ActiveRule { rule, key });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, rule: &FilterRule) -> Result<()> {
+        let Some(index) = self.rules.iter().position(|active| active.rule == *rule) else {
+            anyhow::bail!("No active filter for {}", rule);
+        };
+
+        // Two spellings of one cgroup path share its map entry
+        let key = self.rules[index].key;
+        let shared = self.rules.iter().filter(|active| active.key == key).count() > 1;
+        if !shared {
+            match key {
+                MapKey::Comm(comm) => self.allowed_comms.remove(&comm)?,
+                MapKey::Uid(uid) => self.ignored_uids.remove(&uid)?,
+                MapKey::Cgroup(id) => self.ignored_cgroups.remove(&id)?,
+            }
+        }
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// Sums the per-CPU drop counters
+    pub fn drops(&self) -> Result<FilterDrops> {
+        let sum = |slot: u32| -> Result<u64> {
+            Ok(self.drops.get(&slot, 0)?.iter().sum())
+        };
+
+        Ok(FilterDrops {
+            comm: sum(FILTER_DROP_COMM)?,
+            uid: sum(FILTER_DROP_UID)?,
+            cgroup: sum(FILTER_DROP_CGROUP)?,
+        })
+    }
+}
+
+/// The rules listed in the `[filters]` config section
+pub fn rules_from_config(config: &FiltersConfig) -> Vec<FilterRule> {
+    let comms = config.allowed_comms.iter().cloned().map(FilterRule::Comm);
+    let uids = config.ignored_uids.iter().copied().map(FilterRule::Uid);
+    let cgroups = config.ignored_cgroups.iter().cloned().map(FilterRule::Cgroup);
+    comms.chain(uids).chain(cgroups).collect()
+}
+
+/// Checks that a rule can be turned into a map key, so config errors show up before the sensor starts
+pub fn validate_rule(rule: &FilterRule) -> Result<()> {
+    match rule {
+        FilterRule::Comm(comm) => comm_key(comm).map(|_| ()),
+        FilterRule::Uid(_) => Ok(()),
+        FilterRule::Cgroup(path) => cgroup_id(path).map(|_| ()),
+    }
+}
+
+fn same_kind(a: &FilterRule, b: &FilterRule) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// The NUL-padded comm the kernel compares against
+fn comm_key(comm: &str) -> Result<[u8; COMM_LEN]> {
+    if comm.is_empty() || comm.len() >= COMM_LEN {
+        anyhow::bail!("comm {:?} must be 1 to {} bytes long", comm, COMM_LEN - 1);
+    }
+
+    let mut key = [0u8; COMM_LEN];
+    key[..comm.len()].copy_from_slice(comm.as_bytes());
+    Ok(key)
+}
+
+/// The cgroup v2 id the kernel compares against
+fn cgroup_id(path: &str) -> Result<u64> {
+    if !path.starts_with('/') {
+        anyhow::bail!("cgroup {:?} must start with / (relative to {})", path, CGROUP_ROOT);
+    }
+
+    procfs::cgroup_id(path).with_context(|| format!("cgroup {}{} does not exist", CGROUP_ROOT, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comm_keys_are_nul_padded_and_bounded() {
+        let key = comm_key("make").unwrap();
+        assert_eq!(&key[..5], b"make\0");
+        assert!(key[4..].iter().all(|b| *b == 0));
+
+        // The kernel keeps 15 bytes and a NUL
+        assert!(comm_key("fifteen-bytes-x").is_ok());
+        assert!(comm_key("sixteen-bytes-xx").is_err());
+        assert!(comm_key("").is_err());
+    }
+
+    #[test]
+    fn rules_from_config_keep_every_kind() {
+        let config = FiltersConfig {
+            allowed_comms: vec!["backup-agent".to_string()],
+            ignored_uids: vec![33, 34],
+            ignored_cgroups: vec!["/system.slice".to_string()],
+        };
+        assert_eq!(
+            rules_from_config(&config),
+            [
+                FilterRule::Comm("backup-agent".to_string()),
+                FilterRule::Uid(33),
+                FilterRule::Uid(34),
+                FilterRule::Cgroup("/system.slice".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rule_rejects_what_the_maps_cannot_hold() {
+        assert!(validate_rule(&FilterRule::Uid(0)).is_ok());
+        assert!(validate_rule(&FilterRule::Comm("a-very-long-program".to_string())).is_err());
+        assert!(validate_rule(&FilterRule::Cgroup("system.slice".to_string())).is_err());
+        assert!(validate_rule(&FilterRule::Cgroup("/sysrag-no-such-cgroup".to_string())).is_err());
+    }
+
+    #[test]
+    fn cgroup_ids_are_directory_inodes() {
+        use std::os::unix::fs::MetadataExt;
+
+        let Ok(root) = std::fs::metadata(CGROUP_ROOT) else {
+            return; // No cgroup hierarchy in this sandbox
+        };
+        assert_eq!(cgroup_id("/").unwrap(), root.ino());
+    }
+}
@@ -0,0 +1,377 @@
+//! The anomaly history: every report the engine raised, with its investigation and triage state,
+//! kept in a local SQLite database so it survives restarts and reboots.
+//!
+//! The searchable fields are columns with their own index; the full report is stored as JSON.
+//! Every call blocks on disk, so the async side goes through `DaemonState`, which runs them on
+//! a writer thread or with `spawn_blocking`.
+
+use anyhow::{Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::fs;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::Mutex;
+use sysrag_common::ipc::{AnomalyQuery, AnomalyReport, TriageState};
+
+use crate::config::HistoryConfig;
+use crate::rag::persist::create_private_dir;
+
+/// Bumped whenever the schema below changes. Version 2 stores the composite score in `score`
+/// rather than the raw similarity.
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS anomalies (
+        id            TEXT PRIMARY KEY,
+        timestamp     INTEGER NOT NULL,
+        pid           INTEGER NOT NULL,
+        command       TEXT NOT NULL,
+        score         REAL NOT NULL,
+        report        TEXT NOT NULL,
+        investigation TEXT,
+        triage        TEXT NOT NULL DEFAULT 'new'
+    );
+    CREATE INDEX IF NOT EXISTS anomalies_timestamp ON anomalies (timestamp);
+    CREATE INDEX IF NOT EXISTS anomalies_pid ON anomalies (pid);
+    CREATE INDEX IF NOT EXISTS anomalies_command ON anomalies (command);
+    CREATE INDEX IF NOT EXISTS anomalies_score ON anomalies (score);
+";
+
+pub struct AnomalyHistory {
+    // Queries are short and local, so callers simply take turns
+    db: Mutex<Connection>,
+    max_anomalies: u64,
+    max_age_secs: u64,
+}
+
+impl AnomalyHistory {
+    /// Opens (or creates) the database at `path`. If that fails the history is kept in memory
+    /// for this run only, so detection never stops over a storage problem.
+    pub fn open_or_in_memory(config: &HistoryConfig) -> Self {
+        match Self::open(config) {
+            Ok(history) => history,
+            Err(e) => {
+                eprintln!(
+                    "⚠️ Failed to open the anomaly history {} ({:#}), keeping anomalies in memory only",
+                    config.path.display(), e
+                );
+                Self::in_memory(config)
+            }
+        }
+    }
+
+    /// A history that lasts for this run only
+    pub fn in_memory(config: &HistoryConfig) -> Self {
+        let db = Connection::open_in_memory().expect("SQLite can always open an in-memory database");
+        Self::with_schema(db, config).expect("The schema is valid")
+    }
+
+    fn open(config: &HistoryConfig) -> Result<Self> {
+        let path = &config.path;
+        if let Some(dir) = path.parent() {
+            create_private_dir(dir)?;
+        }
+        // Anomalies carry command lines, so only root may read them. SQLite gives the WAL and
+        // shared memory files the permissions of the database itself.
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict {}", path.display()))?;
+        drop(file);
+
+        let db = Connection::open(path)?;
+        // Readers no longer wait for a write, and a commit costs no fsync of its own
+        let journal: String = db.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        if !journal.eq_ignore_ascii_case("wal") {
+            eprintln!("⚠️ The anomaly history {} stays in {} journal mode", path.display(), journal);
+        }
+        db.pragma_update(None, "synchronous", "NORMAL")?;
+
+        let history = Self::with_schema(db, config)?;
+        let pruned = history.prune(crate::rag::current_timestamp())?;
+        if pruned > 0 {
+            println!("🧹 Dropped {} anomalies past the history retention limits", pruned);
+        }
+
+        let count: i64 = history.db().query_row("SELECT COUNT(*) FROM anomalies", [], |row| row.get(0))?;
+        println!("🗄️ Anomaly history at {} holds {} anomalies", path.display(), count);
+        Ok(history)
+    }
+
+    fn with_schema(db: Connection, config: &HistoryConfig) -> Result<Self> {
+        let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            anyhow::bail!("Database schema {} is newer than this daemon ({})", version, SCHEMA_VERSION);
+        }
+
+        db.execute_batch(SCHEMA)?;
+        if version == 1 {
+            // Reports from before composite scoring keep their similarity
+            db.execute(
+                "UPDATE anomalies SET score = COALESCE(json_extract(report, '$.score.total'), score)",
+                [],
+            )?;
+        }
+        db.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self {
+            db: Mutex::new(db),
+            max_anomalies: config.max_anomalies,
+            max_age_secs: config.max_age_secs,
+        })
+    }
+
+    fn db(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // A panic mid-query leaves the connection usable, so a poisoned lock is fine to reuse
+        self.db.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Stores a freshly detected anomaly
+    pub fn insert(&self, anomaly: &AnomalyReport) -> Result<()> {
+        self.db().execute(
+            "INSERT OR REPLACE INTO anomalies (id, timestamp, pid, command, score, report, investigation, triage)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                anomaly.id,
+                anomaly.timestamp as i64,
+                anomaly.pid,
+                anomaly.command,
+                anomaly.final_score(),
+                serde_json::to_string(anomaly)?,
+                anomaly.investigation,
+                anomaly.triage.as_str(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Drops anomalies older than `max_age_secs`, then the oldest beyond `max_anomalies`.
+    /// Returns how many went.
+    pub fn prune(&self, now: u64) -> Result<usize> {
+        let db = self.db();
+        let mut pruned = 0;
+        if self.max_age_secs > 0 {
+            let cutoff = now.saturating_sub(self.max_age_secs);
+            pruned += db.execute("DELETE FROM anomalies WHERE timestamp < ?1", params![cutoff as i64])?;
+        }
+        pruned += db.execute(
+            "DELETE FROM anomalies WHERE rowid IN (
+                 SELECT rowid FROM anomalies ORDER BY timestamp DESC, rowid DESC LIMIT -1 OFFSET ?1
+             )",
+            params![self.max_anomalies.min(i64::MAX as u64) as i64],
+        )?;
+        Ok(pruned)
+    }
+
+    /// The last `tail` anomalies matching `query`, oldest first
+    pub fn query(&self, tail: usize, query: &AnomalyQuery) -> Result<Vec<AnomalyReport>> {
+        // Only the filters actually set become conditions, so SQLite can pick the matching index
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(since) = query.since {
+            conditions.push("timestamp >= ?");
+            values.push(Value::Integer(since as i64));
+        }
+        if let Some(until) = query.until {
+            conditions.push("timestamp <= ?");
+            values.push(Value::Integer(until as i64));
+        }
+        if let Some(command) = &query.command {
+            conditions.push("command = ?");
+            values.push(Value::Text(command.clone()));
+        }
+        if let Some(min_score) = query.min_score {
+            conditions.push("score >= ?");
+            values.push(Value::Real(min_score.into()));
+        }
+        if let Some(max_score) = query.max_score {
+            conditions.push("score <= ?");
+            values.push(Value::Real(max_score.into()));
+        }
+        values.push(Value::Integer(tail as i64));
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let sql = format!(
+            "SELECT report, investigation, triage FROM anomalies {} ORDER BY timestamp DESC, rowid DESC LIMIT ?",
+            filter
+        );
+
+        let db = self.db();
+        let mut statement = db.prepare_cached(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, String>(0)?, row.get(1)?, row.get::<_, String>(2)?))
+        })?;
+
+        let mut anomalies = rows
+            .map(|row| {
+                let (report, investigation, triage) = row?;
+                from_row(&report, investigation, &triage)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        anomalies.reverse();
+        Ok(anomalies)
+    }
+
+    pub fn find(&self, id: &str) -> Result<Option<AnomalyReport>> {
+        let row = self
+            .db()
+            .query_row(
+                "SELECT report, investigation, triage FROM anomalies WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get::<_, String>(2)?)),
+            )
+            .optional()?;
+
+        row.map(|(report, investigation, triage)| from_row(&report, investigation, &triage))
+            .transpose()
+    }
+
+    /// Keeps the latest LLM analysis of an anomaly. Returns false if there is no such anomaly.
+    pub fn set_investigation(&self, id: &str, investigation: &str) -> Result<bool> {
+        let updated = self.db().execute(
+            "UPDATE anomalies SET investigation = ?2 WHERE id = ?1",
+            params![id, investigation],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Returns false if there is no such anomaly
+    pub fn set_triage(&self, id: &str, triage: TriageState) -> Result<bool> {
+        let updated = self.db().execute(
+            "UPDATE anomalies SET triage = ?2 WHERE id = ?1",
+            params![id, triage.as_str()],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+/// Rebuilds a report from its stored JSON. Investigation and triage change after insertion,
+/// so their columns win over the copies inside the JSON.
+fn from_row(report: &str, investigation: Option<String>, triage: &str) -> Result<AnomalyReport> {
+    let mut anomaly: AnomalyReport = serde_json::from_str(report).context("Corrupt anomaly in the history")?;
+    anomaly.investigation = investigation;
+    anomaly.triage = triage.parse().map_err(anyhow::Error::msg)?;
+    Ok(anomaly)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use sysrag_common::ipc::ScoreBreakdown;
+
+    fn history(max_anomalies: u64, max_age_secs: u64) -> AnomalyHistory {
+        AnomalyHistory::in_memory(&HistoryConfig { path: PathBuf::new(), max_anomalies, max_age_secs })
+    }
+
+    fn anomaly(id: &str, timestamp: u64) -> AnomalyReport {
+        AnomalyReport {
+            id: id.to_string(),
+            timestamp,
+            pid: 1,
+            uid: 0,
+            command: "nc".to_string(),
+            exe: "/usr/bin/nc".to_string(),
+            argv: Vec::new(),
+            context: Default::default(),
+            connections: Vec::new(),
+            file_accesses: Vec::new(),
+            ancestry: Vec::new(),
+            fidelity: Default::default(),
+            similarity_score: 0.1,
+            score: None,
+            nearest: None,
+            scope: None,
+            raw_log: String::new(),
+            triage: TriageState::New,
+            investigation: None,
+        }
+    }
+
+    /// An anomaly whose composite score is far above its similarity
+    fn scored(id: &str, total: f32) -> AnomalyReport {
+        AnomalyReport {
+            score: Some(ScoreBreakdown {
+                executable: "/usr/bin/nc".to_string(),
+                total,
+                similarity: 0.1,
+                frequency: 0.0,
+                recency: 0.0,
+                uid_spread: 0.0,
+                sightings: 0,
+                last_seen: None,
+                distinct_uids: 0,
+            }),
+            ..anomaly(id, 10)
+        }
+    }
+
+    fn ids(history: &AnomalyHistory) -> Vec<String> {
+        let all = history.query(100, &AnomalyQuery::default()).unwrap();
+        all.into_iter().map(|anomaly| anomaly.id).collect()
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_anomalies() {
+        let history = history(2, 0);
+        for (id, timestamp) in [("a", 10), ("b", 30), ("c", 20)] {
+            history.insert(&anomaly(id, timestamp)).unwrap();
+        }
+
+        assert_eq!(history.prune(30).unwrap(), 1);
+        assert_eq!(ids(&history), ["c", "b"]);
+    }
+
+    #[test]
+    fn prune_drops_anomalies_past_the_age_limit() {
+        let history = history(100, 50);
+        for (id, timestamp) in [("a", 10), ("b", 60), ("c", 100)] {
+            history.insert(&anomaly(id, timestamp)).unwrap();
+        }
+
+        assert_eq!(history.prune(100).unwrap(), 1);
+        assert_eq!(ids(&history), ["b", "c"]);
+    }
+
+    #[test]
+    fn score_filters_use_the_composite_score() {
+        let history = history(100, 0);
+        history.insert(&scored("a", 0.5)).unwrap();
+        history.insert(&anomaly("b", 20)).unwrap();
+
+        let below = |max_score| {
+            let query = AnomalyQuery { max_score: Some(max_score), ..Default::default() };
+            let found = history.query(100, &query).unwrap();
+            found.into_iter().map(|anomaly| anomaly.id).collect::<Vec<_>>()
+        };
+        assert_eq!(below(0.3), ["b"]);
+        assert_eq!(below(0.5), ["a", "b"]);
+    }
+
+    #[test]
+    fn version_1_rows_are_rescored() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        db.pragma_update(None, "user_version", 1).unwrap();
+        for report in [scored("a", 0.5), anomaly("b", 20)] {
+            db.execute(
+                "INSERT INTO anomalies (id, timestamp, pid, command, score, report) VALUES (?1, ?2, 1, 'nc', ?3, ?4)",
+                params![report.id, report.timestamp as i64, report.similarity_score, serde_json::to_string(&report).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let config = HistoryConfig { path: PathBuf::new(), max_anomalies: 100, max_age_secs: 0 };
+        let history = AnomalyHistory::with_schema(db, &config).unwrap();
+        let query = AnomalyQuery { min_score: Some(0.3), ..Default::default() };
+        let found: Vec<_> = history.query(100, &query).unwrap().into_iter().map(|anomaly| anomaly.id).collect();
+        assert_eq!(found, ["a"]);
+    }
+}
@@ -1,7 +1,11 @@
+use sysrag_common::events::Fidelity;
 use sysrag_common::ipc::AnomalyReport;
+use std::net::SocketAddr;
 use std::time::Duration;
 use anyhow::{Context, Result};
 
+use crate::config::LlmConfig;
+
 /// The LLM Analyzer responsible for turning raw math and logs into human security alerts
 pub struct LlmAnalyzer {
     model_name: String,
@@ -11,13 +15,13 @@ pub struct LlmAnalyzer {
 
 impl LlmAnalyzer {
     /// Initializes the connection to the local LLM inference engine
-    pub fn new() -> Self {
+    pub fn new(config: &LlmConfig) -> Self {
         println!("🚀 Initializing Production LLM Analyzer (Ollama backend)...");
         Self {
-            model_name: "llama3".to_string(), 
-            inference_url: "http://127.0.0.1:11434/api/generate".to_string(),
+            model_name: config.model.clone(),
+            inference_url: config.url.clone(),
             client: reqwest::Client::builder()
-                .timeout(Duration::from_secs(30))
+                .timeout(Duration::from_secs(config.timeout_secs))
                 .build()
                 .expect("Failed to build HTTP client"),
         }
@@ -44,7 +48,7 @@ impl LlmAnalyzer {
             }))
             .send()
             .await
-            .context("Failed to connect to Ollama. Is the Ollama service running on port 11434?")?;
+            .with_context(|| format!("Failed to connect to Ollama at {}. Is the Ollama service running?", self.inference_url))?;
 
         // Catch non-200 HTTP errors gracefully
         if !res.status().is_success() {
@@ -76,16 +80,98 @@ impl LlmAnalyzer {
             4. If a flag like '-e' is used, explain exactly what '-e' does, do not substitute it with '-c'. \
             \
             ANOMALY DATA: \
-            PID: {} \
+            PID: {} (parent PID {}) \
+            User: uid {} gid {} login uid {} \
+            Terminal: {} \
+            cgroup id: {}, mount namespace: {}, pid namespace: {} \
+            Process Ancestry: {} \
             Command: {} \
+            Executable: {} \
+            Command Line: {} \
+            Outbound Connections: {} \
+            Sensitive Files Opened: {} \
             Similarity Score: {:.2} \
+            Executable History: {} \
+            Observation: {} \
             Raw Log: {} \
             \
             Provide your forensic analysis now.",
             anomaly.pid,
+            anomaly.context.ppid,
+            anomaly.uid,
+            anomaly.context.gid,
+            anomaly.context.loginuid.map_or("unset".to_string(), |uid| uid.to_string()),
+            anomaly.context.tty.as_deref().unwrap_or("none (not interactive)"),
+            anomaly.context.cgroup_id,
+            anomaly.context.mnt_ns,
+            anomaly.context.pid_ns,
+            format_ancestry(anomaly),
             anomaly.command, 
+            anomaly.exe,
+            anomaly.argv.join(" "),
+            format_connections(anomaly),
+            format_file_accesses(anomaly),
             anomaly.similarity_score,
+            format_executable_history(anomaly),
+            match anomaly.fidelity {
+                Fidelity::Exact => "captured by a kernel probe",
+                Fidelity::Sampled => "inferred by polling /proc; short-lived processes, connections and file opens may be missing",
+            },
             anomaly.raw_log
         )
     }
-}
\ No newline at end of file
+}
+
+/// Renders the tracked outbound connections as "ip:port, ip:port"
+fn format_connections(anomaly: &AnomalyReport) -> String {
+    if anomaly.connections.is_empty() {
+        return "none observed".to_string();
+    }
+
+    anomaly
+        .connections
+        .iter()
+        .map(|c| SocketAddr::new(c.address, c.port).to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Renders the watched files the process opened as "/etc/shadow (read), ..."
+fn format_file_accesses(anomaly: &AnomalyReport) -> String {
+    if anomaly.file_accesses.is_empty() {
+        return "none observed".to_string();
+    }
+
+    anomaly
+        .file_accesses
+        .iter()
+        .map(|f| format!("{} ({})", f.path, if f.write { "write" } else { "read" }))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Renders the ancestry chain as "sshd → bash → curl → sh"
+fn format_ancestry(anomaly: &AnomalyReport) -> String {
+    if anomaly.ancestry.is_empty() {
+        return "unknown".to_string();
+    }
+
+    anomaly
+        .ancestry
+        .iter()
+        .map(|p| p.comm.as_str())
+        .collect::<Vec<_>>()
+        .join(" → ")
+}
+
+/// Renders how familiar the executable was, e.g. "seen 3 times before, by 2 distinct uids"
+fn format_executable_history(anomaly: &AnomalyReport) -> String {
+    match &anomaly.score {
+        Some(score) if score.sightings > 0 => format!(
+            "seen {} times before, by {} distinct uids",
+            score.sightings, score.distinct_uids
+        ),
+        Some(_) => "never seen before on this host".to_string(),
+        None => "unknown".to_string(),
+    }
+}
@@ -1,70 +1,249 @@
+mod activity;
+mod auth;
 mod bpf;
+mod config;
+mod counters;
+mod files;
+mod filters;
+mod history;
 mod llm;
+mod procfs;
+mod proctree;
 mod rag;
+mod recording;
 mod server;
+mod sources;
+mod state;
 
 use anyhow::Result;
+use clap::Parser;
 use std::sync::Arc;
+use std::time::Duration;
+use sysrag_common::events::KernelEvent;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{mpsc, Mutex};
-use sysrag_common::ipc::AnomalyReport;
+
+use config::{DaemonArgs, DaemonConfig};
+use state::DaemonState;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     println!("🚀 Starting sysragd (Systems RAG Daemon)...");
 
-    // 1. Shared State: This holds our detected anomalies in memory. 
-    // We wrap it in an Arc<Mutex> so both the AI engine (which writes to it) 
-    // and the IPC server (which reads from it) can access it safely across threads.
-    let anomalies_store: Arc<Mutex<Vec<AnomalyReport>>> = Arc::new(Mutex::new(Vec::new()));
+    // 0. Resolve defaults, the config file and CLI overrides before touching anything else
+    let args = DaemonArgs::parse();
+    let mut config = DaemonConfig::load(&args)?;
+
+    // 1. Build every configured event source (the eBPF Kernel Sensor, replays, generators...)
+    // first, since what they observe decides what may be written to disk.
+    let built = sources::build(&config)?;
+    if !built.observes_host {
+        // A replayed incident or invented traffic must not teach the real baseline or fill the real history
+        println!("🧪 No source observes this host, keeping the baseline and the anomaly history in memory only");
+        config.rag.persist_baseline = false;
+    }
+
+    // Shared State: detected anomalies, event counters, start time and baseline size.
+    // The AI engine writes to it and the IPC server reads from it, so we share it behind an Arc.
+    let state = Arc::new(DaemonState::new(&config, built.observes_host)?);
 
-    // 2. Setup the communication channel: Kernel Sensor -> AI Engine
-    // This allows the eBPF sensor to instantly drop logs into a queue without waiting for the math.
-    let (log_tx, mut log_rx) = mpsc::channel::<String>(1000);
+    // 2. Setup the communication channel: Event Sources -> AI Engine
+    // This allows the sources to instantly drop typed events into a queue without waiting for the math.
+    let (event_tx, mut event_rx) = mpsc::channel::<KernelEvent>(config.pipeline.channel_capacity);
 
     // 3. Initialize the RAG Engine (Local Vector DB & Embedding Models)
-    let mut rag_engine = rag::RagEngine::new()?;
+    let mut rag_engine = rag::RagEngine::new(&config.rag)?;
+    state.set_baseline_size(rag_engine.baseline_size());
+
+    // 4. Start the event sources.
+    // This comes before the IPC server because `sysrag filter` edits the sensor's filter maps.
+    if built.observes_host {
+        // Replayed or generated events come from elsewhere, so only live sources start from the running processes
+        state.seed_process_table().await;
+    }
+    for source in built.sources {
+        let name = source.name();
+        let event_tx = event_tx.clone();
+        tokio::spawn(async move {
+            match source.run(event_tx).await {
+                Ok(()) => println!("⏹️ Event source {} finished", name),
+                Err(e) => eprintln!("🔥 Event source {} failed: {:#}", name, e),
+            }
+        });
+    }
+    // Only the sources hold senders now, so the loop below ends once all of them have finished
+    drop(event_tx);
 
-    // 4. Start the IPC Server in the background to listen for CLI commands
-    // (In our prototype `server.rs` we mocked the response, but in production, 
-    // you would pass `anomalies_store.clone()` into `start_ipc_server` here!)
+    let (kernel_filters, sensor_counters) = match built.sensor_maps {
+        Some(maps) => (Some(maps.filters), Some(maps.counters)),
+        None => (None, None),
+    };
+
+    // 5. Start the IPC Server in the background to listen for CLI commands.
+    // Requests for the engine itself (modes, approvals) come back to the loop below.
+    let (engine_tx, mut engine_rx) = mpsc::channel::<rag::EngineCommand>(16);
+    let ipc_ctx = Arc::new(server::ServerContext {
+        state: state.clone(),
+        analyzer: llm::LlmAnalyzer::new(&config.llm),
+        access: auth::AccessPolicy::new(config.access.clone()),
+        filters: kernel_filters.map(Mutex::new),
+        engine: engine_tx,
+    });
+    let socket_path = config.server.socket_path.clone();
     tokio::spawn(async move {
-        server::start_ipc_server().await;
+        server::start_ipc_server(&socket_path, ipc_ctx).await;
     });
 
-    // 5. Initialize and inject the eBPF Kernel Sensor
-    let mut bpf_manager = bpf::BpfManager::new()?;
-    
-    // Spawn the kernel listener in the background
+    // Once per second, sample the event rate and the probes' ring buffer counters for `sysrag status`
+    let stats_state = state.clone();
     tokio::spawn(async move {
-        if let Err(e) = bpf_manager.start_listening(log_tx).await {
-            eprintln!("🔥 Kernel Sensor Error: {}", e);
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            stats_state.sample_event_rate();
+
+            let Some(counters) = &sensor_counters else {
+                continue;
+            };
+            match counters.read() {
+                Ok(stats) => {
+                    let lost = stats_state.update_sensor_stats(stats).await;
+                    if lost > 0 {
+                        eprintln!("⚠️ Ring buffer full: {} kernel events lost in the last second", lost);
+                    }
+                }
+                Err(e) => eprintln!("⚠️ Failed to read sensor counters: {:#}", e),
+            }
         }
     });
 
+    // Optionally keep a copy of everything that enters the pipeline for later replay
+    let mut recorder = args.record.as_deref().map(recording::EventRecorder::create).transpose()?;
+
+    // Write the baseline every `checkpoint_interval_secs`, off the event loop, and once more on shutdown
+    let checkpoint_period = Duration::from_secs(config.rag.checkpoint_interval_secs);
+    let mut checkpoints = tokio::time::interval_at(tokio::time::Instant::now() + checkpoint_period, checkpoint_period);
+    let mut running_checkpoint: Option<tokio::task::JoinHandle<()>> = None;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    // Registered once, so a SIGINT arriving while an event is being processed isn't lost
+    let mut sigint = signal(SignalKind::interrupt())?;
+
     println!("🟢 Systems RAG AI Engine online. Monitoring kernel events in real-time...");
 
-    // 6. The Main Event Loop: Consume kernel logs and do the math
-    while let Some(raw_log) = log_rx.recv().await {
-        // Pass the raw Linux kernel string into the AI embedding math
-        match rag_engine.process_log(&raw_log) {
-            Ok(Some(anomaly)) => {
-                println!(
-                    "🚨 ANOMALY DETECTED: [{}] Score: {:.2}", 
-                    anomaly.command, anomaly.similarity_score
-                );
-                
-                // Save the anomaly to our shared state so the CLI can fetch it later
-                let mut store = anomalies_store.lock().await;
-                store.push(anomaly);
+    // 6. The Main Event Loop: Consume kernel events and do the math
+    loop {
+        tokio::select! {
+            event = event_rx.recv() => {
+                let Some(event) = event else {
+                    // Only replays, audit log reads and bounded generators run out of events; the kernel sensor never closes the channel
+                    println!("🏁 Event stream ended");
+                    break;
+                };
+                if let Some(Err(e)) = recorder.as_mut().map(|r| r.record(&event)) {
+                    eprintln!("⚠️ Failed to record event, recording stopped: {:#}", e);
+                    recorder = None;
+                }
+                process_event(&state, &mut rag_engine, &event, built.observes_host).await;
+            }
+            Some(command) = engine_rx.recv() => {
+                // Mode switches and approvals are saved right away rather than at the next interval;
+                // `sysrag mode` only asks, and mustn't write the baseline each time
+                if rag_engine.handle_command(command) {
+                    checkpoints.reset_immediately();
+                }
+                state.set_baseline_size(rag_engine.baseline_size());
             }
-            Ok(None) => {
-                // Normal system behavior. The RAG engine silently updates its baseline.
+            _ = checkpoints.tick() => {
+                // A slow disk must not stack up checkpoints
+                if running_checkpoint.as_ref().is_some_and(|task| !task.is_finished()) {
+                    continue;
+                }
+                if let Some(snapshot) = rag_engine.checkpoint() {
+                    running_checkpoint = Some(tokio::task::spawn_blocking(move || save_checkpoint(&snapshot)));
+                }
             }
-            Err(e) => {
-                eprintln!("⚠️ RAG Engine Error: {}", e);
+            _ = sigint.recv() => {
+                println!("🛑 Interrupted, shutting down...");
+                break;
+            }
+            _ = sigterm.recv() => {
+                println!("🛑 Terminated, shutting down...");
+                break;
             }
         }
     }
 
+    if let Some(task) = running_checkpoint {
+        let _ = task.await;
+    }
+    if let Some(snapshot) = rag_engine.checkpoint() {
+        save_checkpoint(&snapshot);
+    }
+    state.flush_history().await;
+
+    println!(
+        "📊 {} events processed, {} anomalies detected",
+        state.events_processed(), state.anomalies_detected()
+    );
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Runs one event through the daemon state and the RAG engine. `on_this_host` is false for
+/// events replayed or invented, whose paths can't be looked up here.
+async fn process_event(state: &DaemonState, rag_engine: &mut rag::RagEngine, event: &KernelEvent, on_this_host: bool) {
+    // Pass the typed kernel event into the AI embedding math
+    state.record_event();
+    match event {
+        KernelEvent::Connect(conn) => state.record_connection(conn).await,
+        KernelEvent::FileOpen(open) => state.record_file_access(open).await,
+        KernelEvent::Exec(exec) => state.record_exec(exec).await,
+        KernelEvent::Fork(fork) => state.record_fork(fork).await,
+        KernelEvent::Exit(exit) => state.record_exit(exit).await,
+    }
+
+    // Scoped baselines can be keyed by the parent's command name,
+    // and executables are scored by the file they name rather than the path handed to execve
+    let (parent_comm, executable) = match event {
+        KernelEvent::Exec(exec) => {
+            let (pid, filename) = (exec.pid, exec.filename.clone());
+            // Resolving follows links through /proc, which must not stall the event loop
+            let executable = if on_this_host {
+                tokio::task::spawn_blocking(move || procfs::canonical_executable(pid, &filename, true))
+                    .await
+                    .unwrap_or_default()
+            } else {
+                procfs::canonical_executable(pid, &filename, false)
+            };
+            (state.process_comm(exec.context.ppid).await, executable)
+        }
+        _ => (None, String::new()),
+    };
+    let result = rag_engine.process_event(event, parent_comm.as_deref(), &executable);
+    state.set_baseline_size(rag_engine.baseline_size());
+
+    match result {
+        Ok(Some(mut anomaly)) => {
+            anomaly.ancestry = state.ancestry(anomaly.pid).await;
+            println!(
+                "🚨 ANOMALY DETECTED: [{}] Score: {:.2}", 
+                anomaly.command, anomaly.final_score()
+            );
+            
+            // Save the anomaly to our shared state so the CLI can fetch it later
+            state.push_anomaly(anomaly);
+        }
+        Ok(None) => {
+            // Normal system behavior. The RAG engine silently updates its baseline.
+        }
+        Err(e) => {
+            eprintln!("⚠️ RAG Engine Error: {}", e);
+        }
+    }
+}
+
+fn save_checkpoint(snapshot: &rag::persist::BaselineSnapshot) {
+    match snapshot.save() {
+        Ok(()) => println!("💾 Baseline checkpoint: {} entries saved to {}", snapshot.entry_count(), snapshot.path.display()),
+        Err(e) => eprintln!("⚠️ Failed to save the baseline: {:#}", e),
+    }
+}
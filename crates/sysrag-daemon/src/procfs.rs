@@ -0,0 +1,316 @@
+//! Readers for the per-process files under `/proc`, shared by the process table seeding
+//! and the `/proc` polling event source. Every reader returns `None` once the process is gone.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// `/proc/<pid>/stat` start times are in clock ticks of `sysconf(_SC_CLK_TCK)` per second
+pub fn clock_ticks_per_sec() -> u64 {
+    static TICKS: OnceLock<u64> = OnceLock::new();
+    *TICKS.get_or_init(|| {
+        // SAFETY: sysconf only reads a system setting
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        // USER_HZ is 100 on every mainstream build, should the call ever fail
+        if ticks > 0 { ticks as u64 } else { 100 }
+    })
+}
+
+/// Where the cgroup v2 hierarchy is mounted
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The fields of `/proc/<pid>/stat` we use
+pub struct ProcStat {
+    pub comm: String,
+    pub ppid: u32,
+    /// Encoded device number of the controlling terminal, 0 if none
+    pub tty_nr: u32,
+    /// Clock ticks after boot at which the process started. Together with the PID this identifies a process.
+    pub start_ticks: u64,
+}
+
+pub fn read_stat(pid: u32) -> Option<ProcStat> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The comm is wrapped in parentheses and may itself contain spaces or ')'
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat[open + 1..close].to_string();
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+
+    // Fields after the comm start at "state" (field 3 in proc(5))
+    Some(ProcStat {
+        comm,
+        ppid: fields.get(1)?.parse().ok()?,
+        tty_nr: fields.get(4)?.parse().ok()?,
+        start_ticks: fields.get(19)?.parse().ok()?,
+    })
+}
+
+/// Real uid and gid from `/proc/<pid>/status`
+pub fn read_uid_gid(pid: u32) -> Option<(u32, u32)> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let first_id = |prefix: &str| -> Option<u32> {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .and_then(|ids| ids.split_whitespace().next())
+            .and_then(|id| id.parse().ok())
+    };
+
+    Some((first_id("Uid:")?, first_id("Gid:")?))
+}
+
+/// The thread group a task belongs to, which is its own PID unless it is a thread
+pub fn read_tgid(pid: u32) -> Option<u32> {
+    fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+/// The executable, only readable for other users' processes with CAP_SYS_PTRACE
+pub fn read_exe(pid: u32) -> Option<String> {
+    fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Symlinks followed before a path is given up on, as the kernel's own limit
+const MAX_SYMLINKS: usize = 40;
+
+/// The file an exec ran, so `./run.sh`, `/bin/sh` and `/usr/bin/dash` each name the file itself
+/// whatever path was handed to execve. With `on_this_host`, the path is resolved the way the
+/// process sees the filesystem: inside its root (a container's, say), relative to its working
+/// directory, following symlinks. Events from elsewhere are only cleaned up lexically. Empty if
+/// `filename` is. Reads `/proc` and follows links, so keep it off the async event loop.
+pub fn canonical_executable(pid: u32, filename: &str, on_this_host: bool) -> String {
+    if filename.is_empty() {
+        return String::new();
+    }
+
+    let mut path = PathBuf::from(filename);
+    if on_this_host {
+        let root = PathBuf::from(format!("/proc/{}/root", pid));
+        if path.is_relative() {
+            // execve doesn't change the working directory, so it is still the caller's
+            if let Some(cwd) = working_directory(pid) {
+                path = cwd.join(path);
+            }
+        }
+        if path.is_absolute() && fs::symlink_metadata(&root).is_ok() {
+            return resolve_in_root(&root, &path).to_string_lossy().into_owned();
+        }
+    }
+
+    lexical_clean(&path).to_string_lossy().into_owned()
+}
+
+/// The working directory of `pid` as seen from its own root
+fn working_directory(pid: u32) -> Option<PathBuf> {
+    // Both links read as paths from our root, e.g. /var/lib/docker/.../merged and .../merged/app
+    let root = fs::read_link(format!("/proc/{}/root", pid)).ok()?;
+    let cwd = fs::read_link(format!("/proc/{}/cwd", pid)).ok()?;
+    let inside = cwd.strip_prefix(&root).ok()?;
+    Some(Path::new("/").join(inside))
+}
+
+/// Follows the symlinks of the absolute `path` as if `root` were `/`, so absolute link targets
+/// stay inside it. Missing components are kept as they are.
+fn resolve_in_root(root: &Path, path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    let mut pending: Vec<_> = path.components().rev().map(|c| c.as_os_str().to_owned()).collect();
+    let mut links = 0;
+
+    while let Some(component) = pending.pop() {
+        match Path::new(&component).components().next() {
+            Some(Component::RootDir) => resolved = PathBuf::from("/"),
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                let target = (links < MAX_SYMLINKS)
+                    .then(|| fs::read_link(root.join(candidate.strip_prefix("/").unwrap_or(&candidate))).ok())
+                    .flatten();
+                match target {
+                    Some(target) => {
+                        links += 1;
+                        pending.extend(target.components().rev().map(|c| c.as_os_str().to_owned()));
+                    }
+                    None => resolved = candidate,
+                }
+            }
+            _ => {}
+        }
+    }
+    resolved
+}
+
+/// Drops "." and resolves ".." without following links
+fn lexical_clean(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match clean.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    clean.pop();
+                }
+                // "/.." is "/"
+                Some(Component::RootDir) => {}
+                _ => clean.push(".."),
+            },
+            other => clean.push(other),
+        }
+    }
+    clean
+}
+
+/// argv as the process sees it. Empty for kernel threads and zombies.
+pub fn read_cmdline(pid: u32) -> Vec<String> {
+    fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|raw| {
+            raw.split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The audit login uid, `None` if it was never set (daemons)
+pub fn read_loginuid(pid: u32) -> Option<u32> {
+    let raw = fs::read_to_string(format!("/proc/{}/loginuid", pid)).ok()?;
+    raw.trim().parse().ok().filter(|uid| *uid != u32::MAX)
+}
+
+/// The inode number of a namespace, e.g. `read_namespace(pid, "mnt")`
+pub fn read_namespace(pid: u32, kind: &str) -> Option<u32> {
+    // The link reads like "mnt:[4026531841]"
+    let link = fs::read_link(format!("/proc/{}/ns/{}", pid, kind)).ok()?;
+    let link = link.to_string_lossy();
+    link.split_once('[')?.1.trim_end_matches(']').parse().ok()
+}
+
+/// The cgroup v2 id of a process, the same value `bpf_get_current_cgroup_id` returns
+pub fn read_cgroup_id(pid: u32) -> Option<u64> {
+    let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    cgroup_id(path).ok()
+}
+
+/// The cgroup v2 id of a cgroup path (relative to `CGROUP_ROOT`), which is the inode number of its directory
+pub fn cgroup_id(path: &str) -> std::io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = Path::new(CGROUP_ROOT).join(path.trim_start_matches('/'));
+    fs::metadata(dir).map(|metadata| metadata.ino())
+}
+
+/// Turns `tty_nr` into a name like "pts3" or "tty1", matching what the kernel sensor reports
+pub fn tty_name(tty_nr: u32) -> Option<String> {
+    if tty_nr == 0 {
+        return None;
+    }
+
+    // Linux dev_t: major in bits 8-19, minor in bits 0-7 and 20-31
+    let major = (tty_nr >> 8) & 0xfff;
+    let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+    match major {
+        4 if minor < 64 => Some(format!("tty{}", minor)),
+        4 => Some(format!("ttyS{}", minor - 64)),
+        136..=143 => Some(format!("pts{}", (major - 136) * 256 + minor)),
+        _ => Some(format!("{}:{}", major, minor)),
+    }
+}
+
+/// The Unix timestamp the host booted at, from the `btime` line of `/proc/stat`
+pub fn boot_time() -> Option<u64> {
+    fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+}
+
+/// Every numeric entry of `/proc`, i.e. the PIDs of all processes (not threads)
+pub fn list_pids() -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_from_elsewhere_are_cleaned_lexically() {
+        assert_eq!(canonical_executable(1, "/usr/bin/../lib/./tool", false), "/usr/lib/tool");
+        assert_eq!(canonical_executable(1, "/../bin/sh", false), "/bin/sh");
+        assert_eq!(canonical_executable(1, "./bin/../run.sh", false), "run.sh");
+        assert_eq!(canonical_executable(1, "", false), "");
+    }
+
+    #[test]
+    fn paths_on_this_host_follow_the_working_directory_and_links() {
+        let dir = std::env::temp_dir().join(format!("sysrag-procfs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("dash");
+        fs::write(&target, b"").unwrap();
+        std::os::unix::fs::symlink(&target, dir.join("sh")).unwrap();
+        let target = fs::canonicalize(&target).unwrap().to_string_lossy().into_owned();
+
+        let link = dir.join("sh").to_string_lossy().into_owned();
+        assert_eq!(canonical_executable(std::process::id(), &link, true), target);
+        // Relative paths resolve against the working directory of the process that exec'd
+        let mut child = std::process::Command::new("sleep").arg("10").current_dir(&dir).spawn().unwrap();
+        let resolved = canonical_executable(child.id(), "./sh", true);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(resolved, target);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn links_resolve_inside_the_process_root() {
+        let root = std::env::temp_dir().join(format!("sysrag-root-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/busybox"), b"").unwrap();
+        // Absolute targets point into the container, not at the host's files
+        std::os::unix::fs::symlink("/usr/bin", root.join("bin")).unwrap();
+        std::os::unix::fs::symlink("busybox", root.join("usr/bin/sh")).unwrap();
+        std::os::unix::fs::symlink("/loop", root.join("loop")).unwrap();
+
+        assert_eq!(resolve_in_root(&root, Path::new("/bin/sh")), Path::new("/usr/bin/busybox"));
+        assert_eq!(resolve_in_root(&root, Path::new("/bin/../../usr/bin/gone")), Path::new("/usr/bin/gone"));
+        // A link loop gives up instead of spinning
+        assert_eq!(resolve_in_root(&root, Path::new("/loop")), Path::new("/loop"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tty_names_match_the_kernel_sensor() {
+        assert_eq!(tty_name(0), None);
+        assert_eq!(tty_name(4 << 8 | 1).as_deref(), Some("tty1"));
+        assert_eq!(tty_name(4 << 8 | 65).as_deref(), Some("ttyS1"));
+        assert_eq!(tty_name(136 << 8 | 3).as_deref(), Some("pts3"));
+        // Minors past 255 continue in the next major
+        assert_eq!(tty_name(137 << 8 | 2).as_deref(), Some("pts258"));
+    }
+
+    #[test]
+    fn clock_ticks_come_from_the_system() {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        assert_eq!(clock_ticks_per_sec(), ticks as u64);
+    }
+}
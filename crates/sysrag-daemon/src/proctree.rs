@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use sysrag_common::events::{ExecEvent, ExitEvent, ForkEvent};
+use sysrag_common::ipc::{ProcessInfo, ProcessTreeNode};
+
+use crate::procfs;
+
+/// Guards the ancestry walk against loops from PID reuse
+const MAX_ANCESTRY_DEPTH: usize = 64;
+
+/// A live parent/child tree of every process on the host, built from fork, exec and exit events.
+/// Exited processes stay around (up to `max_exited`) so recent ancestry remains explainable.
+pub struct ProcessTable {
+    processes: HashMap<u32, ProcessInfo>,
+    children: HashMap<u32, Vec<u32>>,
+    exited: VecDeque<u32>,
+    max_exited: usize,
+}
+
+impl ProcessTable {
+    pub fn new(max_exited: usize) -> Self {
+        Self {
+            processes: HashMap::new(),
+            children: HashMap::new(),
+            exited: VecDeque::new(),
+            max_exited,
+        }
+    }
+
+    /// Fills the table with every process already running, so ancestry works from the first event
+    pub fn seed_from_proc(&mut self) {
+        let boot_time = procfs::boot_time().unwrap_or(0);
+
+        for pid in procfs::list_pids() {
+            if let Some(info) = read_proc_entry(pid, boot_time) {
+                self.insert(info);
+            }
+        }
+        println!("🌳 Process table seeded with {} running processes", self.processes.len());
+    }
+
+    pub fn record_fork(&mut self, fork: &ForkEvent, timestamp: u64) {
+        let exe = self
+            .processes
+            .get(&fork.parent_pid)
+            .map(|parent| parent.exe.clone())
+            .unwrap_or_default();
+
+        self.insert(ProcessInfo {
+            pid: fork.child_pid,
+            ppid: fork.parent_pid,
+            uid: fork.uid,
+            comm: fork.comm.clone(),
+            exe,
+            started_at: timestamp,
+            exited_at: None,
+            exit_status: None,
+            exit_signal: None,
+        });
+    }
+
+    /// An exec keeps the PID but replaces the program, and with it the name
+    pub fn record_exec(&mut self, exec: &ExecEvent, timestamp: u64) {
+        match self.processes.get_mut(&exec.pid) {
+            Some(process) if process.exited_at.is_none() => {
+                process.comm = exec.program_name().to_string();
+                process.exe = exec.filename.clone();
+                process.uid = exec.uid;
+            }
+            _ => self.insert(ProcessInfo {
+                pid: exec.pid,
+                ppid: exec.context.ppid,
+                uid: exec.uid,
+                comm: exec.program_name().to_string(),
+                exe: exec.filename.clone(),
+                started_at: timestamp,
+                exited_at: None,
+                exit_status: None,
+                exit_signal: None,
+            }),
+        }
+    }
+
+    pub fn record_exit(&mut self, exit: &ExitEvent, timestamp: u64) {
+        let Some(process) = self.processes.get_mut(&exit.pid) else {
+            return;
+        };
+        if process.exited_at.is_some() {
+            return;
+        }
+
+        process.exited_at = Some(timestamp);
+        process.exit_status = exit.status();
+        process.exit_signal = exit.signal();
+
+        self.exited.push_back(exit.pid);
+        while self.exited.len() > self.max_exited {
+            if let Some(oldest) = self.exited.pop_front() {
+                self.remove(oldest);
+            }
+        }
+    }
+
+    /// The command name of `pid`, if the table knows the process
+    pub fn comm(&self, pid: u32) -> Option<String> {
+        self.processes.get(&pid).map(|process| process.comm.clone())
+    }
+
+    /// The chain from the oldest known ancestor down to `pid` itself, e.g. sshd → bash → curl → sh
+    pub fn ancestry(&self, pid: u32) -> Vec<ProcessInfo> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = pid;
+
+        while let Some(process) = self.processes.get(&current) {
+            if !seen.insert(current) || chain.len() >= MAX_ANCESTRY_DEPTH {
+                break;
+            }
+            chain.push(process.clone());
+            if process.ppid == 0 || process.ppid == current {
+                break;
+            }
+            current = process.ppid;
+        }
+
+        chain.reverse();
+        chain
+    }
+
+    /// `pid` and all of its known descendants
+    pub fn subtree(&self, pid: u32) -> Option<ProcessTreeNode> {
+        let mut seen = HashSet::new();
+        self.build_subtree(pid, &mut seen)
+    }
+
+    fn build_subtree(&self, pid: u32, seen: &mut HashSet<u32>) -> Option<ProcessTreeNode> {
+        if !seen.insert(pid) {
+            return None;
+        }
+        let process = self.processes.get(&pid)?.clone();
+        let children = self
+            .children
+            .get(&pid)
+            .map(|kids| kids.iter().filter_map(|kid| self.build_subtree(*kid, seen)).collect())
+            .unwrap_or_default();
+
+        Some(ProcessTreeNode { process, children })
+    }
+
+    fn insert(&mut self, info: ProcessInfo) {
+        // A reused PID replaces the old process entirely
+        if self.processes.contains_key(&info.pid) {
+            self.remove(info.pid);
+        }
+
+        self.children.entry(info.ppid).or_default().push(info.pid);
+        self.processes.insert(info.pid, info);
+    }
+
+    fn remove(&mut self, pid: u32) {
+        if let Some(process) = self.processes.remove(&pid) {
+            if let Some(siblings) = self.children.get_mut(&process.ppid) {
+                siblings.retain(|sibling| *sibling != pid);
+            }
+        }
+        // Otherwise a process that reuses the PID would inherit the old one's children
+        self.children.remove(&pid);
+        self.exited.retain(|exited| *exited != pid);
+    }
+}
+
+/// Reads pid, ppid, uid, comm, exe and start time of one running process
+fn read_proc_entry(pid: u32, boot_time: u64) -> Option<ProcessInfo> {
+    let stat = procfs::read_stat(pid)?;
+    let uid = procfs::read_uid_gid(pid).map_or(0, |(uid, _)| uid);
+
+    Some(ProcessInfo {
+        pid,
+        ppid: stat.ppid,
+        uid,
+        comm: stat.comm,
+        exe: procfs::read_exe(pid).unwrap_or_default(),
+        started_at: boot_time + stat.start_ticks / procfs::clock_ticks_per_sec(),
+        exited_at: None,
+        exit_status: None,
+        exit_signal: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fork(parent_pid: u32, child_pid: u32) -> ForkEvent {
+        ForkEvent { parent_pid, child_pid, uid: 0, comm: "bash".to_string() }
+    }
+
+    fn exec(pid: u32, ppid: u32, filename: &str) -> ExecEvent {
+        ExecEvent {
+            pid,
+            uid: 0,
+            // The sensor sees the exec from the caller's side
+            comm: "sshd".to_string(),
+            context: sysrag_common::events::ProcessContext { ppid, ..Default::default() },
+            filename: filename.to_string(),
+            argv: Vec::new(),
+            argv_truncated: false,
+            fidelity: Default::default(),
+        }
+    }
+
+    #[test]
+    fn exec_renames_the_process_after_the_new_program() {
+        let mut table = ProcessTable::new(16);
+        table.record_fork(&ForkEvent { parent_pid: 1, child_pid: 100, uid: 0, comm: "sshd".to_string() }, 1);
+        table.record_exec(&exec(100, 1, "/usr/bin/bash"), 2);
+        // Unknown processes enter the table under the new name too, cut like the kernel cuts it
+        table.record_exec(&exec(200, 100, "./a-rather-long-program-name"), 3);
+
+        let names: Vec<_> = table.ancestry(200).into_iter().map(|p| p.comm).collect();
+        assert_eq!(names, ["bash", "a-rather-long-p"]);
+    }
+
+    #[test]
+    fn reused_pid_does_not_inherit_old_children() {
+        let mut table = ProcessTable::new(0);
+        table.record_fork(&fork(1, 100), 1);
+        table.record_fork(&fork(100, 200), 2);
+
+        // PID 100 exits and is evicted at once, then comes back as an unrelated process
+        table.record_exit(&ExitEvent { pid: 100, comm: "bash".to_string(), exit_code: 0, fidelity: Default::default() }, 3);
+        table.record_fork(&fork(1, 100), 4);
+
+        assert!(table.subtree(100).unwrap().children.is_empty());
+    }
+}
@@ -1,18 +1,36 @@
 use fastembed::{TextEmbedding, InitOptions, EmbeddingModel};
 
+/// The model every baseline vector comes from
+const EMBEDDING_MODEL: EmbeddingModel = EmbeddingModel::AllMiniLML6V2;
+
 pub struct LogEmbedder {
     model: TextEmbedding,
+    model_id: String,
+    dimension: usize,
 }
 
 impl LogEmbedder {
     /// Initializes the local embedding model
     pub fn new() -> Result<Self, anyhow::Error> {
         // We use a tiny, lightning-fast model perfect for short logs
-        let model = TextEmbedding::try_new(InitOptions::new(
-            EmbeddingModel::AllMiniLML6V2
-        ))?;
-        
-        Ok(Self { model })
+        let model = TextEmbedding::try_new(InitOptions::new(EMBEDDING_MODEL))?;
+        let info = TextEmbedding::get_model_info(&EMBEDDING_MODEL)?;
+
+        Ok(Self {
+            model,
+            model_id: info.model_code.clone(),
+            dimension: info.dim,
+        })
+    }
+
+    /// Identifies the model, e.g. "Qdrant/all-MiniLM-L6-v2-onnx". Vectors from different models can't be compared.
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Length of every vector this model produces
+    pub fn dimension(&self) -> usize {
+        self.dimension
     }
 
     /// Converts a raw OS log string into a vector of f32 numbers.
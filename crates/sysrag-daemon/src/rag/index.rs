@@ -0,0 +1,677 @@
+//! Nearest-neighbour search over the baseline vectors.
+//!
+//! `FlatIndex` compares a query with every vector: exact, but each lookup gets slower as the
+//! baseline grows. `Hnsw` is a Hierarchical Navigable Small World graph (Malkov & Yashunin, 2016):
+//! every vector is a node linked to its closest neighbours on a stack of ever sparser layers, and a
+//! lookup walks the graph greedily from the top. It finds the true nearest neighbour most of the
+//! time while only touching a few hundred vectors, whatever the size of the baseline.
+//!
+//! Both compare by cosine similarity. Vectors are normalized on insert, so that is a dot product.
+//! Vectors and links live in `ChunkedVec`s, so a checkpoint can clone an index cheaply.
+//! This module only uses std, so `benches/baseline_search.rs` can include it as is.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{self, Read, Write};
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+/// Nodes never get more layers than this, whatever the dice say
+const MAX_LAYERS: usize = 16;
+
+/// `entry_point` of an empty graph in the serialized form
+const NO_ENTRY_POINT: u32 = u32::MAX;
+
+/// Items per chunk of a `ChunkedVec`
+const CHUNK_LEN: usize = 1024;
+
+/// A growable array kept in fixed-size shared chunks. A clone only copies the chunk pointers, and
+/// a write to a shared chunk copies that chunk alone, so the copy a checkpoint saves costs next to
+/// nothing while the live one keeps changing.
+#[derive(Clone)]
+pub struct ChunkedVec<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T: Clone> ChunkedVec<T> {
+    pub fn new() -> Self {
+        Self { chunks: Vec::new(), len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len.is_multiple_of(CHUNK_LEN) {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_LEN)));
+        }
+        let last = self.chunks.last_mut().expect("A chunk was just added if there was none");
+        Arc::make_mut(last).push(value);
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+}
+
+impl<T: Clone> Default for ChunkedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for ChunkedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        let mut chunked = Self::new();
+        for item in items {
+            chunked.push(item);
+        }
+        chunked
+    }
+}
+
+impl<T> Index<usize> for ChunkedVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.chunks[index / CHUNK_LEN][index % CHUNK_LEN]
+    }
+}
+
+impl<T: Clone> IndexMut<usize> for ChunkedVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut Arc::make_mut(&mut self.chunks[index / CHUNK_LEN])[index % CHUNK_LEN]
+    }
+}
+
+/// A baseline vector found by a lookup
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbour {
+    pub id: u32,
+    pub similarity: f32,
+}
+
+impl Eq for Neighbour {}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Normalized vectors addressed by insertion order
+#[derive(Clone)]
+struct Vectors {
+    dimension: usize,
+    data: ChunkedVec<Arc<[f32]>>,
+}
+
+impl Vectors {
+    fn new(dimension: usize) -> Self {
+        Self { dimension, data: ChunkedVec::new() }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn push(&mut self, vector: &[f32]) -> u32 {
+        debug_assert_eq!(vector.len(), self.dimension);
+        let id = self.len() as u32;
+        self.data.push(normalized(vector).into());
+        id
+    }
+
+    fn get(&self, id: u32) -> &[f32] {
+        &self.data[id as usize]
+    }
+
+    fn similarity(&self, id: u32, query: &[f32]) -> f32 {
+        dot(self.get(id), query)
+    }
+}
+
+/// Exact search by comparing against every vector
+#[derive(Clone)]
+pub struct FlatIndex {
+    vectors: Vectors,
+}
+
+impl FlatIndex {
+    pub fn new(dimension: usize) -> Self {
+        Self { vectors: Vectors::new(dimension) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Adds a vector and returns its id, which is the number of vectors inserted before it
+    pub fn insert(&mut self, vector: &[f32]) -> u32 {
+        self.vectors.push(vector)
+    }
+
+    /// The normalized vector stored under `id`
+    pub fn vector(&self, id: u32) -> &[f32] {
+        self.vectors.get(id)
+    }
+
+    /// The `k` most similar vectors, best first
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Neighbour> {
+        let query = normalized(query);
+        let mut best = BinaryHeap::with_capacity(k + 1);
+
+        for id in 0..self.len() as u32 {
+            best.push(Reverse(Neighbour { id, similarity: self.vectors.similarity(id, &query) }));
+            if best.len() > k {
+                best.pop();
+            }
+        }
+        sorted_best_first(best)
+    }
+}
+
+/// Tuning of an HNSW graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswParams {
+    /// Links per node and layer (twice as many on the bottom layer). More links give better recall
+    /// and cost memory and insert time.
+    pub m: usize,
+    /// Candidates weighed when linking a new node. Higher builds a better graph, more slowly.
+    pub ef_construction: usize,
+    /// Candidates kept during a lookup. This is the recall/latency knob: higher is closer to exact and slower.
+    pub ef_search: usize,
+}
+
+/// Approximate search over a navigable small world graph that grows one insert at a time
+#[derive(Clone)]
+pub struct Hnsw {
+    params: HnswParams,
+    vectors: Vectors,
+    /// For every node, its links on each layer it lives on, bottom layer first
+    links: ChunkedVec<Vec<Vec<u32>>>,
+    /// A node on the top layer, where every lookup starts
+    entry_point: Option<u32>,
+    /// State of the xorshift generator that picks node layers
+    rng: u64,
+}
+
+impl Hnsw {
+    pub fn new(dimension: usize, params: HnswParams) -> Self {
+        Self {
+            params,
+            vectors: Vectors::new(dimension),
+            links: ChunkedVec::new(),
+            entry_point: None,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    /// The normalized vector stored under `id`
+    pub fn vector(&self, id: u32) -> &[f32] {
+        self.vectors.get(id)
+    }
+
+    /// Adds a vector, links it into the graph and returns its id
+    pub fn insert(&mut self, vector: &[f32]) -> u32 {
+        let id = self.vectors.push(vector);
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            return id;
+        };
+
+        let query = self.vectors.get(id).to_vec();
+        let top = self.top_layer(entry_point);
+        let mut nearest = vec![self.neighbour(entry_point, &query)];
+
+        // Above the new node's own layers we only need the closest entry point for the next layer down
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(&query, &nearest, 1, layer);
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(&query, &nearest, self.params.ef_construction, layer);
+            let selected = self.select_neighbours(&candidates, self.params.m);
+
+            self.links[id as usize][layer] = selected.iter().map(|n| n.id).collect();
+            for neighbour in &selected {
+                self.link(neighbour.id, id, layer);
+            }
+            nearest = candidates;
+        }
+
+        if level > top {
+            self.entry_point = Some(id);
+        }
+        id
+    }
+
+    /// The `k` most similar vectors, best first. Usually, but not always, the exact answer.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Neighbour> {
+        self.search_with_ef(query, k, self.params.ef_search)
+    }
+
+    /// `search` keeping `ef_search` candidates instead of the configured number. Unlike the
+    /// other parameters it doesn't shape the graph, so the benchmark sweeps it on one build.
+    pub fn search_with_ef(&self, query: &[f32], k: usize, ef_search: usize) -> Vec<Neighbour> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let query = normalized(query);
+        let mut nearest = vec![self.neighbour(entry_point, &query)];
+        for layer in (1..=self.top_layer(entry_point)).rev() {
+            nearest = self.search_layer(&query, &nearest, 1, layer);
+        }
+
+        let mut found = self.search_layer(&query, &nearest, ef_search.max(k), 0);
+        found.truncate(k);
+        found
+    }
+
+    /// Serializes the links, to be read back with `HnswGraph::read_from`. The vectors are not included.
+    pub fn write_graph(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.params.m as u32).to_be_bytes())?;
+        writer.write_all(&(self.params.ef_construction as u32).to_be_bytes())?;
+        writer.write_all(&self.entry_point.unwrap_or(NO_ENTRY_POINT).to_be_bytes())?;
+
+        for layers in self.links.iter() {
+            writer.write_all(&[layers.len() as u8])?;
+            for links in layers {
+                writer.write_all(&(links.len() as u16).to_be_bytes())?;
+                for link in links {
+                    writer.write_all(&link.to_be_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds an index from saved vectors and links. Returns `None` if the graph was built with
+    /// other parameters or doesn't fit the vectors, in which case the caller should re-insert them.
+    pub fn from_graph<'a>(
+        dimension: usize,
+        params: HnswParams,
+        vectors: impl IntoIterator<Item = &'a [f32]>,
+        graph: HnswGraph,
+    ) -> Option<Self> {
+        if graph.m != params.m || graph.ef_construction != params.ef_construction {
+            return None;
+        }
+
+        let mut index = Self::new(dimension, params);
+        for vector in vectors {
+            index.vectors.push(vector);
+        }
+        if index.vectors.len() != graph.links.len() {
+            return None;
+        }
+
+        index.links = graph.links.into_iter().collect();
+        index.entry_point = graph.entry_point;
+        index.rng ^= index.links.len() as u64;
+        Some(index)
+    }
+
+    fn top_layer(&self, id: u32) -> usize {
+        self.links[id as usize].len() - 1
+    }
+
+    fn neighbour(&self, id: u32, query: &[f32]) -> Neighbour {
+        Neighbour { id, similarity: self.vectors.similarity(id, query) }
+    }
+
+    /// Picks a layer with exponentially decaying probability, so each layer holds about 1/m of the one below
+    fn random_level(&mut self) -> usize {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let random = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D);
+
+        // Uniform in (0, 1]
+        let uniform = ((random >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let level_multiplier = 1.0 / (self.params.m.max(2) as f64).ln();
+        ((-uniform.ln() * level_multiplier) as usize).min(MAX_LAYERS - 1)
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    /// Best-first walk of one layer, keeping the `ef` closest nodes seen. Returns them best first.
+    fn search_layer(&self, query: &[f32], entry: &[Neighbour], ef: usize, layer: usize) -> Vec<Neighbour> {
+        let mut visited: HashSet<u32> = entry.iter().map(|n| n.id).collect();
+        let mut candidates: BinaryHeap<Neighbour> = entry.iter().copied().collect();
+        let mut found: BinaryHeap<Reverse<Neighbour>> = entry.iter().copied().map(Reverse).collect();
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map_or(f32::MIN, |Reverse(n)| n.similarity);
+            if candidate.similarity < worst && found.len() >= ef {
+                // Everything left is further away than what we already have
+                break;
+            }
+
+            for &link in &self.links[candidate.id as usize][layer] {
+                if !visited.insert(link) {
+                    continue;
+                }
+
+                let neighbour = self.neighbour(link, query);
+                let worst = found.peek().map_or(f32::MIN, |Reverse(n)| n.similarity);
+                if found.len() < ef || neighbour.similarity > worst {
+                    candidates.push(neighbour);
+                    found.push(Reverse(neighbour));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        sorted_best_first(found)
+    }
+
+    /// Chooses up to `m` links from `candidates` (best first). A candidate is skipped while it is
+    /// closer to an already chosen link than to the base node, which keeps links pointing in
+    /// different directions; skipped ones fill any remaining slots.
+    fn select_neighbours(&self, candidates: &[Neighbour], m: usize) -> Vec<Neighbour> {
+        let mut selected: Vec<Neighbour> = Vec::with_capacity(m);
+        for candidate in candidates {
+            if selected.len() >= m {
+                return selected;
+            }
+            let candidate_vector = self.vectors.get(candidate.id);
+            let diverse = selected
+                .iter()
+                .all(|chosen| self.vectors.similarity(chosen.id, candidate_vector) < candidate.similarity);
+            if diverse {
+                selected.push(*candidate);
+            }
+        }
+
+        for candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            if !selected.iter().any(|chosen| chosen.id == candidate.id) {
+                selected.push(*candidate);
+            }
+        }
+        selected
+    }
+
+    /// Adds a link from `from` to `to`, pruning `from`'s links on that layer if it has too many
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        let max_links = self.max_links(layer);
+        self.links[from as usize][layer].push(to);
+        if self.links[from as usize][layer].len() <= max_links {
+            return;
+        }
+
+        let base = self.vectors.get(from);
+        let mut candidates: Vec<Neighbour> = self.links[from as usize][layer]
+            .iter()
+            .map(|&id| Neighbour { id, similarity: self.vectors.similarity(id, base) })
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+
+        let kept = self.select_neighbours(&candidates, max_links);
+        self.links[from as usize][layer] = kept.iter().map(|n| n.id).collect();
+    }
+}
+
+/// The links of a saved `Hnsw`, as read back from disk
+pub struct HnswGraph {
+    m: usize,
+    ef_construction: usize,
+    entry_point: Option<u32>,
+    links: Vec<Vec<Vec<u32>>>,
+}
+
+impl HnswGraph {
+    /// Reads what `Hnsw::write_graph` wrote for `nodes` vectors, rejecting links to nodes that don't exist
+    pub fn read_from(reader: &mut impl Read, nodes: usize) -> io::Result<Self> {
+        let corrupt = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt HNSW graph: {}", what));
+
+        let m = read_u32(reader)? as usize;
+        let ef_construction = read_u32(reader)? as usize;
+        let entry_point = match read_u32(reader)? {
+            NO_ENTRY_POINT => None,
+            id if (id as usize) < nodes => Some(id),
+            _ => return Err(corrupt("entry point out of range")),
+        };
+
+        let mut links = Vec::with_capacity(nodes);
+        for _ in 0..nodes {
+            let mut layer_count = [0u8; 1];
+            reader.read_exact(&mut layer_count)?;
+            if layer_count[0] == 0 || layer_count[0] as usize > MAX_LAYERS {
+                return Err(corrupt("bad layer count"));
+            }
+
+            let mut layers = Vec::with_capacity(layer_count[0] as usize);
+            for _ in 0..layer_count[0] {
+                let mut count = [0u8; 2];
+                reader.read_exact(&mut count)?;
+                let layer = (0..u16::from_be_bytes(count))
+                    .map(|_| read_u32(reader))
+                    .collect::<io::Result<Vec<u32>>>()?;
+                if layer.iter().any(|&id| id as usize >= nodes) {
+                    return Err(corrupt("link out of range"));
+                }
+                layers.push(layer);
+            }
+            links.push(layers);
+        }
+
+        // Lookups index links by layer, so every link must point at a node that lives on that layer
+        for layers in &links {
+            for (layer, targets) in layers.iter().enumerate() {
+                if targets.iter().any(|&id| links[id as usize].len() <= layer) {
+                    return Err(corrupt("link to a node missing from its layer"));
+                }
+            }
+        }
+        if entry_point.is_none() && nodes > 0 {
+            return Err(corrupt("no entry point"));
+        }
+
+        Ok(Self { m, ef_construction, entry_point, links })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    // Eight independent sums let the compiler use SIMD; a single running sum can't be reordered
+    let mut sums = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder()).map(|(x, y)| x * y).sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for lane in 0..8 {
+            sums[lane] += x[lane] * y[lane];
+        }
+    }
+    sums.iter().sum::<f32>() + tail
+}
+
+/// Scales a vector to length 1. The zero vector stays zero, and so is dissimilar to everything.
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+fn sorted_best_first(heap: BinaryHeap<Reverse<Neighbour>>) -> Vec<Neighbour> {
+    // The min-heap's ascending order of `Reverse` is descending similarity
+    heap.into_sorted_vec().into_iter().map(|Reverse(n)| n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIMENSION: usize = 16;
+    const PARAMS: HnswParams = HnswParams { m: 8, ef_construction: 64, ef_search: 32 };
+
+    /// Deterministic xorshift vectors in [-1, 1)
+    fn random_vectors(count: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut state = seed;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+        };
+        (0..count).map(|_| (0..DIMENSION).map(|_| next()).collect()).collect()
+    }
+
+    fn built(vectors: &[Vec<f32>]) -> Hnsw {
+        let mut hnsw = Hnsw::new(DIMENSION, PARAMS);
+        for vector in vectors {
+            hnsw.insert(vector);
+        }
+        hnsw
+    }
+
+    fn graph_bytes(hnsw: &Hnsw) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        hnsw.write_graph(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn hnsw_recall_matches_the_flat_index() {
+        let vectors = random_vectors(2000, 0x5EED);
+        let queries = random_vectors(100, 0xC0FFEE);
+        let hnsw = built(&vectors);
+        let mut flat = FlatIndex::new(DIMENSION);
+        for vector in &vectors {
+            flat.insert(vector);
+        }
+
+        let hits = queries.iter().filter(|q| hnsw.search(q, 1)[0].id == flat.search(q, 1)[0].id).count();
+        assert!(hits >= 95, "recall {}%", hits);
+    }
+
+    #[test]
+    fn ef_search_trades_recall_for_work() {
+        let vectors = random_vectors(2000, 0x5EED);
+        let queries = random_vectors(100, 0xC0FFEE);
+        let hnsw = built(&vectors);
+        let exact = {
+            let mut flat = FlatIndex::new(DIMENSION);
+            for vector in &vectors {
+                flat.insert(vector);
+            }
+            flat
+        };
+        let recall = |ef: usize| {
+            let hits = queries.iter().filter(|q| hnsw.search_with_ef(q, 1, ef)[0].id == exact.search(q, 1)[0].id);
+            hits.count()
+        };
+
+        assert!(recall(256) >= recall(1));
+        assert_eq!(hnsw.search(&queries[0], 3), hnsw.search_with_ef(&queries[0], 3, PARAMS.ef_search));
+    }
+
+    #[test]
+    fn saved_graph_round_trips() {
+        let vectors = random_vectors(300, 7);
+        let hnsw = built(&vectors);
+
+        let bytes = graph_bytes(&hnsw);
+        let graph = HnswGraph::read_from(&mut bytes.as_slice(), vectors.len()).unwrap();
+        let restored = Hnsw::from_graph(DIMENSION, PARAMS, vectors.iter().map(Vec::as_slice), graph).unwrap();
+
+        assert_eq!(graph_bytes(&restored), bytes);
+        for query in random_vectors(20, 11) {
+            assert_eq!(restored.search(&query, 5), hnsw.search(&query, 5));
+        }
+    }
+
+    #[test]
+    fn saved_graph_with_other_params_is_not_reused() {
+        let vectors = random_vectors(50, 7);
+        let bytes = graph_bytes(&built(&vectors));
+        let graph = HnswGraph::read_from(&mut bytes.as_slice(), vectors.len()).unwrap();
+
+        let other = HnswParams { m: 12, ..PARAMS };
+        assert!(Hnsw::from_graph(DIMENSION, other, vectors.iter().map(Vec::as_slice), graph).is_none());
+    }
+
+    #[test]
+    fn corrupt_graphs_are_rejected() {
+        let vectors = random_vectors(50, 7);
+        let bytes = graph_bytes(&built(&vectors));
+        let read = |bytes: &[u8]| HnswGraph::read_from(&mut &bytes[..], vectors.len()).map(|_| ()).map_err(|e| e.kind());
+
+        // m, ef_construction, then the entry point
+        let mut out_of_range = bytes.clone();
+        out_of_range[8..12].copy_from_slice(&50u32.to_be_bytes());
+        assert_eq!(read(&out_of_range), Err(io::ErrorKind::InvalidData));
+
+        // The layer count of the first node follows the header
+        let mut no_layers = bytes.clone();
+        no_layers[12] = 0;
+        assert_eq!(read(&no_layers), Err(io::ErrorKind::InvalidData));
+
+        let mut too_many_layers = bytes.clone();
+        too_many_layers[12] = MAX_LAYERS as u8 + 1;
+        assert_eq!(read(&too_many_layers), Err(io::ErrorKind::InvalidData));
+
+        assert_eq!(read(&bytes[..bytes.len() - 1]), Err(io::ErrorKind::UnexpectedEof));
+        assert_eq!(read(&bytes), Ok(()));
+    }
+
+    #[test]
+    fn chunked_vec_clones_stay_unchanged() {
+        let mut live: ChunkedVec<usize> = (0..CHUNK_LEN + 10).collect();
+        let snapshot = live.clone();
+
+        live[3] = 0;
+        live[CHUNK_LEN + 1] = 0;
+        live.push(7);
+
+        assert_eq!(snapshot.len(), CHUNK_LEN + 10);
+        assert_eq!((snapshot[3], snapshot[CHUNK_LEN + 1]), (3, CHUNK_LEN + 1));
+        assert_eq!((live[3], live[CHUNK_LEN + 1], live[CHUNK_LEN + 10]), (0, 0, 7));
+        assert!(snapshot.iter().copied().eq(0..CHUNK_LEN + 10));
+    }
+}
@@ -1,99 +1,464 @@
 pub mod embed;
+pub mod index;
+pub mod mode;
+pub mod persist;
+pub mod scope;
+pub mod scoring;
 pub mod store;
 
-use sysrag_common::ipc::AnomalyReport;
+use sysrag_common::events::{ExecEvent, KernelEvent};
+use sysrag_common::ipc::{AnomalyReport, BaselineEntry, BaselineOrigin, EngineMode, ModeStatus, TriageState};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
+use crate::config::{RagConfig, ScoringConfig};
+
 use self::embed::LogEmbedder;
+use self::mode::{ModeState, SavedMode};
+use self::persist::BaselineSnapshot;
+use self::scope::Scopes;
+use self::scoring::{Executable, Executables};
 use self::store::VectorStore;
 
+/// Requests from the IPC server, answered by the main loop between events
+pub enum EngineCommand {
+    GetMode { reply: oneshot::Sender<ModeStatus> },
+    SetMode { mode: EngineMode, learning_secs: Option<u64>, reply: oneshot::Sender<ModeStatus> },
+    /// Accept an anomaly's command and executable into the baselines that flagged it
+    Approve { anomaly: Box<AnomalyReport>, reply: oneshot::Sender<anyhow::Result<()>> },
+}
+
 /// The central AI engine that orchestrates embeddings and vector math
 pub struct RagEngine {
     embedder: LogEmbedder,
+    /// The global baseline, judging execs whose scope hasn't learned enough yet
     store: VectorStore,
+    scopes: Scopes,
+    /// The executables behind the learned commands
+    executables: Executables,
+    anomaly_threshold: f32,
+    scoring: ScoringConfig,
+    mode: ModeState,
+    /// Where checkpoints go, `None` if the baseline lives in memory only
+    baseline_path: Option<PathBuf>,
+    /// The baseline changed since the last checkpoint
+    dirty: bool,
 }
 
 impl RagEngine {
     /// Initializes the AI models and the local vector database
-    pub fn new() -> Result<Self, anyhow::Error> {
+    pub fn new(config: &RagConfig) -> Result<Self, anyhow::Error> {
         println!("Initializing RAG Engine and loading local AI models...");
         
         let mut embedder = LogEmbedder::new()?;
-        // We set a strict anomaly threshold. 
-        // Anything with a similarity below 0.2 is flagged.
-        let mut store = VectorStore::new(0.2); 
+        // Pick up where the last run left off
+        let baseline_path = config.persist_baseline.then(|| config.baseline_path.clone());
+        let restored = match baseline_path.as_deref() {
+            Some(path) => restore_baseline(path, &mut embedder, config)?,
+            None => None,
+        };
+        let Restored { mut store, scopes, executables, mode: saved_mode } = restored.unwrap_or_else(|| Restored {
+            store: VectorStore::new(embedder.dimension(), &config.index),
+            scopes: Vec::new(),
+            executables: Vec::new(),
+            mode: None,
+        });
+        let scopes = Scopes::new(config, embedder.dimension(), scopes);
+        if !config.scope.by.is_empty() {
+            println!(
+                "🗂️ Scoped baselines by {}, {} known so far",
+                Scopes::fields(&config.scope), scopes.len()
+            );
+        }
 
-        // --- THE FIX: Seed the Baseline ---
+        // --- Seed the Baseline ---
         // Pre-load the VectorStore with background noise so it doesn't freak out on boot
-        let safe_commands = vec![
-            "systemd", "crond", "NetworkManager", "bash", "sh", 
-            "python3", "gnome-shell", "polkitd", "Xwayland", "env",
-            "spotify", "flatpak", "gio-launch-desktop", "(sd-worker)",
-            "run-parts", "0anacron", "ThreadPoolSingl", "(tmpfiles)",
-            "gsd-xsettings", "p11-kit", "get-scale-facto", "ollama", "sh"
-        ];
-
-        println!("🌱 Seeding baseline with {} standard Linux processes...", safe_commands.len());
-        
-        for cmd in safe_commands {
-            if let Ok(vector) = embedder.embed_log(cmd) {
-                // Add these directly to the store before we process any real logs
-                store.add_to_baseline(cmd, vector);
+        if store.is_empty() {
+            println!("🌱 Seeding baseline with {} standard Linux processes...", config.seed_commands.len());
+
+            let now = current_timestamp();
+            for cmd in &config.seed_commands {
+                if let Ok(vector) = embedder.embed_log(cmd) {
+                    // Add these directly to the store before we process any real logs
+                    store.add_to_baseline(cmd, vector, BaselineOrigin::Seed, now);
+                }
             }
         }
         // -----------------------------------
 
-        Ok(Self { embedder, store })
+        let now = current_timestamp();
+        let mut mode = ModeState::new(&config.mode, saved_mode, now);
+        // Enforcing without executables on record would flag every binary, seeds included, until it
+        // was promoted. That is the case for a fresh baseline and one saved before executable scoring.
+        let scoring = &config.scoring;
+        let scores_executables = scoring.frequency_weight + scoring.recency_weight + scoring.uid_weight > 0.0;
+        if executables.is_empty() && scores_executables && mode.mode() == EngineMode::Enforcement {
+            println!("🎓 No executables on record yet, learning which executables are normal before enforcing");
+            mode.set(EngineMode::Learning, None, now);
+        }
+        match (mode.mode(), mode.saved().learning_until) {
+            (EngineMode::Learning, Some(until)) => println!(
+                "🎓 Learning mode: everything seen joins the baseline for another {}h",
+                until.saturating_sub(now).div_ceil(3600)
+            ),
+            (EngineMode::Learning, None) => {
+                println!("🎓 Learning mode: everything seen joins the baseline until an operator switches to enforcement")
+            }
+            (EngineMode::Enforcement, _) => {
+                println!("🛡️ Enforcement mode: anomalies join the baseline only when approved or seen repeatedly")
+            }
+        }
+
+        Ok(Self {
+            embedder,
+            store,
+            scopes,
+            executables: Executables::new(executables),
+            anomaly_threshold: config.anomaly_threshold,
+            scoring: config.scoring.clone(),
+            mode,
+            baseline_path,
+            dirty: true,
+        })
+    }
+
+    /// A copy of the baseline to write to disk, or `None` if persistence is off or nothing changed
+    /// since the last checkpoint
+    pub fn checkpoint(&mut self) -> Option<BaselineSnapshot> {
+        let path = self.baseline_path.clone()?;
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+
+        let mut scopes: Vec<_> = self.scopes.iter().map(|(key, store)| (key.clone(), store.snapshot())).collect();
+        scopes.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(BaselineSnapshot {
+            path,
+            model_id: self.embedder.model_id().to_string(),
+            dimension: self.embedder.dimension(),
+            mode: self.mode.saved(),
+            global: self.store.snapshot(),
+            scope_fields: self.scopes.fields_in_use(),
+            scopes,
+            executables: self.executables.snapshot(),
+        })
+    }
+
+    /// Answers a request from the IPC server. Returns true if the mode or the baseline changed,
+    /// which should then be saved right away rather than at the next checkpoint.
+    pub fn handle_command(&mut self, command: EngineCommand) -> bool {
+        let now = current_timestamp();
+        let learning_ended = self.mode.end_learning_if_due(now);
+        if learning_ended {
+            self.dirty = true;
+        }
+
+        // A client that hung up before the answer doesn't need it
+        match command {
+            EngineCommand::GetMode { reply } => {
+                let _ = reply.send(self.mode.status(now));
+                learning_ended
+            }
+            EngineCommand::SetMode { mode, learning_secs, reply } => {
+                self.mode.set(mode, learning_secs, now);
+                self.dirty = true;
+                println!("🔀 Engine switched to {} mode", mode);
+                let _ = reply.send(self.mode.status(now));
+                true
+            }
+            EngineCommand::Approve { anomaly, reply } => {
+                let approved = self.approve(&anomaly, now);
+                let changed = approved.is_ok();
+                let _ = reply.send(approved);
+                changed || learning_ended
+            }
+        }
+    }
+
+    /// Adds an anomaly's command and executable to the baseline on an operator's word, including
+    /// the scope that flagged it
+    fn approve(&mut self, anomaly: &AnomalyReport, now: u64) -> Result<(), anyhow::Error> {
+        let command = &anomaly.command;
+        let scope = anomaly.scope.as_deref();
+        let vector = self.embedder.embed_log(command)?;
+        let entry = BaselineEntry::new(command, BaselineOrigin::OperatorApproved, now);
+        // Reports from before executables were canonicalized only have the path handed to execve
+        let exe = match &anomaly.score {
+            Some(score) if !score.executable.is_empty() => score.executable.as_str(),
+            _ => executable_key(&anomaly.exe, command),
+        };
+        self.learn(scope, entry, Some(vector), exe, anomaly.uid);
+        self.mode.forget(scope, command);
+        match scope {
+            Some(scope) => println!("✅ {} approved into the baseline of {}", command, scope),
+            None => println!("✅ {} approved into the baseline", command),
+        }
+        Ok(())
+    }
+
+    /// Adds an entry to the global baseline and to its scope's, merging it into the existing
+    /// entries for the same command, and records the executable behind it. `vector` may only be
+    /// `None` if both baselines already know the command.
+    fn learn(&mut self, scope: Option<&str>, entry: BaselineEntry, vector: Option<Vec<f32>>, exe: &str, uid: u32) {
+        self.executables.record(exe, uid, entry.count, entry.first_seen, entry.last_seen);
+        self.dirty = true;
+
+        let Some(vector) = vector else {
+            if let Some(store) = scope.and_then(|key| self.scopes.get_mut(key)) {
+                store.merge(&entry);
+            }
+            self.store.merge(&entry);
+            return;
+        };
+        if let Some(key) = scope {
+            self.scopes.get_or_create(key, entry.last_seen).insert(entry.clone(), vector.clone());
+        }
+        self.store.insert(entry, vector);
     }
 
-    /// Processes a raw log from the eBPF kernel sensor.
+    /// Number of distinct commands currently held in the baseline
+    pub fn baseline_size(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Processes a typed event from a kernel sensor.
+    /// `parent` is the command name of an exec's parent, if known, for baselines scoped by it, and
+    /// `executable` the canonical path of the program it ran, empty if unknown.
     /// Returns `Some(AnomalyReport)` if it's suspicious, or `None` if it's normal.
-    pub fn process_log(&mut self, raw_log: &str) -> Result<Option<AnomalyReport>, anyhow::Error> {
-        // 1. Parse the raw log
-        let pid_str = extract_value(raw_log, "pid=").unwrap_or("0".to_string());
-        let pid: u32 = pid_str.parse().unwrap_or(0);
-        let command = extract_value(raw_log, "comm=").unwrap_or("unknown".to_string());
+    pub fn process_event(
+        &mut self,
+        event: &KernelEvent,
+        parent: Option<&str>,
+        executable: &str,
+    ) -> Result<Option<AnomalyReport>, anyhow::Error> {
+        match event {
+            KernelEvent::Exec(exec) => self.process_exec(exec, parent, executable, event),
+            // Connections and file accesses are tracked by the daemon state and attached to exec anomalies
+            // Fork and exit only feed the process table
+            KernelEvent::Connect(_)
+            | KernelEvent::FileOpen(_)
+            | KernelEvent::Fork(_)
+            | KernelEvent::Exit(_) => Ok(None),
+        }
+    }
+
+    fn process_exec(
+        &mut self,
+        exec: &ExecEvent,
+        parent: Option<&str>,
+        executable: &str,
+        event: &KernelEvent,
+    ) -> Result<Option<AnomalyReport>, anyhow::Error> {
+        let command = &exec.comm;
+        let exe = executable_key(executable, command);
+        let now = current_timestamp();
+        if self.mode.end_learning_if_due(now) {
+            self.dirty = true;
+        }
+
+        // The exec's scope learns from it either way, but only judges it once it has seen enough
+        let scope = self.scopes.key(exec, parent);
+        if let Some(key) = &scope {
+            self.scopes.touch(key, now);
+        }
+        let judge = self.scopes.judge(scope.as_deref()).map(str::to_string);
+        let scope_store = scope.as_deref().and_then(|key| self.scopes.get(key));
+        let in_global = self.store.contains(command);
+        let in_scope = scope_store.is_some_and(|store| store.contains(command));
+        let known = if judge.is_some() { in_scope } else { in_global };
 
-        // 2. Convert the COMMAND (not the raw_log) into a mathematical vector.
+        // 1. Convert the COMMAND into a mathematical vector.
         // This ensures "systemd" matches our seeded "systemd" perfectly.
-        let vector = self.embedder.embed_log(&command)?;
+        // Text already in both baselines matches itself exactly, no need to embed it again,
+        // and a baseline that holds the command has its vector.
+        let vector = if in_global && (scope.is_none() || in_scope) {
+            None
+        } else {
+            let stored = self.store.vector_of(command).or_else(|| scope_store.and_then(|store| store.vector_of(command)));
+            match stored {
+                Some(vector) => Some(vector),
+                None => Some(self.embedder.embed_log(command)?),
+            }
+        };
+
+        // 2. Check it against the scope's baseline in the Vector Store, or the global one
+        let judge_store = judge.as_deref().and_then(|key| self.scopes.get(key)).unwrap_or(&self.store);
+        let (similarity, nearest) = match (&vector, known) {
+            (Some(vector), false) => {
+                let lookup = judge_store.lookup(vector);
+                (lookup.similarity, lookup.nearest.cloned())
+            }
+            _ => (1.0, judge_store.entry(command).cloned()),
+        };
 
-        // 3. Check it against our baseline in the Vector Store
-        let (is_anomaly, similarity_score) = self.store.check_anomaly(&command, &vector);
+        // 3. Discount it if the executable behind it is unfamiliar
+        let score = self.executables.score(&self.scoring, exe, similarity, now);
+        let learned = BaselineEntry::new(command, BaselineOrigin::Learned, now);
 
-        if is_anomaly {
+        // If even the highest match is lower than our threshold, flag it!
+        if score.total < self.anomaly_threshold {
             // It's an anomaly! Generate a report.
             let report = AnomalyReport {
                 id: Uuid::new_v4().to_string(), 
-                timestamp: current_timestamp(),
-                pid,
+                timestamp: now,
+                pid: exec.pid,
+                uid: exec.uid,
                 command: command.clone(),
-                similarity_score,
-                raw_log: raw_log.to_string(),
+                exe: exec.filename.clone(),
+                argv: exec.argv.clone(),
+                context: exec.context.clone(),
+                connections: Vec::new(),
+                file_accesses: Vec::new(),
+                ancestry: Vec::new(),
+                fidelity: exec.fidelity,
+                similarity_score: similarity,
+                score: Some(score),
+                nearest,
+                scope: judge.clone(),
+                raw_log: event.to_string(),
+                triage: TriageState::New,
+                investigation: None,
             };
 
-            // Add it to the baseline so it becomes part of the system's "memory"
-            self.store.add_to_baseline(&command, vector);
+            match self.mode.mode() {
+                // While learning, add it to the baseline so it becomes part of the system's "memory"
+                EngineMode::Learning => self.learn(scope.as_deref(), learned, vector, exe, exec.uid),
+                // While enforcing, it has to keep coming back before it counts as normal
+                EngineMode::Enforcement => {
+                    if let Some(sightings) = self.mode.sighting(judge.as_deref(), command, now) {
+                        println!(
+                            "📈 {} was seen {} times within the promotion window, adding it to the baseline",
+                            command, sightings.len()
+                        );
+                        let entry = BaselineEntry {
+                            count: sightings.len() as u64,
+                            first_seen: sightings.front().copied().unwrap_or(now),
+                            ..learned
+                        };
+                        self.learn(scope.as_deref(), entry, vector, exe, exec.uid);
+                    }
+                }
+            }
 
             return Ok(Some(report));
         }
 
-        // 4. If it's normal, just update the baseline and move on
-        self.store.add_to_baseline(&command, vector);
+        // 4. If it's normal, just update the baselines and move on
+        self.learn(scope.as_deref(), learned, vector, exe, exec.uid);
         Ok(None)
     }
 }
 
-// --- Helper Functions ---
+/// The executable an exec is scored by: its canonical path, or the command name when the source
+/// didn't capture one
+fn executable_key<'a>(executable: &'a str, command: &'a str) -> &'a str {
+    if executable.is_empty() {
+        command
+    } else {
+        executable
+    }
+}
 
-fn extract_value(log: &str, key: &str) -> Option<String> {
-    log.split_whitespace()
-        .find(|word| word.starts_with(key))
-        .map(|word| word.trim_start_matches(key).to_string())
+/// What `restore_baseline` got back from disk
+struct Restored {
+    store: VectorStore,
+    scopes: Vec<(String, VectorStore)>,
+    /// Empty if the file predates executable scoring
+    executables: Vec<(String, Executable)>,
+    mode: Option<SavedMode>,
 }
 
-fn current_timestamp() -> u64 {
+/// Loads the saved baselines and the mode they were in, or returns `None` to start from the seeds.
+/// A corrupt file is moved aside, and a baseline from another embedding model is re-embedded from
+/// its commands. Fails only if the file can't be read at all, so a full or failing disk doesn't
+/// cost the learned baseline.
+fn restore_baseline(
+    path: &Path,
+    embedder: &mut LogEmbedder,
+    config: &RagConfig,
+) -> Result<Option<Restored>, anyhow::Error> {
+    let saved = match persist::load(path) {
+        Ok(Some(saved)) => saved,
+        Ok(None) => return Ok(None),
+        Err(e) if !persist::is_corrupt(&e) => {
+            return Err(e.context(format!(
+                "Failed to read the saved baseline {}, fix the problem or move the file away to start over",
+                path.display()
+            )));
+        }
+        Err(e) => {
+            let mut aside = path.to_path_buf().into_os_string();
+            aside.push(".corrupt");
+            eprintln!(
+                "⚠️ Saved baseline {} is unreadable ({:#}), moving it to {} and starting from the seeds",
+                path.display(), e, aside.to_string_lossy()
+            );
+            if let Err(e) = fs::rename(path, &aside) {
+                eprintln!("⚠️ Failed to move the unreadable baseline aside: {}", e);
+            }
+            return Ok(None);
+        }
+    };
+
+    // Scopes keyed by other fields would never be looked up again
+    let mut scopes = saved.scopes;
+    let fields = Scopes::fields(&config.scope);
+    if !scopes.is_empty() && saved.scope_fields != fields {
+        println!(
+            "🗂️ Dropping {} saved baselines scoped by {:?}, scopes are now keyed by {:?}",
+            scopes.len(), saved.scope_fields, fields
+        );
+        scopes.clear();
+    }
+
+    let dimension = embedder.dimension();
+    if saved.model_id == embedder.model_id() && saved.dimension == dimension {
+        let restore = |saved| VectorStore::restore(dimension, &config.index, saved);
+        let store = restore(saved.global);
+        let scopes: Vec<_> = scopes.into_iter().map(|(key, saved)| (key, restore(saved))).collect();
+        println!(
+            "💾 Restored {} baseline entries and {} scoped baselines from {}",
+            store.len(), scopes.len(), path.display()
+        );
+        return Ok(Some(Restored { store, scopes, executables: saved.executables.unwrap_or_default(), mode: saved.mode }));
+    }
+
+    println!(
+        "♻️ Saved baseline was built with {} ({} dims), re-embedding its {} commands with {}...",
+        saved.model_id, saved.dimension, saved.global.entries.len(), embedder.model_id()
+    );
+    // Scopes mostly repeat commands of the global baseline, so each is embedded once
+    let mut vectors: HashMap<String, Vec<f32>> = HashMap::new();
+    let mut reembed = |saved: persist::SavedStore| {
+        let mut store = VectorStore::new(dimension, &config.index);
+        for (entry, _) in saved.entries {
+            let vector = match vectors.get(&entry.command) {
+                Some(vector) => vector.clone(),
+                None => match embedder.embed_log(&entry.command) {
+                    Ok(vector) => {
+                        vectors.insert(entry.command.clone(), vector.clone());
+                        vector
+                    }
+                    Err(_) => continue,
+                },
+            };
+            store.insert(entry, vector);
+        }
+        store
+    };
+    let store = reembed(saved.global);
+    let scopes = scopes.into_iter().map(|(key, saved)| (key, reembed(saved))).collect();
+    Ok(Some(Restored { store, scopes, executables: saved.executables.unwrap_or_default(), mode: saved.mode }))
+}
+
+// --- Helper Functions ---
+
+pub fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
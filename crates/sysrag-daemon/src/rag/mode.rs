@@ -0,0 +1,265 @@
+//! Learning and enforcement: whether anomalies may join the baseline on their own.
+//!
+//! While learning, everything the engine sees becomes part of the baseline. Once enforcing, an
+//! anomaly only does so when an operator approves it or it keeps recurring, so an attacker's tool
+//! doesn't become "normal" the second time it runs.
+
+use std::collections::{HashMap, VecDeque};
+use sysrag_common::ipc::{EngineMode, ModeStatus};
+
+use crate::config::ModeConfig;
+
+/// Anomalous commands tracked towards promotion before the one seen least recently is dropped
+const MAX_PENDING: usize = 4096;
+
+/// The mode as saved with the baseline, so a restart doesn't reopen a learning period
+#[derive(Debug, Clone, Copy)]
+pub struct SavedMode {
+    pub mode: EngineMode,
+    /// When learning ends on its own, `None` if it never does
+    pub learning_until: Option<u64>,
+}
+
+pub struct ModeState {
+    mode: EngineMode,
+    learning_until: Option<u64>,
+    learning_period_secs: u64,
+    promote_after_sightings: u32,
+    promotion_window_secs: u64,
+    /// When each anomalous command was seen within the promotion window, oldest first, keyed by
+    /// the scope that flagged it (`None` for the global baseline) and the command
+    sightings: HashMap<(Option<String>, String), VecDeque<u64>>,
+}
+
+impl ModeState {
+    /// Resumes the saved mode, or starts a fresh baseline in the configured one
+    pub fn new(config: &ModeConfig, saved: Option<SavedMode>, now: u64) -> Self {
+        let mut state = Self {
+            mode: config.initial,
+            learning_until: None,
+            learning_period_secs: config.learning_period_secs,
+            promote_after_sightings: config.promote_after_sightings,
+            promotion_window_secs: config.promotion_window_secs,
+            sightings: HashMap::new(),
+        };
+        match saved {
+            Some(saved) => {
+                state.mode = saved.mode;
+                state.learning_until = saved.learning_until;
+            }
+            None => state.set(config.initial, None, now),
+        }
+        state
+    }
+
+    pub fn mode(&self) -> EngineMode {
+        self.mode
+    }
+
+    pub fn saved(&self) -> SavedMode {
+        SavedMode { mode: self.mode, learning_until: self.learning_until }
+    }
+
+    /// Switches modes. Learning lasts `learning_secs`, or the configured period if `None`.
+    pub fn set(&mut self, mode: EngineMode, learning_secs: Option<u64>, now: u64) {
+        self.mode = mode;
+        self.learning_until = match mode {
+            EngineMode::Learning => {
+                let secs = learning_secs.unwrap_or(self.learning_period_secs);
+                (secs > 0).then(|| now.saturating_add(secs))
+            }
+            EngineMode::Enforcement => None,
+        };
+        // Sightings only count towards promotion while enforcing
+        self.sightings.clear();
+    }
+
+    /// Switches to enforcement once the learning period is over. Returns true if it did.
+    pub fn end_learning_if_due(&mut self, now: u64) -> bool {
+        let due = self.mode == EngineMode::Learning && self.learning_until.is_some_and(|until| now >= until);
+        if due {
+            println!("🛡️ Learning period over, the engine is now enforcing");
+            self.set(EngineMode::Enforcement, None, now);
+        }
+        due
+    }
+
+    /// Counts a sighting of an anomalous command while enforcing. Once it has been seen often
+    /// enough within the window, returns the times it was seen (oldest first) and stops tracking it.
+    /// Sightings count per scope, so a command recurring under one uid isn't promoted for another.
+    pub fn sighting(&mut self, scope: Option<&str>, command: &str, now: u64) -> Option<VecDeque<u64>> {
+        if self.promote_after_sightings == 0 {
+            return None;
+        }
+        let key = (scope.map(str::to_string), command.to_string());
+        if !self.sightings.contains_key(&key) && self.sightings.len() >= MAX_PENDING {
+            self.forget_stalest();
+        }
+
+        let times = self.sightings.entry(key.clone()).or_default();
+        times.push_back(now);
+        let window_start = now.saturating_sub(self.promotion_window_secs);
+        while times.front().is_some_and(|&seen| seen < window_start) {
+            times.pop_front();
+        }
+
+        if times.len() < self.promote_after_sightings as usize {
+            return None;
+        }
+        self.sightings.remove(&key)
+    }
+
+    /// Stops tracking a command that joined a baseline some other way
+    pub fn forget(&mut self, scope: Option<&str>, command: &str) {
+        self.sightings.remove(&(scope.map(str::to_string), command.to_string()));
+    }
+
+    pub fn status(&self, now: u64) -> ModeStatus {
+        let window_start = now.saturating_sub(self.promotion_window_secs);
+        ModeStatus {
+            mode: self.mode,
+            learning_until: self.learning_until,
+            promote_after_sightings: self.promote_after_sightings,
+            promotion_window_secs: self.promotion_window_secs,
+            pending_promotions: self
+                .sightings
+                .values()
+                .filter(|times| times.back().is_some_and(|&seen| seen >= window_start))
+                .count(),
+        }
+    }
+
+    fn forget_stalest(&mut self) {
+        let stalest = self
+            .sightings
+            .iter()
+            .min_by_key(|(_, times)| times.back().copied())
+            .map(|(key, _)| key.clone());
+        if let Some(key) = stalest {
+            self.sightings.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(initial: EngineMode, learning_period_secs: u64, promote_after_sightings: u32) -> ModeConfig {
+        ModeConfig { initial, learning_period_secs, promote_after_sightings, promotion_window_secs: 60 }
+    }
+
+    fn enforcing(promote_after_sightings: u32) -> ModeState {
+        ModeState::new(&config(EngineMode::Enforcement, 0, promote_after_sightings), None, 0)
+    }
+
+    #[test]
+    fn learning_ends_after_the_configured_period() {
+        let mut state = ModeState::new(&config(EngineMode::Learning, 100, 3), None, 1000);
+        assert_eq!(state.saved().learning_until, Some(1100));
+
+        assert!(!state.end_learning_if_due(1099));
+        assert_eq!(state.mode(), EngineMode::Learning);
+        assert!(state.end_learning_if_due(1100));
+        assert_eq!(state.mode(), EngineMode::Enforcement);
+        assert_eq!(state.saved().learning_until, None);
+        assert!(!state.end_learning_if_due(2000));
+    }
+
+    #[test]
+    fn learning_without_an_end_lasts_until_switched() {
+        let mut state = ModeState::new(&config(EngineMode::Learning, 0, 3), None, 1000);
+        assert_eq!(state.saved().learning_until, None);
+        assert!(!state.end_learning_if_due(u64::MAX));
+
+        // An explicit duration overrides the configured period, and 0 means no end
+        state.set(EngineMode::Learning, Some(50), 2000);
+        assert_eq!(state.saved().learning_until, Some(2050));
+        state.set(EngineMode::Learning, Some(0), 2000);
+        assert_eq!(state.saved().learning_until, None);
+
+        state.set(EngineMode::Enforcement, Some(50), 3000);
+        assert_eq!((state.mode(), state.saved().learning_until), (EngineMode::Enforcement, None));
+    }
+
+    #[test]
+    fn saved_mode_is_resumed() {
+        let saved = SavedMode { mode: EngineMode::Learning, learning_until: Some(500) };
+        let mut state = ModeState::new(&config(EngineMode::Enforcement, 100, 3), Some(saved), 1000);
+
+        assert_eq!(state.mode(), EngineMode::Learning);
+        assert!(state.end_learning_if_due(1000));
+    }
+
+    #[test]
+    fn switching_modes_forgets_sightings() {
+        let mut state = enforcing(2);
+        assert!(state.sighting(None, "nc", 10).is_none());
+
+        state.set(EngineMode::Enforcement, None, 20);
+        assert!(state.sighting(None, "nc", 30).is_none());
+    }
+
+    #[test]
+    fn promoted_at_exactly_the_configured_sightings() {
+        let mut state = enforcing(3);
+        assert!(state.sighting(None, "nc", 0).is_none());
+        assert!(state.sighting(None, "nc", 10).is_none());
+        assert_eq!(state.status(10).pending_promotions, 1);
+
+        assert_eq!(state.sighting(None, "nc", 20), Some(VecDeque::from([0, 10, 20])));
+        // Promotion starts the count over
+        assert_eq!(state.status(20).pending_promotions, 0);
+        assert!(state.sighting(None, "nc", 30).is_none());
+    }
+
+    #[test]
+    fn sightings_outside_the_window_expire() {
+        let mut state = enforcing(3);
+        assert!(state.sighting(None, "nc", 0).is_none());
+        assert!(state.sighting(None, "nc", 10).is_none());
+        // The window is 60 seconds, so both earlier sightings are gone
+        assert!(state.sighting(None, "nc", 100).is_none());
+        assert!(state.sighting(None, "nc", 110).is_none());
+        assert_eq!(state.sighting(None, "nc", 120), Some(VecDeque::from([100, 110, 120])));
+        assert_eq!(state.status(1000).pending_promotions, 0);
+    }
+
+    #[test]
+    fn sightings_count_per_scope() {
+        let mut state = enforcing(2);
+        assert!(state.sighting(Some("uid=1000"), "nc", 0).is_none());
+        assert!(state.sighting(Some("uid=33"), "nc", 1).is_none());
+        assert!(state.sighting(None, "nc", 2).is_none());
+        assert!(state.sighting(Some("uid=33"), "nc", 3).is_some());
+
+        state.forget(Some("uid=1000"), "nc");
+        assert!(state.sighting(Some("uid=1000"), "nc", 4).is_none());
+    }
+
+    #[test]
+    fn zero_sightings_never_promote() {
+        let mut state = enforcing(0);
+        for now in 0..10 {
+            assert!(state.sighting(None, "nc", now).is_none());
+        }
+        assert_eq!(state.status(10).pending_promotions, 0);
+    }
+
+    #[test]
+    fn stalest_command_is_dropped_past_max_pending() {
+        let config = ModeConfig { promotion_window_secs: 1_000_000, ..config(EngineMode::Enforcement, 0, 2) };
+        let mut state = ModeState::new(&config, None, 0);
+        let newest = MAX_PENDING as u64;
+        for i in 0..newest {
+            assert!(state.sighting(None, &format!("cmd{}", i), i).is_none());
+        }
+        assert_eq!(state.status(newest).pending_promotions, MAX_PENDING);
+
+        assert!(state.sighting(None, "newcomer", newest).is_none());
+        assert_eq!(state.status(newest).pending_promotions, MAX_PENDING);
+        // "cmd0" was seen least recently and made way, so this is its first sighting again
+        assert!(state.sighting(None, "cmd0", newest + 1).is_none());
+        assert!(state.sighting(None, "newcomer", newest + 2).is_some());
+    }
+}
@@ -0,0 +1,542 @@
+//! On-disk format of the baseline, so the engine keeps what it learned across restarts.
+//!
+//! A baseline file is a gzip stream (whose CRC catches corruption) holding a header, the global
+//! baseline, the scoped baselines and the executables they learned:
+//!
+//! ```text
+//! "SYSRAGBL" | format version (u32 BE) | model id length (u16 BE) | model id (UTF-8)
+//! dimension (u32 BE) | mode (u8: 0 learning, 1 enforcement) | learning until (u64 BE, 0 = no end)
+//! global baseline
+//! scope fields length (u16 BE) | scope fields (UTF-8, e.g. "uid,parent") | scope count (u32 BE)
+//! scope key length (u16 BE) | scope key (UTF-8) | scoped baseline
+//! ...
+//! executable count (u64 BE)
+//! path length (u16 BE) | path (UTF-8) | count (u64 BE) | first seen (u64 BE) | last seen (u64 BE)
+//! uid count (u16 BE) | uid count × u32 BE
+//! ...
+//! ```
+//!
+//! where each baseline is
+//!
+//! ```text
+//! entry count (u64 BE)
+//! command length (u16 BE) | command (UTF-8) | count (u64 BE) | first seen (u64 BE)
+//! last seen (u64 BE) | origin (u8: 0 seed, 1 learned, 2 operator approved) | dimension × f32 BE
+//! ...
+//! index kind (u8: 0 linear, 1 HNSW) | HNSW links, see `Hnsw::write_graph`
+//! ```
+//!
+//! Vectors are only comparable when they come from the same embedding model, so the header records
+//! which model produced them. The commands are kept too, so a baseline can be re-embedded.
+//!
+//! Older formats are still read. Before format 6 there are no executables, and before 5 there is
+//! only the global baseline. Format 1 has no index
+//! section, and neither 1 nor 2 has the per-entry metadata: their repeated commands are merged
+//! into one entry, counted, and dated to the file's last modification. Before format 4 the mode
+//! wasn't saved, so the configured initial mode applies.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use sysrag_common::ipc::{BaselineEntry, BaselineOrigin, EngineMode};
+
+use super::index::{ChunkedVec, HnswGraph};
+use super::mode::SavedMode;
+use super::scoring::Executable;
+use super::store::SearchIndex;
+
+const MAGIC: &[u8; 8] = b"SYSRAGBL";
+const FORMAT_VERSION: u32 = 6;
+/// Oldest format this daemon still reads
+const OLDEST_FORMAT_VERSION: u32 = 1;
+
+const INDEX_LINEAR: u8 = 0;
+const INDEX_HNSW: u8 = 1;
+
+/// Anything longer means the file is corrupt; real commands are at most a few hundred bytes
+const MAX_COMMAND_LEN: u16 = 4096;
+const MAX_DIMENSION: u32 = 16384;
+const MAX_UIDS: u16 = 1024;
+
+/// A baseline file read back from disk
+pub struct BaselineFile {
+    pub model_id: String,
+    pub dimension: usize,
+    /// `None` in formats that didn't save it
+    pub mode: Option<SavedMode>,
+    pub global: SavedStore,
+    /// The `[rag.scope] by` fields the scopes were keyed by
+    pub scope_fields: String,
+    pub scopes: Vec<(String, SavedStore)>,
+    /// `None` in formats that didn't save them
+    pub executables: Option<Vec<(String, Executable)>>,
+}
+
+/// One baseline read back from disk
+pub struct SavedStore {
+    /// One per distinct command, with its vector
+    pub entries: Vec<(BaselineEntry, Vec<f32>)>,
+    /// Saved HNSW links, if the baseline was indexed with HNSW
+    pub graph: Option<HnswGraph>,
+}
+
+/// A copy of the baselines taken by the engine, written out without holding up the event loop
+pub struct BaselineSnapshot {
+    pub path: PathBuf,
+    pub model_id: String,
+    pub dimension: usize,
+    pub mode: SavedMode,
+    pub global: StoreSnapshot,
+    pub scope_fields: String,
+    pub scopes: Vec<(String, StoreSnapshot)>,
+    pub executables: Vec<(String, Executable)>,
+}
+
+/// A copy of one baseline, sharing its unchanged chunks with the live one
+pub struct StoreSnapshot {
+    /// The entry behind each vector of `index`
+    pub entries: ChunkedVec<BaselineEntry>,
+    pub index: SearchIndex,
+}
+
+impl BaselineSnapshot {
+    /// Number of entries across all baselines
+    pub fn entry_count(&self) -> usize {
+        self.global.entries.len() + self.scopes.iter().map(|(_, scope)| scope.entries.len()).sum::<usize>()
+    }
+
+    /// Writes the snapshot next to `path` and renames it into place, so a crash mid-write
+    /// leaves the previous checkpoint intact
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            create_private_dir(dir)?;
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let file = create_private_file(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        let mut writer = BufWriter::new(GzEncoder::new(file, Compression::fast()));
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        write_string(&mut writer, &self.model_id)?;
+        writer.write_all(&(self.dimension as u32).to_be_bytes())?;
+        writer.write_all(&[mode_code(self.mode.mode)])?;
+        writer.write_all(&self.mode.learning_until.unwrap_or(0).to_be_bytes())?;
+
+        write_store(&mut writer, &self.global)?;
+        write_string(&mut writer, &self.scope_fields)?;
+        writer.write_all(&(self.scopes.len() as u32).to_be_bytes())?;
+        for (key, scope) in &self.scopes {
+            write_string(&mut writer, key)?;
+            write_store(&mut writer, scope)?;
+        }
+        writer.write_all(&(self.executables.len() as u64).to_be_bytes())?;
+        for (path, executable) in &self.executables {
+            write_string(&mut writer, path)?;
+            writer.write_all(&executable.count.to_be_bytes())?;
+            writer.write_all(&executable.first_seen.to_be_bytes())?;
+            writer.write_all(&executable.last_seen.to_be_bytes())?;
+            writer.write_all(&(executable.uids.len() as u16).to_be_bytes())?;
+            for uid in &executable.uids {
+                writer.write_all(&uid.to_be_bytes())?;
+            }
+        }
+
+        let file = writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .finish()?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to move the baseline into {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Creates `dir` and any missing parents readable by root only. Directories that already
+/// exist keep their permissions, so a path under /tmp never tightens /tmp itself.
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))
+}
+
+/// Opens `path` for writing, truncated, readable by root only even if it already existed
+/// with looser permissions
+pub fn create_private_file(path: &Path) -> io::Result<File> {
+    let file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+fn write_store(writer: &mut impl Write, store: &StoreSnapshot) -> io::Result<()> {
+    writer.write_all(&(store.entries.len() as u64).to_be_bytes())?;
+
+    for (id, entry) in store.entries.iter().enumerate() {
+        write_string(writer, &entry.command)?;
+        writer.write_all(&entry.count.to_be_bytes())?;
+        writer.write_all(&entry.first_seen.to_be_bytes())?;
+        writer.write_all(&entry.last_seen.to_be_bytes())?;
+        writer.write_all(&[origin_code(entry.origin)])?;
+        for value in store.index.vector(id as u32) {
+            writer.write_all(&value.to_be_bytes())?;
+        }
+    }
+
+    match &store.index {
+        SearchIndex::Linear(_) => writer.write_all(&[INDEX_LINEAR]),
+        SearchIndex::Hnsw(hnsw) => {
+            writer.write_all(&[INDEX_HNSW])?;
+            hnsw.write_graph(writer)
+        }
+    }
+}
+
+/// Whether an error from `load` means the file itself is damaged, rather than the disk or its
+/// permissions failing to let it be read
+pub fn is_corrupt(error: &anyhow::Error) -> bool {
+    match error.chain().find_map(|cause| cause.downcast_ref::<io::Error>()) {
+        // Truncated files, bad gzip checksums and impossible values
+        Some(e) => matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput
+        ),
+        // Everything `load` rejects itself
+        None => true,
+    }
+}
+
+/// Reads the baseline at `path`. A missing file is `Ok(None)`; a damaged one is an error.
+pub fn load(path: &Path) -> Result<Option<BaselineFile>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    // Formats without per-entry dates get the time the file was last written
+    let modified = file
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |age| age.as_secs());
+    let mut reader = BufReader::new(GzDecoder::new(file));
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).context("Not a sysrag baseline")?;
+    if &magic != MAGIC {
+        anyhow::bail!("Not a sysrag baseline");
+    }
+
+    let version = u32::from_be_bytes(read_array(&mut reader)?);
+    if !(OLDEST_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        anyhow::bail!(
+            "Baseline format {}, this daemon reads formats {} to {}",
+            version, OLDEST_FORMAT_VERSION, FORMAT_VERSION
+        );
+    }
+
+    let model_id = read_string(&mut reader)?;
+    let dimension = u32::from_be_bytes(read_array(&mut reader)?);
+    if dimension == 0 || dimension > MAX_DIMENSION {
+        anyhow::bail!("Corrupt baseline: dimension {}", dimension);
+    }
+    let mode = if version >= 4 {
+        let [mode] = read_array(&mut reader)?;
+        let mode = mode_from_code(mode).with_context(|| format!("Corrupt baseline: unknown mode {}", mode))?;
+        let learning_until = u64::from_be_bytes(read_array(&mut reader)?);
+        Some(SavedMode { mode, learning_until: (learning_until != 0).then_some(learning_until) })
+    } else {
+        None
+    };
+
+    let global = read_store(&mut reader, version, dimension as usize, modified)?;
+    let mut scope_fields = String::new();
+    let mut scopes = Vec::new();
+    if version >= 5 {
+        scope_fields = read_string(&mut reader)?;
+        let count = u32::from_be_bytes(read_array(&mut reader)?);
+        for _ in 0..count {
+            let key = read_string(&mut reader)?;
+            scopes.push((key, read_store(&mut reader, version, dimension as usize, modified)?));
+        }
+    }
+
+    let executables = if version >= 6 {
+        let count = u64::from_be_bytes(read_array(&mut reader)?);
+        let mut executables = Vec::with_capacity(count.min(65536) as usize);
+        for _ in 0..count {
+            let path = read_string(&mut reader)?;
+            let count = u64::from_be_bytes(read_array(&mut reader)?);
+            let first_seen = u64::from_be_bytes(read_array(&mut reader)?);
+            let last_seen = u64::from_be_bytes(read_array(&mut reader)?);
+            let uid_count = u16::from_be_bytes(read_array(&mut reader)?);
+            if uid_count > MAX_UIDS {
+                anyhow::bail!("Corrupt baseline: {} uids for {:?}", uid_count, path);
+            }
+            let uids = (0..uid_count)
+                .map(|_| read_array(&mut reader).map(u32::from_be_bytes))
+                .collect::<io::Result<Vec<u32>>>()?;
+            executables.push((path, Executable { count, first_seen, last_seen, uids }));
+        }
+        Some(executables)
+    } else {
+        None
+    };
+
+    // Reading to the end makes the decoder verify the gzip checksum
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest)?;
+    if !rest.is_empty() {
+        anyhow::bail!("Corrupt baseline: {} bytes after the last entry", rest.len());
+    }
+
+    Ok(Some(BaselineFile {
+        model_id,
+        dimension: dimension as usize,
+        mode,
+        global,
+        scope_fields,
+        scopes,
+        executables,
+    }))
+}
+
+/// Reads one baseline. `modified` dates the entries of formats that didn't record when they were seen.
+fn read_store(reader: &mut impl Read, version: u32, dimension: usize, modified: u64) -> Result<SavedStore> {
+    let count = u64::from_be_bytes(read_array(reader)?);
+
+    // The count comes from the file, so don't trust it for the allocation
+    let mut entries: Vec<(BaselineEntry, Vec<f32>)> = Vec::with_capacity(count.min(65536) as usize);
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut merged = false;
+    for _ in 0..count {
+        let command = read_string(reader)?;
+        let entry = if version >= 3 {
+            let count = u64::from_be_bytes(read_array(reader)?);
+            let first_seen = u64::from_be_bytes(read_array(reader)?);
+            let last_seen = u64::from_be_bytes(read_array(reader)?);
+            let [origin] = read_array(reader)?;
+            let origin = origin_from_code(origin)
+                .with_context(|| format!("Corrupt baseline: unknown origin {}", origin))?;
+            BaselineEntry { command, count, first_seen, last_seen, origin }
+        } else {
+            BaselineEntry::new(&command, BaselineOrigin::Learned, modified)
+        };
+        let vector = (0..dimension)
+            .map(|_| read_array(reader).map(f32::from_be_bytes))
+            .collect::<io::Result<Vec<f32>>>()?;
+
+        if let Some(&position) = positions.get(&entry.command) {
+            if version >= 3 {
+                anyhow::bail!("Corrupt baseline: {:?} stored twice", entry.command);
+            }
+            entries[position].0.count += 1;
+            merged = true;
+            continue;
+        }
+        positions.insert(entry.command.clone(), entries.len());
+        entries.push((entry, vector));
+    }
+
+    let mut graph = if version >= 2 {
+        let [kind] = read_array(reader)?;
+        match kind {
+            INDEX_LINEAR => None,
+            INDEX_HNSW => Some(HnswGraph::read_from(reader, count as usize)?),
+            _ => anyhow::bail!("Corrupt baseline: unknown index kind {}", kind),
+        }
+    } else {
+        None
+    };
+    // Links of merged entries point at the wrong nodes, so the index has to be rebuilt
+    if merged {
+        graph = None;
+    }
+
+    Ok(SavedStore { entries, graph })
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A u16 BE length followed by that many bytes of UTF-8
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = u16::from_be_bytes(read_array(reader)?);
+    if len > MAX_COMMAND_LEN {
+        anyhow::bail!("Corrupt baseline: {} byte string", len);
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).context("Corrupt baseline: invalid UTF-8")
+}
+
+fn mode_code(mode: EngineMode) -> u8 {
+    match mode {
+        EngineMode::Learning => 0,
+        EngineMode::Enforcement => 1,
+    }
+}
+
+fn mode_from_code(code: u8) -> Option<EngineMode> {
+    match code {
+        0 => Some(EngineMode::Learning),
+        1 => Some(EngineMode::Enforcement),
+        _ => None,
+    }
+}
+
+fn origin_code(origin: BaselineOrigin) -> u8 {
+    match origin {
+        BaselineOrigin::Seed => 0,
+        BaselineOrigin::Learned => 1,
+        BaselineOrigin::OperatorApproved => 2,
+    }
+}
+
+fn origin_from_code(code: u8) -> Option<BaselineOrigin> {
+    match code {
+        0 => Some(BaselineOrigin::Seed),
+        1 => Some(BaselineOrigin::Learned),
+        2 => Some(BaselineOrigin::OperatorApproved),
+        _ => None,
+    }
+}
+
+/// A u16 BE length followed by the UTF-8, cut down to what `read_string` accepts
+fn write_string(writer: &mut impl Write, value: &str) -> io::Result<()> {
+    let value = truncate_command(value);
+    writer.write_all(&(value.len() as u16).to_be_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+/// Cuts a command down to what `load` accepts, on a character boundary
+fn truncate_command(command: &str) -> &str {
+    let mut end = command.len().min(MAX_COMMAND_LEN as usize);
+    while !command.is_char_boundary(end) {
+        end -= 1;
+    }
+    &command[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IndexConfig;
+    use crate::rag::store::VectorStore;
+
+    const DIMENSION: usize = 2;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sysrag-persist-{}-{}.bin", name, std::process::id()))
+    }
+
+    fn snapshot(path: PathBuf, mode: SavedMode) -> BaselineSnapshot {
+        let mut global = VectorStore::new(DIMENSION, &IndexConfig::Linear);
+        global.add_to_baseline("bash", vec![1.0, 0.0], BaselineOrigin::Seed, 100);
+        let mut scoped = VectorStore::new(DIMENSION, &IndexConfig::Linear);
+        scoped.add_to_baseline("nginx", vec![0.0, 1.0], BaselineOrigin::Learned, 200);
+
+        BaselineSnapshot {
+            path,
+            model_id: "test-model".to_string(),
+            dimension: DIMENSION,
+            mode,
+            global: global.snapshot(),
+            scope_fields: "uid".to_string(),
+            scopes: vec![("uid=33".to_string(), scoped.snapshot())],
+            executables: vec![(
+                "/usr/bin/bash".to_string(),
+                Executable { count: 3, first_seen: 100, last_seen: 150, uids: vec![0, 1000] },
+            )],
+        }
+    }
+
+    fn round_trip(mode: SavedMode) -> BaselineFile {
+        let path = temp_path(&format!("{:?}", mode.mode));
+        snapshot(path.clone(), mode).save().unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap().expect("The file was just written")
+    }
+
+    #[test]
+    fn mode_round_trips() {
+        let learning = SavedMode { mode: EngineMode::Learning, learning_until: Some(5000) };
+        let loaded = round_trip(learning).mode.unwrap();
+        assert_eq!((loaded.mode, loaded.learning_until), (EngineMode::Learning, Some(5000)));
+
+        let enforcing = SavedMode { mode: EngineMode::Enforcement, learning_until: None };
+        let loaded = round_trip(enforcing).mode.unwrap();
+        assert_eq!((loaded.mode, loaded.learning_until), (EngineMode::Enforcement, None));
+    }
+
+    #[test]
+    fn baselines_and_executables_round_trip() {
+        let loaded = round_trip(SavedMode { mode: EngineMode::Enforcement, learning_until: None });
+
+        assert_eq!(loaded.model_id, "test-model");
+        assert_eq!(loaded.dimension, DIMENSION);
+        let (entry, vector) = &loaded.global.entries[0];
+        assert_eq!((entry.command.as_str(), entry.origin, entry.first_seen), ("bash", BaselineOrigin::Seed, 100));
+        assert_eq!(vector, &[1.0, 0.0]);
+        assert_eq!(loaded.scope_fields, "uid");
+        assert_eq!(loaded.scopes.len(), 1);
+        assert_eq!(loaded.scopes[0].0, "uid=33");
+        assert_eq!(loaded.scopes[0].1.entries[0].0.command, "nginx");
+        let executables = loaded.executables.unwrap();
+        assert_eq!(executables[0].0, "/usr/bin/bash");
+        assert_eq!(executables[0].1.uids, [0, 1000]);
+    }
+
+    #[test]
+    fn format_4_mode_is_read() {
+        let path = temp_path("v4");
+        let mut writer = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        writer.write_all(MAGIC).unwrap();
+        writer.write_all(&4u32.to_be_bytes()).unwrap();
+        write_string(&mut writer, "test-model").unwrap();
+        writer.write_all(&(DIMENSION as u32).to_be_bytes()).unwrap();
+        writer.write_all(&[mode_code(EngineMode::Learning)]).unwrap();
+        writer.write_all(&7000u64.to_be_bytes()).unwrap();
+        // The global baseline, with one entry and no HNSW links
+        writer.write_all(&1u64.to_be_bytes()).unwrap();
+        write_string(&mut writer, "bash").unwrap();
+        for value in [2u64, 100, 200] {
+            writer.write_all(&value.to_be_bytes()).unwrap();
+        }
+        writer.write_all(&[origin_code(BaselineOrigin::Learned)]).unwrap();
+        for value in [0.6f32, 0.8] {
+            writer.write_all(&value.to_be_bytes()).unwrap();
+        }
+        writer.write_all(&[INDEX_LINEAR]).unwrap();
+        writer.finish().unwrap();
+
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap().unwrap();
+
+        let mode = loaded.mode.unwrap();
+        assert_eq!((mode.mode, mode.learning_until), (EngineMode::Learning, Some(7000)));
+        assert_eq!(loaded.global.entries[0].0.count, 2);
+        // Scopes and executables came later
+        assert!(loaded.scopes.is_empty());
+        assert!(loaded.executables.is_none());
+    }
+}
@@ -0,0 +1,236 @@
+//! Scoped baselines: besides the global baseline, one per uid, parent command, cgroup or a
+//! combination of them, so `nc` can be normal for a developer and alien for a web server worker.
+//!
+//! A scope only judges execs once it has seen enough distinct commands; until then the global
+//! baseline does, while the scope keeps learning. Once `max_scopes` exist, the one used least
+//! recently makes way for a new one.
+
+use std::collections::HashMap;
+use sysrag_common::events::ExecEvent;
+
+use super::store::VectorStore;
+use crate::config::{IndexConfig, RagConfig, ScopeConfig, ScopeField};
+
+pub struct Scopes {
+    config: ScopeConfig,
+    /// Settings for the baselines created on the fly
+    dimension: usize,
+    index: IndexConfig,
+    stores: HashMap<String, Scope>,
+    /// `max_scopes` was reached and logged
+    full: bool,
+}
+
+struct Scope {
+    store: VectorStore,
+    /// When an exec last fell in this scope
+    last_used: u64,
+}
+
+impl Scopes {
+    pub fn new(config: &RagConfig, dimension: usize, saved: Vec<(String, VectorStore)>) -> Self {
+        Self {
+            config: config.scope.clone(),
+            dimension,
+            index: config.index.clone(),
+            // Saved scopes were last used no earlier than they last learned something
+            stores: saved
+                .into_iter()
+                .map(|(key, store)| (key, Scope { last_used: store.last_seen(), store }))
+                .collect(),
+            full: false,
+        }
+    }
+
+    /// Identifies the configured scope fields, so saved scopes are only reused under the same ones
+    pub fn fields(config: &ScopeConfig) -> String {
+        config.by.iter().map(|field| field.as_str()).collect::<Vec<_>>().join(",")
+    }
+
+    /// Name of the scope an exec belongs to, e.g. "uid=1000 parent=bash", or `None` when
+    /// scoping is off. `parent` is the comm of the exec's parent, if the process table knows it;
+    /// execs of unknown parents have no scope rather than all sharing one.
+    pub fn key(&self, exec: &ExecEvent, parent: Option<&str>) -> Option<String> {
+        if self.config.by.is_empty() {
+            return None;
+        }
+
+        let parts = self
+            .config
+            .by
+            .iter()
+            .map(|field| match field {
+                ScopeField::Uid => Some(format!("uid={}", exec.uid)),
+                ScopeField::Parent => parent.map(|parent| format!("parent={}", parent)),
+                ScopeField::Cgroup => Some(format!("cgroup={}", exec.context.cgroup_id)),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(parts.join(" "))
+    }
+
+    /// `fields` of the running configuration, recorded with the saved scopes
+    pub fn fields_in_use(&self) -> String {
+        Self::fields(&self.config)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stores.len()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&VectorStore> {
+        self.stores.get(key).map(|scope| &scope.store)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut VectorStore> {
+        self.stores.get_mut(key).map(|scope| &mut scope.store)
+    }
+
+    /// Notes that an exec fell in the scope at `now`, keeping it from being evicted
+    pub fn touch(&mut self, key: &str, now: u64) {
+        if let Some(scope) = self.stores.get_mut(key) {
+            scope.last_used = scope.last_used.max(now);
+        }
+    }
+
+    /// The scope's baseline, created empty if it doesn't exist yet. Once `max_scopes` baselines
+    /// exist, the least recently used one is dropped to make room.
+    pub fn get_or_create(&mut self, key: &str, now: u64) -> &mut VectorStore {
+        if !self.stores.contains_key(key) {
+            if self.stores.len() >= self.config.max_scopes {
+                if !self.full {
+                    self.full = true;
+                    eprintln!(
+                        "⚠️ {} scoped baselines reached, the least recently used ones now make way for new scopes",
+                        self.config.max_scopes
+                    );
+                }
+                self.evict_least_recently_used();
+            }
+            let store = VectorStore::new(self.dimension, &self.index);
+            self.stores.insert(key.to_string(), Scope { store, last_used: now });
+        }
+        let scope = self.stores.get_mut(key).expect("The scope was just inserted");
+        scope.last_used = scope.last_used.max(now);
+        &mut scope.store
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .stores
+            .iter()
+            .min_by(|a, b| a.1.last_used.cmp(&b.1.last_used).then_with(|| a.0.cmp(b.0)))
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.stores.remove(&key);
+        }
+    }
+
+    /// Whether the scope has seen enough to judge execs on its own
+    pub fn is_mature(&self, key: &str) -> bool {
+        self.stores.get(key).is_some_and(|scope| scope.store.len() >= self.config.min_entries)
+    }
+
+    /// The scope that judges an exec in `scope`, or `None` if the global baseline does
+    pub fn judge<'a>(&self, scope: Option<&'a str>) -> Option<&'a str> {
+        scope.filter(|key| self.is_mature(key))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &VectorStore)> {
+        self.stores.iter().map(|(key, scope)| (key, &scope.store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysrag_common::events::ProcessContext;
+    use sysrag_common::ipc::BaselineOrigin;
+
+    const DIMENSION: usize = 4;
+
+    fn scopes(by: Vec<ScopeField>, min_entries: usize, max_scopes: usize) -> Scopes {
+        let config = RagConfig { scope: ScopeConfig { by, min_entries, max_scopes }, ..Default::default() };
+        Scopes::new(&config, DIMENSION, Vec::new())
+    }
+
+    fn exec(uid: u32, cgroup_id: u64) -> ExecEvent {
+        ExecEvent {
+            pid: 42,
+            uid,
+            comm: "nc".to_string(),
+            context: ProcessContext { cgroup_id, ..Default::default() },
+            filename: "/usr/bin/nc".to_string(),
+            argv: Vec::new(),
+            argv_truncated: false,
+            fidelity: Default::default(),
+        }
+    }
+
+    fn learn(scopes: &mut Scopes, key: &str, commands: &[&str], now: u64) {
+        let store = scopes.get_or_create(key, now);
+        for (i, command) in commands.iter().enumerate() {
+            let mut vector = vec![0.0; DIMENSION];
+            vector[i % DIMENSION] = 1.0;
+            store.add_to_baseline(command, vector, BaselineOrigin::Learned, now);
+        }
+    }
+
+    #[test]
+    fn key_combines_the_fields_in_order() {
+        let scopes = scopes(vec![ScopeField::Cgroup, ScopeField::Uid, ScopeField::Parent], 1, 8);
+        assert_eq!(scopes.key(&exec(33, 7), Some("nginx")).as_deref(), Some("cgroup=7 uid=33 parent=nginx"));
+    }
+
+    #[test]
+    fn key_is_none_when_scoping_is_off() {
+        let scopes = scopes(Vec::new(), 1, 8);
+        assert_eq!(scopes.key(&exec(33, 7), Some("nginx")), None);
+    }
+
+    #[test]
+    fn key_is_none_for_an_unknown_parent() {
+        let scopes = scopes(vec![ScopeField::Uid, ScopeField::Parent], 1, 8);
+        assert_eq!(scopes.key(&exec(33, 7), None), None);
+
+        let by_uid = self::scopes(vec![ScopeField::Uid], 1, 8);
+        assert_eq!(by_uid.key(&exec(33, 7), None).as_deref(), Some("uid=33"));
+    }
+
+    #[test]
+    fn scope_matures_after_min_entries() {
+        let mut scopes = scopes(vec![ScopeField::Uid], 2, 8);
+        assert!(!scopes.is_mature("uid=33"));
+
+        learn(&mut scopes, "uid=33", &["nginx"], 100);
+        assert!(!scopes.is_mature("uid=33"));
+
+        learn(&mut scopes, "uid=33", &["php-fpm"], 100);
+        assert!(scopes.is_mature("uid=33"));
+    }
+
+    #[test]
+    fn immature_or_unknown_scopes_fall_back_to_the_global_baseline() {
+        let mut scopes = scopes(vec![ScopeField::Uid], 2, 8);
+        learn(&mut scopes, "uid=33", &["nginx"], 100);
+        learn(&mut scopes, "uid=1000", &["bash", "vim"], 100);
+
+        assert_eq!(scopes.judge(None), None);
+        assert_eq!(scopes.judge(Some("uid=0")), None);
+        assert_eq!(scopes.judge(Some("uid=33")), None);
+        assert_eq!(scopes.judge(Some("uid=1000")), Some("uid=1000"));
+    }
+
+    #[test]
+    fn least_recently_used_scope_is_evicted() {
+        let mut scopes = scopes(vec![ScopeField::Uid], 1, 2);
+        learn(&mut scopes, "uid=1", &["a"], 100);
+        learn(&mut scopes, "uid=2", &["b"], 200);
+        scopes.touch("uid=1", 300);
+
+        learn(&mut scopes, "uid=3", &["c"], 400);
+        assert_eq!(scopes.len(), 2);
+        assert!(scopes.get("uid=1").is_some());
+        assert!(scopes.get("uid=2").is_none());
+        assert!(scopes.get("uid=3").is_some());
+    }
+}
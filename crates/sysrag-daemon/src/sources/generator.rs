@@ -0,0 +1,78 @@
+use std::time::Duration;
+use sysrag_common::events::{ExecEvent, Fidelity, KernelEvent, ProcessContext};
+use tokio::sync::mpsc;
+
+use super::{EventSource, SourceFuture};
+use crate::config::GeneratorConfig;
+
+/// PIDs handed out by the generator start at the highest `pid_max` the kernel allows (2^22),
+/// so they never collide with a real process
+const FIRST_SYNTHETIC_PID: u32 = 1 << 22;
+
+/// How many PIDs the generator cycles through before reusing them, keeping them a valid `pid_t`
+const SYNTHETIC_PIDS: u64 = (i32::MAX as u32 - FIRST_SYNTHETIC_PID + 1) as u64;
+
+/// Synthetic execs cycling through a fixed list of commands, for tests and CI
+pub struct GeneratorSource {
+    config: GeneratorConfig,
+}
+
+impl GeneratorSource {
+    pub fn new(config: GeneratorConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl EventSource for GeneratorSource {
+    fn name(&self) -> &'static str {
+        "generator"
+    }
+
+    fn run(self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture {
+        Box::pin(async move {
+            let config = self.config;
+            let mut ticker = tokio::time::interval(Duration::from_secs(1) / config.events_per_second);
+            let commands = config.commands.iter().cycle();
+
+            for (sent, command) in commands.enumerate() {
+                if config.count.is_some_and(|count| sent as u64 >= count) {
+                    break;
+                }
+                ticker.tick().await;
+
+                let event = KernelEvent::Exec(ExecEvent {
+                    pid: synthetic_pid(sent as u64),
+                    uid: config.uid,
+                    comm: command.chars().take(15).collect(),
+                    context: ProcessContext { ppid: 1, gid: config.uid, ..Default::default() },
+                    filename: format!("/usr/bin/{}", command),
+                    argv: vec![command.clone()],
+                    argv_truncated: false,
+                    fidelity: Fidelity::Exact,
+                });
+                if event_sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The PID of the `sent`-th generated exec
+fn synthetic_pid(sent: u64) -> u32 {
+    FIRST_SYNTHETIC_PID + (sent % SYNTHETIC_PIDS) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_pids_stay_above_pid_max_and_wrap() {
+        assert_eq!(synthetic_pid(0), 1 << 22);
+        assert_eq!(synthetic_pid(SYNTHETIC_PIDS - 1), i32::MAX as u32);
+        assert_eq!(synthetic_pid(SYNTHETIC_PIDS), 1 << 22);
+        assert_eq!(synthetic_pid(u64::MAX), FIRST_SYNTHETIC_PID + (u64::MAX % SYNTHETIC_PIDS) as u32);
+    }
+}
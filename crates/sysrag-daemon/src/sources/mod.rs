@@ -0,0 +1,75 @@
+//! Producers of kernel events. The daemon runs every source listed under `[[sources]]` at once
+//! and merges their events into the single channel the RAG loop reads from.
+
+pub mod audit;
+pub mod generator;
+pub mod proc_poll;
+
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use sysrag_common::events::KernelEvent;
+use tokio::sync::mpsc;
+
+use crate::bpf::{BpfManager, SensorMaps};
+use crate::config::{DaemonConfig, SourceConfig};
+use crate::recording::ReplaySource;
+use crate::{files, filters};
+
+pub type SourceFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// Anything that can feed typed events into the pipeline
+pub trait EventSource: Send {
+    /// Short name for logs, e.g. "bpf" or "replay"
+    fn name(&self) -> &'static str;
+
+    /// Sends events until the source is exhausted or `event_sender` is closed
+    fn run(self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture;
+}
+
+/// The configured sources, ready to run
+pub struct Sources {
+    pub sources: Vec<Box<dyn EventSource>>,
+    /// Filter and counter maps of the BPF sensor, if it is one of the sources
+    pub sensor_maps: Option<SensorMaps>,
+    /// Whether the sources observe this host, as opposed to replaying or inventing events.
+    /// Config validation keeps the two kinds from being mixed.
+    pub observes_host: bool,
+}
+
+/// Builds every source in `config.sources`. The BPF sensor is loaded and attached here.
+pub fn build(config: &DaemonConfig) -> Result<Sources> {
+    let mut built = Sources {
+        sources: Vec::new(),
+        sensor_maps: None,
+        observes_host: config.sources.iter().all(SourceConfig::observes_host),
+    };
+
+    for source in config.sources.iter() {
+        match source {
+            SourceConfig::Bpf => {
+                let watched_paths = files::parse_patterns(&config.files.watched_paths)?;
+                let filter_rules = filters::rules_from_config(&config.filters);
+                let (bpf_manager, sensor_maps) = BpfManager::new(watched_paths, filter_rules)?;
+
+                built.sources.push(Box::new(bpf_manager));
+                built.sensor_maps = Some(sensor_maps);
+            }
+            SourceConfig::Replay { path, speed } => {
+                built.sources.push(Box::new(ReplaySource::open(path, *speed)?));
+            }
+            SourceConfig::Proc(proc_poll) => {
+                built.sources.push(Box::new(proc_poll::ProcPollSource::new(proc_poll)));
+            }
+            SourceConfig::Audit(audit) => {
+                let watched_paths = files::parse_patterns(&config.files.watched_paths)?;
+                built.sources.push(Box::new(audit::AuditSource::new(audit, watched_paths)));
+            }
+            SourceConfig::Generator(generator) => {
+                built.sources.push(Box::new(generator::GeneratorSource::new(generator.clone())));
+            }
+        }
+    }
+
+    Ok(built)
+}
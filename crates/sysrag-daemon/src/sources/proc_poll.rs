@@ -0,0 +1,189 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::time::Duration;
+use sysrag_common::events::{ExecEvent, ExitEvent, Fidelity, KernelEvent, ProcessContext};
+use tokio::sync::mpsc;
+
+use super::{EventSource, SourceFuture};
+use crate::config::ProcPollConfig;
+use crate::procfs::{self, ProcStat};
+
+/// Same bound on argv as the kernel sensor, so both produce comparable embeddings
+const MAX_ARGS: usize = 20;
+
+/// What we remember about a process between two scans
+struct Seen {
+    start_ticks: u64,
+    exe: String,
+    comm: String,
+}
+
+/// Unprivileged fallback for hosts that cannot load BPF. Every `interval` it lists `/proc`
+/// and turns new PIDs into execs and vanished ones into exits. All events are marked
+/// `Fidelity::Sampled`: anything that starts and exits between two scans is never seen.
+pub struct ProcPollSource {
+    interval: Duration,
+    seen: HashMap<u32, Seen>,
+}
+
+impl ProcPollSource {
+    pub fn new(config: &ProcPollConfig) -> Self {
+        Self {
+            interval: Duration::from_millis(config.interval_ms),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// One pass over `/proc`, returning what changed since the previous pass.
+    /// Exits come first so a reused PID is closed before its new process shows up.
+    fn scan(&mut self) -> Vec<KernelEvent> {
+        let mut current = HashMap::new();
+        let mut exits = Vec::new();
+        let mut started = Vec::new();
+
+        for pid in procfs::list_pids() {
+            let Some(stat) = procfs::read_stat(pid) else {
+                continue;
+            };
+            // Kernel threads and zombies have no command line
+            let argv = procfs::read_cmdline(pid);
+            if argv.is_empty() {
+                continue;
+            }
+            // Other users' exe links need CAP_SYS_PTRACE, argv[0] is the best we can do without it
+            let exe = procfs::read_exe(pid).unwrap_or_else(|| argv[0].clone());
+
+            let is_new = match self.seen.get(&pid) {
+                Some(prev) if prev.start_ticks != stat.start_ticks => {
+                    exits.push(exit_event(pid, &prev.comm));
+                    true
+                }
+                // Same process, new program: it called execve since the last scan
+                Some(prev) => prev.exe != exe,
+                None => true,
+            };
+
+            let seen = Seen { start_ticks: stat.start_ticks, exe: exe.clone(), comm: stat.comm.clone() };
+            if is_new {
+                started.push((stat.start_ticks, exec_event(pid, stat, exe, argv)));
+            }
+            current.insert(pid, seen);
+        }
+
+        for (pid, prev) in &self.seen {
+            if !current.contains_key(pid) {
+                exits.push(exit_event(*pid, &prev.comm));
+            }
+        }
+        self.seen = current;
+
+        // Oldest first, so parents are reported before their children
+        started.sort_by_key(|(start_ticks, _)| *start_ticks);
+        exits.into_iter().chain(started.into_iter().map(|(_, exec)| exec)).collect()
+    }
+
+    /// `scan` on the blocking pool, since it reads several files of every process
+    async fn scan_blocking(mut self: Box<Self>) -> anyhow::Result<(Box<Self>, Vec<KernelEvent>)> {
+        tokio::task::spawn_blocking(move || {
+            let events = self.scan();
+            (self, events)
+        })
+        .await
+        .context("The /proc scan panicked")
+    }
+}
+
+impl EventSource for ProcPollSource {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn run(self: Box<Self>, event_sender: mpsc::Sender<KernelEvent>) -> SourceFuture {
+        Box::pin(async move {
+            // The first pass only learns what is already running; the kernel sensor doesn't report old execs either
+            let (mut source, _) = self.scan_blocking().await?;
+            println!("🔎 Polling /proc every {:?} ({} processes running)", source.interval, source.seen.len());
+
+            let mut ticker = tokio::time::interval(source.interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let events;
+                (source, events) = source.scan_blocking().await?;
+                for event in events {
+                    if event_sender.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn exec_event(pid: u32, stat: ProcStat, exe: String, mut argv: Vec<String>) -> KernelEvent {
+    let argv_truncated = argv.len() > MAX_ARGS;
+    argv.truncate(MAX_ARGS);
+    let (uid, gid) = procfs::read_uid_gid(pid).unwrap_or_default();
+
+    KernelEvent::Exec(ExecEvent {
+        pid,
+        uid,
+        comm: stat.comm,
+        context: ProcessContext {
+            ppid: stat.ppid,
+            gid,
+            loginuid: procfs::read_loginuid(pid),
+            cgroup_id: procfs::read_cgroup_id(pid).unwrap_or(0),
+            mnt_ns: procfs::read_namespace(pid, "mnt").unwrap_or(0),
+            pid_ns: procfs::read_namespace(pid, "pid").unwrap_or(0),
+            tty: procfs::tty_name(stat.tty_nr),
+        },
+        filename: exe,
+        argv,
+        argv_truncated,
+        fidelity: Fidelity::Sampled,
+    })
+}
+
+fn exit_event(pid: u32, comm: &str) -> KernelEvent {
+    KernelEvent::Exit(ExitEvent {
+        pid,
+        comm: comm.to_string(),
+        // Unknown; `ExitEvent::status` ignores it for sampled exits
+        exit_code: 0,
+        fidelity: Fidelity::Sampled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn for_pid(events: &[KernelEvent], pid: u32) -> Vec<&KernelEvent> {
+        events.iter().filter(|event| event.pid() == pid).collect()
+    }
+
+    #[test]
+    fn scans_report_new_processes_then_their_exit() {
+        let mut source = ProcPollSource::new(&ProcPollConfig { interval_ms: 10 });
+        source.scan();
+
+        let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+        let started = source.scan();
+        // Nothing changed, so the next pass reports nothing for it
+        let unchanged = source.scan();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let exited = source.scan();
+
+        let pid = child.id();
+        let [KernelEvent::Exec(exec)] = for_pid(&started, pid)[..] else {
+            panic!("Expected an exec of {}, got {:?}", pid, for_pid(&started, pid));
+        };
+        assert_eq!(exec.argv, ["sleep", "10"]);
+        assert_eq!(exec.context.ppid, std::process::id());
+        assert_eq!(exec.fidelity, Fidelity::Sampled);
+        assert!(for_pid(&unchanged, pid).is_empty());
+        assert!(matches!(for_pid(&exited, pid)[..], [KernelEvent::Exit(exit)] if exit.fidelity == Fidelity::Sampled));
+    }
+}
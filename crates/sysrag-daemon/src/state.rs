@@ -0,0 +1,392 @@
+use anyhow::Context;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use sysrag_common::events::{ConnectEvent, ExecEvent, ExitEvent, FileOpenEvent, ForkEvent};
+use sysrag_common::ipc::{
+    AnomalyQuery, AnomalyReport, FileAccess, OutboundConnection, ProcessInfo, ProcessTreeNode,
+    ProgramStats, TriageState,
+};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+
+use crate::activity::ActivityTracker;
+use crate::config::DaemonConfig;
+use crate::history::AnomalyHistory;
+use crate::proctree::ProcessTable;
+use crate::rag::current_timestamp;
+
+/// How many anomalies a slow `sysrag watch` client may fall behind before it starts missing some
+const ANOMALY_FEED_CAPACITY: usize = 256;
+
+/// Anomalies waiting for the history writer. Past this, new ones still reach live subscribers
+/// but are left out of the history.
+const HISTORY_QUEUE_CAPACITY: usize = 1024;
+
+/// The history writer applies the retention limits once per this many inserts
+const PRUNE_EVERY_INSERTS: u64 = 100;
+
+enum HistoryWrite {
+    Insert(Box<AnomalyReport>),
+    /// Answered once every write queued before it is done
+    Flush(oneshot::Sender<()>),
+}
+
+/// The live state of the daemon.
+/// The RAG loop in `main.rs` is the only writer; the IPC server only reads from it.
+pub struct DaemonState {
+    started_at: Instant,
+    /// Unix timestamp of `started_at`
+    started_at_unix: u64,
+    events_processed: AtomicU64,
+    /// `events_processed` at the last rate sample
+    events_at_last_sample: AtomicU64,
+    events_per_second: AtomicU64,
+    peak_events_per_second: AtomicU64,
+    /// Last read of the probes' ring buffer counters
+    sensor_stats: Mutex<Vec<ProgramStats>>,
+    anomalies_detected: AtomicU64,
+    baseline_size: AtomicUsize,
+    /// Read on the blocking pool; written only by the history writer thread
+    history: Arc<AnomalyHistory>,
+    history_queue: mpsc::Sender<HistoryWrite>,
+    anomaly_feed: broadcast::Sender<AnomalyReport>,
+    connections: Mutex<ActivityTracker<OutboundConnection>>,
+    file_accesses: Mutex<ActivityTracker<FileAccess>>,
+    processes: Mutex<ProcessTable>,
+}
+
+impl DaemonState {
+    /// `persist_history` is false when the events don't come from this host, so they stay out of its history
+    pub fn new(config: &DaemonConfig, persist_history: bool) -> anyhow::Result<Self> {
+        let history = Arc::new(if persist_history {
+            AnomalyHistory::open_or_in_memory(&config.history)
+        } else {
+            AnomalyHistory::in_memory(&config.history)
+        });
+
+        // Inserts go through a thread of their own, so a slow disk never holds up detection
+        let (history_queue, writes) = mpsc::channel(HISTORY_QUEUE_CAPACITY);
+        let writer = Arc::clone(&history);
+        std::thread::Builder::new()
+            .name("sysrag-history".to_string())
+            .spawn(move || write_history(&writer, writes))
+            .context("Failed to start the anomaly history writer")?;
+
+        Ok(Self {
+            started_at: Instant::now(),
+            started_at_unix: current_timestamp(),
+            events_processed: AtomicU64::new(0),
+            events_at_last_sample: AtomicU64::new(0),
+            events_per_second: AtomicU64::new(0),
+            peak_events_per_second: AtomicU64::new(0),
+            sensor_stats: Mutex::new(Vec::new()),
+            anomalies_detected: AtomicU64::new(0),
+            baseline_size: AtomicUsize::new(0),
+            history,
+            history_queue,
+            anomaly_feed: broadcast::channel(ANOMALY_FEED_CAPACITY).0,
+            connections: Mutex::new(ActivityTracker::new(
+                config.network.connections_per_process,
+                config.network.max_tracked_processes,
+            )),
+            file_accesses: Mutex::new(ActivityTracker::new(
+                config.files.accesses_per_process,
+                config.files.max_tracked_processes,
+            )),
+            processes: Mutex::new(ProcessTable::new(config.processes.max_exited)),
+        })
+    }
+
+    pub fn uptime_seconds(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    /// Counts one kernel event that went through the RAG engine
+    pub fn record_event(&self) {
+        self.events_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed.load(Ordering::Relaxed)
+    }
+
+    /// Turns the events counted since the previous call into a rate. Must be called once per second.
+    pub fn sample_event_rate(&self) {
+        let total = self.events_processed();
+        let previous = self.events_at_last_sample.swap(total, Ordering::Relaxed);
+        let rate = total.saturating_sub(previous);
+
+        self.events_per_second.store(rate, Ordering::Relaxed);
+        self.peak_events_per_second.fetch_max(rate, Ordering::Relaxed);
+    }
+
+    /// Events processed during the last full second
+    pub fn events_per_second(&self) -> u64 {
+        self.events_per_second.load(Ordering::Relaxed)
+    }
+
+    /// Highest `events_per_second` seen since startup
+    pub fn peak_events_per_second(&self) -> u64 {
+        self.peak_events_per_second.load(Ordering::Relaxed)
+    }
+
+    /// Stores a fresh read of the sensor counters and returns how many more events were lost since the previous one
+    pub async fn update_sensor_stats(&self, stats: Vec<ProgramStats>) -> u64 {
+        let mut current = self.sensor_stats.lock().await;
+        let total_lost = |stats: &[ProgramStats]| stats.iter().map(|p| p.lost).sum::<u64>();
+
+        let newly_lost = total_lost(&stats).saturating_sub(total_lost(&current));
+        *current = stats;
+        newly_lost
+    }
+
+    pub async fn sensor_stats(&self) -> Vec<ProgramStats> {
+        self.sensor_stats.lock().await.clone()
+    }
+
+    pub fn anomalies_detected(&self) -> u64 {
+        self.anomalies_detected.load(Ordering::Relaxed)
+    }
+
+    /// Mirrors the number of vectors currently held by the `VectorStore`
+    pub fn set_baseline_size(&self, size: usize) {
+        self.baseline_size.store(size, Ordering::Relaxed);
+    }
+
+    pub fn baseline_size(&self) -> usize {
+        self.baseline_size.load(Ordering::Relaxed)
+    }
+
+    /// Queues a freshly detected anomaly for the history and pushes it to every live subscriber
+    pub fn push_anomaly(&self, anomaly: AnomalyReport) {
+        if self.history_queue.try_send(HistoryWrite::Insert(Box::new(anomaly.clone()))).is_err() {
+            eprintln!("⚠️ The anomaly history is falling behind, anomaly {} is left out of it", anomaly.id);
+        }
+        self.anomalies_detected.fetch_add(1, Ordering::Relaxed);
+
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.anomaly_feed.send(anomaly);
+    }
+
+    /// Waits until every anomaly pushed so far is in the history
+    pub async fn flush_history(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.history_queue.send(HistoryWrite::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Receives every anomaly pushed after this call
+    pub fn subscribe_anomalies(&self) -> broadcast::Receiver<AnomalyReport> {
+        self.anomaly_feed.subscribe()
+    }
+
+    /// Remembers an outbound connection so it can be attached to anomaly reports
+    pub async fn record_connection(&self, conn: &ConnectEvent) {
+        let timestamp = current_timestamp();
+        let connection = OutboundConnection {
+            timestamp,
+            address: conn.address,
+            port: conn.port,
+        };
+        self.connections.lock().await.record(conn.pid, timestamp, connection);
+    }
+
+    /// Remembers an open of a watched path so it can be attached to anomaly reports
+    pub async fn record_file_access(&self, open: &FileOpenEvent) {
+        let timestamp = current_timestamp();
+        let access = FileAccess {
+            timestamp,
+            path: open.path.clone(),
+            write: open.is_write(),
+        };
+        self.file_accesses.lock().await.record(open.pid, timestamp, access);
+    }
+
+    /// Fills the process table with everything already running on this host
+    pub async fn seed_process_table(&self) {
+        self.processes.lock().await.seed_from_proc();
+    }
+
+    pub async fn record_fork(&self, fork: &ForkEvent) {
+        self.processes.lock().await.record_fork(fork, current_timestamp());
+    }
+
+    pub async fn record_exec(&self, exec: &ExecEvent) {
+        self.processes.lock().await.record_exec(exec, current_timestamp());
+    }
+
+    pub async fn record_exit(&self, exit: &ExitEvent) {
+        self.processes.lock().await.record_exit(exit, current_timestamp());
+    }
+
+    /// The command name of `pid`, if the process table knows it
+    pub async fn process_comm(&self, pid: u32) -> Option<String> {
+        self.processes.lock().await.comm(pid)
+    }
+
+    /// The chain of processes that led to `pid`, oldest first, ending with `pid` itself
+    pub async fn ancestry(&self, pid: u32) -> Vec<ProcessInfo> {
+        self.processes.lock().await.ancestry(pid)
+    }
+
+    /// Ancestry and descendants of `pid`, or `None` if the process is unknown
+    pub async fn process_tree(&self, pid: u32) -> Option<(Vec<ProcessInfo>, ProcessTreeNode)> {
+        let processes = self.processes.lock().await;
+        let tree = processes.subtree(pid)?;
+        Some((processes.ancestry(pid), tree))
+    }
+
+    /// Returns the last `tail` anomalies matching `query`, oldest first
+    pub async fn recent_anomalies(&self, tail: usize, query: &AnomalyQuery) -> anyhow::Result<Vec<AnomalyReport>> {
+        let query = query.clone();
+        let mut recent = self.with_history(move |history| history.query(tail, &query)).await?;
+
+        for anomaly in &mut recent {
+            self.attach_activity(anomaly).await;
+        }
+        Ok(recent)
+    }
+
+    pub async fn find_anomaly(&self, id: &str) -> anyhow::Result<Option<AnomalyReport>> {
+        let id = id.to_string();
+        let Some(mut anomaly) = self.with_history(move |history| history.find(&id)).await? else {
+            return Ok(None);
+        };
+
+        self.attach_activity(&mut anomaly).await;
+        Ok(Some(anomaly))
+    }
+
+    /// Keeps the LLM's analysis next to the anomaly in the history
+    pub async fn save_investigation(&self, id: &str, investigation: &str) -> anyhow::Result<bool> {
+        let (id, investigation) = (id.to_string(), investigation.to_string());
+        self.with_history(move |history| history.set_investigation(&id, &investigation)).await
+    }
+
+    /// Returns false if there is no anomaly with this id
+    pub async fn set_triage(&self, id: &str, triage: TriageState) -> anyhow::Result<bool> {
+        let id = id.to_string();
+        self.with_history(move |history| history.set_triage(&id, triage)).await
+    }
+
+    /// Runs a history call on the blocking pool, off the event loop. Anomalies already pushed are
+    /// written first, so one that was just detected can be looked up right away.
+    async fn with_history<T, F>(&self, call: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&AnomalyHistory) -> anyhow::Result<T> + Send + 'static,
+    {
+        self.flush_history().await;
+        let history = Arc::clone(&self.history);
+        tokio::task::spawn_blocking(move || call(&history))
+            .await
+            .context("The anomaly history call panicked")?
+    }
+
+    /// Fills in what the flagged process did since it was detected
+    async fn attach_activity(&self, anomaly: &mut AnomalyReport) {
+        // The trackers only cover this run; PIDs of older anomalies may belong to other processes by now
+        if anomaly.timestamp < self.started_at_unix {
+            return;
+        }
+        anomaly.connections = self.connections.lock().await.since(anomaly.pid, anomaly.timestamp);
+        anomaly.file_accesses = self.file_accesses.lock().await.since(anomaly.pid, anomaly.timestamp);
+    }
+}
+
+/// The history writer thread: stores queued anomalies until the daemon state goes away
+fn write_history(history: &AnomalyHistory, mut writes: mpsc::Receiver<HistoryWrite>) {
+    let mut inserted = 0u64;
+    while let Some(write) = writes.blocking_recv() {
+        match write {
+            HistoryWrite::Insert(anomaly) => {
+                if let Err(e) = history.insert(&anomaly) {
+                    eprintln!("⚠️ Failed to store anomaly {} in the history: {:#}", anomaly.id, e);
+                    continue;
+                }
+                inserted += 1;
+                if inserted.is_multiple_of(PRUNE_EVERY_INSERTS) {
+                    if let Err(e) = history.prune(current_timestamp()) {
+                        eprintln!("⚠️ Failed to trim the anomaly history: {:#}", e);
+                    }
+                }
+            }
+            HistoryWrite::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> DaemonState {
+        DaemonState::new(&DaemonConfig::default(), false).unwrap()
+    }
+
+    fn anomaly(id: &str) -> AnomalyReport {
+        AnomalyReport {
+            id: id.to_string(),
+            timestamp: current_timestamp(),
+            pid: 42,
+            uid: 0,
+            command: "nc".to_string(),
+            exe: "/usr/bin/nc".to_string(),
+            argv: Vec::new(),
+            context: Default::default(),
+            connections: Vec::new(),
+            file_accesses: Vec::new(),
+            ancestry: Vec::new(),
+            fidelity: Default::default(),
+            similarity_score: 0.1,
+            score: None,
+            nearest: None,
+            scope: None,
+            raw_log: String::new(),
+            triage: TriageState::New,
+            investigation: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn pushed_anomalies_are_counted_and_served() {
+        let state = state();
+        state.record_event();
+        state.record_event();
+        state.push_anomaly(anomaly("a"));
+        state.push_anomaly(anomaly("b"));
+
+        assert_eq!(state.events_processed(), 2);
+        assert_eq!(state.anomalies_detected(), 2);
+        let recent = state.recent_anomalies(1, &AnomalyQuery::default()).await.unwrap();
+        assert_eq!(recent.iter().map(|a| a.id.as_str()).collect::<Vec<_>>(), ["b"]);
+        assert!(state.find_anomaly("a").await.unwrap().is_some());
+        assert!(state.find_anomaly("missing").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn the_rate_counts_events_since_the_last_sample() {
+        let state = state();
+        (0..5).for_each(|_| state.record_event());
+        state.sample_event_rate();
+        state.record_event();
+        state.sample_event_rate();
+
+        assert_eq!(state.events_per_second(), 1);
+        assert_eq!(state.peak_events_per_second(), 5);
+    }
+
+    #[tokio::test]
+    async fn only_newly_lost_events_are_reported() {
+        let state = state();
+        let stats = |lost| vec![ProgramStats { program: "execve".to_string(), sent: 100, lost }];
+
+        assert_eq!(state.update_sensor_stats(stats(3)).await, 3);
+        assert_eq!(state.update_sensor_stats(stats(3)).await, 0);
+        assert_eq!(state.update_sensor_stats(stats(5)).await, 2);
+        assert_eq!(state.sensor_stats().await[0].lost, 5);
+    }
+}